@@ -3,6 +3,7 @@ use core::slice::Iter;
 use std::collections::HashMap;
 
 /// 元素以线性存储，但是又可以通过Key来快速访问
+#[derive(Clone, Debug)]
 pub struct HashVec<K, V> {
     vec: Vec<V>,
     map: HashMap<K, usize>,
@@ -97,4 +98,10 @@ impl<K, V> HashVec<K, V> where K: Eq + Hash {
             None => None,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<K, V> PartialEq for HashVec<K, V> where K: Eq + Hash, V: PartialEq {
+    fn eq(&self, other: &Self) -> bool {
+        self.vec == other.vec && self.map == other.map
+    }
+}
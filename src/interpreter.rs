@@ -1,8 +1,10 @@
 use std::rc::Rc;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::io;
 use std::fs::File;
 use std::io::prelude::*;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use num_traits::FromPrimitive;
 use colored::*;
 
@@ -15,7 +17,7 @@ use data_reader::*;
 mod metadata;
 use metadata::*;
 mod assembly_name;
-use assembly_name::*;
+pub use assembly_name::*;
 
 mod type_ref;
 use type_ref::*;
@@ -25,6 +27,8 @@ mod field;
 use field::*;
 mod method;
 use method::*;
+mod exception_clause;
+use exception_clause::*;
 mod param;
 use param::*;
 mod member_ref;
@@ -39,6 +43,12 @@ mod exported_type;
 use exported_type::*;
 mod method_spec;
 use method_spec::*;
+mod method_impl;
+use method_impl::*;
+mod generic_param;
+use generic_param::*;
+mod assembly_resolver;
+pub use assembly_resolver::*;
 
 mod type_sig;
 use type_sig::*;
@@ -46,6 +56,18 @@ mod calling_convention_sig;
 use calling_convention_sig::*;
 mod object;
 use object::*;
+mod array;
+use array::*;
+mod vtable;
+use vtable::*;
+mod delegate;
+use delegate::*;
+mod runtime_type;
+use runtime_type::*;
+mod runtime_error;
+pub use runtime_error::*;
+#[cfg(test)]
+mod test_assembly;
 
 use crate::hash_vec::HashVec;
 
@@ -65,6 +87,7 @@ pub struct Assembly {
     pub params: Vec<Param>,                     //  (0x08000001...), Param
     pub member_refs: Vec<MemberRef>,            //  (0x0A000001...), MemberRef
     pub standalone_sigs: Vec<StandaloneSig>,    //  (0x11000001...), StandaloneSig
+    pub method_impls: Vec<MethodImpl>,          //  (0x19000001...), MethodImpl
     pub type_specs: Vec<TypeSpec>,              //  (0x1B000001...), TypeSpec 
     pub assembly_refs: Vec<AssemblyRef>,        //  (0x23000001...), AssemblyRef
     pub exported_types: HashVec<String, ExportedType>, // (0x27000001...), ExportedType
@@ -72,19 +95,30 @@ pub struct Assembly {
 }
 
 impl Assembly {
-    const NET5_PATH: &'static str = r"C:\Program Files\dotnet\shared\Microsoft.NETCore.App\5.0.11\";
+    /// CLI头中记录的入口方法，为0表示没有入口（例如类库）
+    pub fn entry_point_token(&self) -> u32 {
+        self.metadata.cor20_header.entry_point_token
+    }
 
     pub fn new(assembly_path: &String, is_cor_lib: bool) -> io::Result<Assembly> {
         let mut file = File::open(assembly_path)?;
         let metadata = file.metadata().expect("unable to read metadata");
         let mut image = vec![0; metadata.len() as usize];
         file.read(&mut image).expect("Error reading assembly, overflow.");
+        let mut assembly = Assembly::from_bytes(image, is_cor_lib)?;
+        assembly.assembly_path = assembly_path.to_string();
+        println!("Assembly loaded: {:?}", assembly_path);
+        Ok(assembly)
+    }
+
+    /// 从内存中的PE镜像创建Assembly，assembly_path为空
+    pub fn from_bytes(image: Vec<u8>, is_cor_lib: bool) -> io::Result<Assembly> {
         let mut reader = DataReader::new(image);
         let pe = PE::new(&mut reader)?;
         let metadata = Metadata::new(&pe, &mut reader)?;
 
         let type_refs = TypeRef::read_type_refs(&metadata)?;
-        let type_defs = TypeDef::read_type_defs(&metadata)?;
+        let mut type_defs = TypeDef::read_type_defs(&metadata)?;
         // 读取完之后ref和def之后，更新def中的field_list
 
         let field_to_type_map = type_defs.vec().map(|t| t.field_list.start_rid).collect::<Vec<u32>>();
@@ -94,15 +128,28 @@ impl Assembly {
         // 那么这个数组就存放的是[1, 1, 4, 4]
         // read_methods的时候，假设一个method的Rid是3，那么就能知道4是第一个比3大的，是第3个方法的Method，即0x06000003
         let method_to_type_map = type_defs.vec().map(|t| t.method_list.start_rid).collect::<Vec<u32>>();
-        let methods = Method::read_methods(&pe, &metadata, method_to_type_map, &mut reader)?;
+        let mut methods = Method::read_methods(&pe, &metadata, method_to_type_map, &mut reader)?;
         let params = Param::read_params(&metadata)?;
 
         let member_refs = MemberRef::read_member_refs(&metadata)?;
         let standalone_sigs = StandaloneSig::read_standalone_sigs(&metadata)?;
+        let method_impls = MethodImpl::read_method_impls(&metadata)?;
         let type_specs = TypeSpec::read_type_specs(&metadata)?;
         let assembly_refs = AssemblyRef::read_assembly_refs(&metadata)?;
         let exported_types = ExportedType::read_exported_types(&metadata)?;
         let method_specs = MethodSpec::read_method_specs(&metadata)?;
+        for generic_param in GenericParam::read_generic_params(&metadata)? {  // 类型参数放到所属的TypeDef或Method中
+            let owner_index = ((generic_param.owner & 0x00FFFFFF) as usize).wrapping_sub(1);
+            match generic_param.owner >> 24 {
+                0x02 => if let Some(type_def) = type_defs.index_get_mut(owner_index) {
+                    type_def.generic_params.push(generic_param);
+                },
+                0x06 => if let Some(method) = methods.get_mut(owner_index) {
+                    method.generic_params.push(generic_param);
+                },
+                _ => {},
+            }
+        }
 
         let assembly_table = &metadata.table_stream.md_tables[0x20];
         let major_version = assembly_table.columns[1].get_cell_u16(0);
@@ -110,21 +157,21 @@ impl Assembly {
         let build_number = assembly_table.columns[3].get_cell_u16(0);
         let revision_number = assembly_table.columns[4].get_cell_u16(0);
         let flags = assembly_table.columns[5].get_cell_u32(0);
-        let public_key_token = metadata.blob_stream.read(assembly_table.columns[6].get_cell_u16_or_u32(0))?;
+        let public_key_or_token = metadata.blob_stream.read(assembly_table.columns[6].get_cell_u16_or_u32(0))?;
         let name = metadata.strings_stream.get_string_clone(assembly_table.columns[7].get_cell_u16_or_u32(0))?;
-        
-        println!("Assembly loaded: {:?}", assembly_path);
+        let culture = metadata.strings_stream.get_string_clone(assembly_table.columns[8].get_cell_u16_or_u32(0))?;
 
         Ok(Assembly {
-            assembly_path: assembly_path.to_string(),
+            assembly_path: String::new(),
             assembly_name: AssemblyName {
                 major_version,
                 minor_version,
                 build_number,
                 revision_number,
                 flags,
-                public_key_token,
+                public_key_or_token,
                 name,
+                culture,
             },
             is_cor_lib,
 
@@ -139,6 +186,7 @@ impl Assembly {
             params,
             member_refs,
             standalone_sigs,
+            method_impls,
             type_specs,
             assembly_refs,
             exported_types,
@@ -146,42 +194,37 @@ impl Assembly {
         })
     }
 
-    pub fn load(assembly_name: &AssemblyName) -> io::Result<Assembly> {
-        let assembly_path = format!("{}{}.dll", Self::NET5_PATH, assembly_name.name);
-        let assembly = Assembly::new(&assembly_path, false)?;
-        if assembly.assembly_name != *assembly_name {
-            return Err(io::Error::new(io::ErrorKind::Other, "Assembly name not match."));
+    /// 通过resolver找到Assembly并加载，加载后按policy检查是否满足引用
+    /// 候选的名称或版本不满足时继续尝试下一个，全部不满足时返回NotFound，以便继续询问其他resolver
+    pub fn load(assembly_name: &AssemblyName, resolver: &dyn AssemblyResolver, policy: BindingPolicy) -> io::Result<Assembly> {
+        let mut mismatches = Vec::new();
+        for resolved in resolver.resolve_all(assembly_name)? {
+            let assembly = match resolved {
+                ResolvedAssembly::Path(path) => Assembly::new(&path.to_string_lossy().into_owned(), false)?,
+                ResolvedAssembly::Bytes(image) => Assembly::from_bytes(image, false)?,
+                ResolvedAssembly::Assembly(assembly) => *assembly,
+            };
+            if assembly_name.matches(&assembly.assembly_name, policy) {
+                return Ok(assembly);
+            }
+            mismatches.push(format!("Assembly name not match, requested: {}, found: {}", assembly_name, assembly.assembly_name));
         }
-        Ok(assembly)
+        Err(io::Error::new(io::ErrorKind::NotFound, mismatches.join("\n")))
     }
 
-    // pub fn load_cor_lib() -> io::Result<Assembly> {
-    //     let assembly_path = format!("{}{}.dll", Self::NET5_PATH, Self::COR_LIB_NAME);
-    //     Assembly::new(&assembly_path, true)
-    // }
+    /// TypeDef或TypeRef的全名，例如System.Int32
+    pub fn get_type_full_name(&self, type_def_or_ref_token: u32) -> Option<String> {
+        let rid = (type_def_or_ref_token & 0x00FFFFFF) as usize;
+        match type_def_or_ref_token >> 24 {
+            0x02 => self.type_defs.index_get(rid.wrapping_sub(1)).map(|t| t.namespace.clone() + "." + &t.name),
+            0x01 => self.type_refs.index_get(rid.wrapping_sub(1)).map(|t| t.full_name.clone()),
+            _ => None,
+        }
+    }
 
     /// 将CorLibType解析成u32，即指向TypeDef（如果当前就是mscorlib）或者TypeRef的token
     pub fn resolve_cor_lib_type(&self, cor_lib_type: &CorLibType) -> io::Result<u32> {
-        let type_full_name = &match cor_lib_type {
-            CorLibType::Void => "System.Void",
-            CorLibType::Boolean => "System.Boolean",
-            CorLibType::Char => "System.Char",
-            CorLibType::SByte => "System.SByte",
-            CorLibType::Byte => "System.Byte",
-            CorLibType::Int16 => "System.Int16",
-            CorLibType::UInt16 => "System.UInt16",
-            CorLibType::Int32 => "System.Int32",
-            CorLibType::UInt32 => "System.UInt32",
-            CorLibType::Int64 => "System.Int64",
-            CorLibType::UInt64 => "System.UInt64",
-            CorLibType::Single => "System.Single",
-            CorLibType::Double => "System.Double",
-            CorLibType::String => "System.String",
-            CorLibType::TypedReference => "System.TypedReference",
-            CorLibType::IntPtr => "System.IntPtr",
-            CorLibType::UIntPtr => "System.UIntPtr",
-            CorLibType::Object => "System.Object",
-        }.to_string();
+        let type_full_name = &cor_lib_type.full_name().to_string();
         if self.is_cor_lib {
             self.type_defs.key_get(type_full_name).map(|t| t.token).ok_or(io::Error::new(io::ErrorKind::Other, "CorLibType not found."))
         } else {
@@ -190,14 +233,67 @@ impl Assembly {
     }
 }
 
+/// 调用堆栈中的一帧
+pub struct CallFrame {
+    pub assembly_index: usize,
+    pub method_index: usize,
+    /// 当前执行到的指令相对于方法体起始的偏移
+    pub offset: usize,
+    /// 调用时的栈ID，托管指针通过它找到对应的帧
+    pub stack_id: usize,
+    pub params: Vec<ILType>,
+    pub locals: Vec<ILType>,
+    /// 替换方法体中!n和!!n的泛型上下文
+    pub generic_context: Rc<GenericContext>,
+    /// 进入方法时求值栈的长度，leave和进入catch时求值栈恢复到这个长度
+    pub stack_base: usize,
+    /// 正在执行的catch块所捕获的异常，用于rethrow <clause index, object index>
+    pub caught_exceptions: HashMap<usize, usize>,
+    /// constrained.前缀指定的类型，只对紧接着的callvirt有效
+    constrained: Option<RuntimeType>,
+    /// readonly.前缀，紧接着的ldelema不检查元素类型
+    readonly: bool,
+    /// 异常从内层代码块（finally或filter）传出时，对于外层来说异常来自整个代码块的范围
+    unwind_range: Option<(usize, usize)>,
+}
+
+impl CallFrame {
+    pub fn new(assembly_index: usize, method_index: usize, stack_id: usize, generic_context: Rc<GenericContext>) -> CallFrame {
+        CallFrame {
+            assembly_index,
+            method_index,
+            offset: 0,
+            stack_id,
+            params: Vec::new(),
+            locals: Vec::new(),
+            generic_context,
+            stack_base: 0,
+            caught_exceptions: HashMap::new(),
+            constrained: None,
+            readonly: false,
+            unwind_range: None,
+        }
+    }
+}
+
+/// il_run执行的代码块如何结束
+enum BlockExit {
+    Ret,
+    EndFinally,
+    /// filter的结果，true表示由这个子句处理
+    EndFilter(bool),
+}
+
 /// 解释器执行的上下文
 pub struct Context {
     assembly: Rc<Assembly>,
     assembly_index: usize,
-    /// 调用堆栈，记录当前assembly的index和method_token
-    call_stack: Vec<(usize, u32)>,
+    /// 调用堆栈，栈顶为当前正在执行的方法
+    call_stack: Vec<CallFrame>,
     /// 栈ID，每次调用加一，因为Local和Param每次调用就要清除，所以如果根据托管指针来改变，需要检查这个值
     stack_id: usize,
+    /// 正在执行filter时，filter内抛出的异常不能被这个index以下的帧捕获
+    filter_floor: usize,
 }
 
 impl Context {
@@ -207,15 +303,7 @@ impl Context {
             assembly_index,
             call_stack: Vec::new(),
             stack_id: 0,
-        }
-    }
-
-    pub fn make_temp(&self) -> Context {
-        Context {
-            assembly: Rc::clone(&self.assembly),
-            assembly_index: self.assembly_index,
-            call_stack: Vec::new(),
-            stack_id: self.stack_id,
+            filter_floor: 0,
         }
     }
 }
@@ -227,32 +315,132 @@ pub struct Interpreter {
     stack: VecDeque<ILType>,
     objects: Vec<Object>,
     strings: Vec<String>,
+    arrays: Vec<Array>,
     
-    /// 存放Assembly里的所有静态字段 <field_token, ILType>
-    pub static_fields: Vec<HashMap<u32, ILType>>,
+    /// 静态字段，泛型类型的每个实例化各有一份 <字段所属类型, <field_token, ILType>>
+    pub static_fields: HashMap<RuntimeType, HashMap<u32, ILType>>,
+    /// 已经构建的虚表 <(assembly_index, type_def_index), VTable>，在类型第一次需要虚调用时构建
+    vtables: HashMap<(usize, usize), Rc<VTable>>,
+    /// 已经检查过约束的泛型实例化 <(assembly_index, TypeDef或MethodDef的token, 类型实参)>
+    validated_instantiations: HashSet<(usize, u32, GenericContext)>,
+
+    /// 用户添加的resolver，按顺序在resolver之前询问
+    resolvers: Vec<Box<dyn AssemblyResolver>>,
+    /// 负责在磁盘上寻找被引用的Assembly，总是最后一个询问
+    pub resolver: PathResolver,
+    /// 被引用的Assembly的版本检查策略，通过set_binding_policy修改
+    binding_policy: BindingPolicy,
+    /// 托管调用堆栈的最大深度，超过时抛出StackOverflowException
+    pub max_call_depth: usize,
 }
 
 impl Interpreter {
+    /// 未处理的托管异常导致退出时的退出码，和CLR在Windows上的异常代码相同
+    pub const UNHANDLED_EXCEPTION_EXIT_CODE: i32 = 0xE0434352u32 as i32;
+    const COR_LIB_NAMES: [&'static str; 2] = ["System.Private.CoreLib", "mscorlib"];
+
     pub fn new(assembly_path: String) -> io::Result<Interpreter> {
+        let assembly = Assembly::new(&assembly_path, false)?;
+        Ok(Self::from_assembly(assembly))
+    }
+
+    /// 以一个已经加载的Assembly作为入口，如果它来自内存，那么不会探测它所在的目录
+    pub fn from_assembly(assembly: Assembly) -> Interpreter {
         let mut assemblies = HashVec::new();
         // assemblies.insert(String::from("mscorlib"), Rc::new(Assembly::load_cor_lib().unwrap()));  // index0放入mscorlib
 
-        let assembly = Assembly::new(&assembly_path, false)?;
+        let resolver = PathResolver::new(&assembly.assembly_path, Self::get_framework_version(&assembly), BindingPolicy::default());
         assemblies.insert(String::default(), Rc::new(assembly));
 
-        Ok(Interpreter {
+        Interpreter {
             assemblies,
             stack: VecDeque::new(),
             objects: Vec::new(),
             strings: Vec::new(),
+            arrays: Vec::new(),
 
-            static_fields: Vec::new(),
-        })
+            static_fields: HashMap::new(),
+            vtables: HashMap::new(),
+            validated_instantiations: HashSet::new(),
+
+            resolvers: Vec::new(),
+            resolver,
+            binding_policy: BindingPolicy::default(),
+            max_call_depth: 1000,
+        }
+    }
+
+    /// 从.NET 5开始，System.Runtime的主次版本号和共享框架的主次版本号一致
+    fn get_framework_version(assembly: &Assembly) -> Option<(u16, u16)> {
+        assembly.assembly_refs.iter()
+            .find(|r| r.assembly_name.name == "System.Runtime")
+            .map(|r| (r.assembly_name.major_version, r.assembly_name.minor_version))
+            .filter(|(major, _)| *major >= 5)
+    }
+
+    /// 修改被引用的Assembly的版本检查策略，自动发现的共享框架也按照新的策略重新选择
+    pub fn set_binding_policy(&mut self, policy: BindingPolicy) {
+        self.binding_policy = policy;
+        self.resolver.set_binding_policy(policy);
+    }
+
+    /// 添加一个探测目录，优先级低于入口Assembly所在目录，高于环境变量和共享框架
+    pub fn add_probing_path<P: Into<std::path::PathBuf>>(&mut self, path: P) {
+        self.resolver.add_probing_path(path);
+    }
+
+    /// 添加一个自定义的resolver，先添加的先询问，都在磁盘探测之前
+    pub fn add_resolver(&mut self, resolver: Box<dyn AssemblyResolver>) {
+        self.resolvers.push(resolver);
     }
 
-    pub fn run(&mut self) {
+    /// 从入口Assembly的入口方法开始执行，args传给Main(string[] args)，返回Main的返回值作为退出码（void则为0）
+    pub fn run(&mut self, args: Vec<String>) -> Result<i32, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(0).unwrap());
+        let entry_point_token = assembly.entry_point_token();
+        if entry_point_token >> 24 != 0x06 || entry_point_token & 0x00FFFFFF == 0 || (entry_point_token & 0x00FFFFFF) as usize > assembly.methods.len() {
+            return Err(RuntimeErrorKind::InvalidToken(entry_point_token).into());
+        }
+        let entry_point = &assembly.methods[(entry_point_token & 0x00FFFFFF) as usize - 1];
+        let (param_count, returns_int) = match &entry_point.signature {
+            Some(CallingConventionSig::MethodSig(sig)) => {
+                (sig.base.parameters.len(), sig.base.ret_type == Some(TypeSig::CorLibTypeSig(CorLibType::Int32)))
+            },
+            _ => return Err(RuntimeErrorKind::BadImage(String::from("Entry point has no method signature")).into()),
+        };
+        match param_count {
+            0 => {},
+            1 => {  // Main(string[] args)
+                let args = args.into_iter().map(|arg| {
+                    self.strings.push(arg);
+                    ILType::Ref(ILRefType::String(self.strings.len() - 1))
+                }).collect();
+                self.arrays.push(Array::new(None, args));
+                self.stack.push_back(ILType::Ref(ILRefType::Array(self.arrays.len() - 1)));
+            },
+            _ => return Err(RuntimeErrorKind::BadImage(String::from("Entry point must have zero or one parameter")).into()),
+        }
+
         println!("\nstart run:\n");
-        self.il_call(&mut Context::new(&self.assemblies.index_get(0).unwrap(), 0), 0x06000001);
+        match self.il_call(&mut Context::new(&assembly, 0), entry_point_token, &GenericContext::default()) {
+            Ok(()) => {},
+            Err(RuntimeError { kind: RuntimeErrorKind::Exception { object, .. }, stack_trace }) => {
+                let (type_name, message) = self.describe_exception(object);
+                let error = RuntimeError {
+                    kind: RuntimeErrorKind::UnhandledException { type_name, message },
+                    stack_trace,
+                };
+                eprintln!("{}", error);
+                return Ok(Self::UNHANDLED_EXCEPTION_EXIT_CODE);
+            },
+            Err(e) => return Err(e),
+        }
+        if returns_int {
+            if let Some(ILType::Val(ILValType::Int32(exit_code))) = self.stack.pop_back() {
+                return Ok(exit_code);
+            }
+        }
+        Ok(0)
     }
 
     pub fn format_il_type(&self, il_type: &ILType) -> String {
@@ -260,25 +448,51 @@ impl Interpreter {
             ILType::Ref(ILRefType::Null) => String::from("Null"),
             ILType::Ref(ILRefType::Object(o)) => format!("{}", self.objects[*o as usize].to_string(self)),
             ILType::Ref(ILRefType::String(s)) => format!("{}", self.strings[*s as usize]),
+            ILType::Ref(ILRefType::Array(a)) => format!("Array[{}]", self.arrays[*a].len()),
             ILType::Val(v) => format!("{}", v.to_string()),
             ILType::Ptr(p) => format!("Ptr: {:?}", p),
             ILType::NPtr(n) => format!("NPtr: {:?}", n),
+            ILType::Handle(ILHandle { runtime_type: Some(runtime_type), .. }) => format!("Handle: {}", self.format_runtime_type(runtime_type)),
+            ILType::Handle(h) => format!("Handle: 0x{:08X}", h.token),
+            ILType::Struct(v) => self.get_type_def_full_name((v.assembly_index, (v.type_token & 0x00FFFFFF) as usize - 1)),
+            ILType::FnPtr(f) => {
+                let assembly = self.assemblies.index_get(f.method.0).unwrap();
+                format!("FnPtr: {}", assembly.methods[f.method.1].to_string(assembly))
+            },
         }
     }
     
-    fn load_assembly(&mut self, assembly_name: &AssemblyName) -> Rc<Assembly> {
-        let assembly = Rc::new(Assembly::load(assembly_name).unwrap());
+    /// 依次询问所有resolver，全部找不到时返回的错误包含每个resolver的信息
+    fn load_assembly(&mut self, assembly_name: &AssemblyName, policy: BindingPolicy) -> io::Result<Rc<Assembly>> {
+        let mut messages = Vec::new();
+        let mut loaded = None;
+        let resolvers = self.resolvers.iter().map(|r| r.as_ref()).chain(std::iter::once(&self.resolver as &dyn AssemblyResolver));
+        for resolver in resolvers {
+            match Assembly::load(assembly_name, resolver, policy) {
+                Ok(assembly) => {
+                    loaded = Some(assembly);
+                    break;
+                },
+                Err(e) if e.kind() == io::ErrorKind::NotFound => messages.push(e.to_string()),
+                Err(e) => return Err(e),
+            }
+        }
+        let assembly = match loaded {
+            Some(assembly) => Rc::new(assembly),
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, messages.join("\n"))),
+        };
         self.assemblies.insert(assembly_name.name.clone(), assembly.clone());
-        self.static_fields.push(HashMap::new());
-        assembly
+        Ok(assembly)
     }
 
     /// 解析给定的type_ref，如果其引用的Assembly未加载，那就加载它，返回(type_def_index, 加载后assembly的index)
-    fn resolve_type_ref(&mut self, ctx: &Context, type_ref_token: u32) -> (usize, usize) {
-        let type_ref = ctx.assembly.type_refs.index_get((type_ref_token & 0x00FFFFFF) as usize - 1).unwrap();
+    fn resolve_type_ref(&mut self, ctx: &Context, type_ref_token: u32) -> Result<(usize, usize), RuntimeError> {
+        let type_ref = ctx.assembly.type_refs.index_get(((type_ref_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+            .ok_or(RuntimeErrorKind::InvalidToken(type_ref_token))?;
         let mut assembly_index;
         let mut assembly = &Rc::clone(&ctx.assembly);
-        let resolution_scope = assembly.metadata.resolve_resolution_scope(type_ref.resolution_scope as u32).unwrap();
+        let resolution_scope = assembly.metadata.resolve_resolution_scope(type_ref.resolution_scope as u32)
+            .ok_or(RuntimeErrorKind::InvalidToken(type_ref_token))?;
         if (resolution_scope >> 24) == 0x23 {  // AssemblyRef
             let assembly_name = &assembly.assembly_refs[(resolution_scope & 0x00FFFFFF) as usize - 1].assembly_name.clone();
             match self.assemblies.key_get_index(&assembly_name.name) {
@@ -286,14 +500,15 @@ impl Interpreter {
                     assembly_index = index;
                 },
                 None => {
-                    self.load_assembly(&assembly_name);
+                    self.load_assembly(&assembly_name, self.binding_policy).map_err(|e| RuntimeErrorKind::UnresolvedAssembly(e.to_string()))?;
                     assembly_index = self.assemblies.len() - 1;
                 },
             };
             assembly = self.assemblies.index_get(assembly_index).unwrap();
             let dest_type_index = assembly.type_defs.key_get_index(&type_ref.full_name);
             if dest_type_index.is_none() {  // 说明是ExportedType
-                let exported_type = assembly.exported_types.key_get(&type_ref.full_name).unwrap();
+                let exported_type = assembly.exported_types.key_get(&type_ref.full_name)
+                    .ok_or_else(|| RuntimeErrorKind::UnresolvedType(type_ref.full_name.clone()))?;
                 match exported_type.implementation_type {
                     ExportedTypeImpl::AssemblyRef => {
                         let assembly_name = &assembly.assembly_refs[exported_type.implementation_rid as usize - 1].assembly_name.clone();
@@ -302,108 +517,773 @@ impl Interpreter {
                                 assembly_index = index;
                             },
                             None => {
-                                self.load_assembly(&assembly_name);
+                                self.load_assembly(&assembly_name, self.binding_policy).map_err(|e| RuntimeErrorKind::UnresolvedAssembly(e.to_string()))?;
                                 assembly_index = self.assemblies.len() - 1;
                             }
                         }
                         assembly = self.assemblies.index_get(assembly_index).unwrap();
-                        return (assembly.type_defs.key_get_index(&type_ref.full_name).unwrap(), assembly_index);
+                        return assembly.type_defs.key_get_index(&type_ref.full_name)
+                            .map(|index| (index, assembly_index))
+                            .ok_or_else(|| RuntimeErrorKind::UnresolvedType(type_ref.full_name.clone()).into());
                     },
-                    _ => todo!()
+                    _ => return Err(RuntimeErrorKind::UnresolvedType(type_ref.full_name.clone()).into()),
                 }
             }
-            return (dest_type_index.unwrap(), assembly_index);
+            return Ok((dest_type_index.unwrap(), assembly_index));
         }
-        panic!("Cannot resolve type reference")
+        Err(RuntimeErrorKind::UnresolvedType(type_ref.full_name.clone()).into())
     }
 
     /// 解析给定的type_token，可能是type_def或者type_ref，如果为type_ref，就可以自动加载Assembly，返回为type_defs的index
-    fn resolve_type_def_or_ref(&mut self, ctx: &mut Context, type_def_or_ref_token: u32) -> usize {
+    fn resolve_type_def_or_ref(&mut self, ctx: &mut Context, type_def_or_ref_token: u32) -> Result<usize, RuntimeError> {
         if type_def_or_ref_token << 8 == 0 {
-            return 0;
+            return Ok(0);
         }
         match type_def_or_ref_token >> 24 {
             0x01 => {  // TypeRef
-                let resolve_result = self.resolve_type_ref(ctx, type_def_or_ref_token);
+                let resolve_result = self.resolve_type_ref(ctx, type_def_or_ref_token)?;
                 ctx.assembly = Rc::clone(self.assemblies.index_get(resolve_result.1).unwrap());
                 ctx.assembly_index = resolve_result.1;
-                resolve_result.0
+                Ok(resolve_result.0)
             },
             0x02 => {  // TypeDef
-                (type_def_or_ref_token as usize & 0x00FFFFFF) - 1
+                Ok((type_def_or_ref_token as usize & 0x00FFFFFF) - 1)
             },
-            _ => panic!("Not a type_def or type_ref"),
+            0x1B => {  // TypeSpec，泛型实例化的类型按照其泛型定义解析
+                let type_spec = ctx.assembly.type_specs.get(((type_def_or_ref_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                    .ok_or(RuntimeErrorKind::InvalidToken(type_def_or_ref_token))?;
+                let generic_type_token = match &type_spec.signature {
+                    Some(TypeSig::GenericInstSig(sig)) => sig.unwarp_token(),
+                    _ => return Err(RuntimeErrorKind::InvalidToken(type_def_or_ref_token).into()),
+                };
+                self.resolve_type_def_or_ref(ctx, generic_type_token)
+            },
+            _ => Err(RuntimeErrorKind::InvalidToken(type_def_or_ref_token).into()),
         }
     }
 
-    /// 获取一个method的local列表
-    fn get_method_locals(&mut self, ctx: &Context, method: &Method) -> Vec<ILType> {
-        if method.local_var_rid == 0 {
-            return Vec::default();
+    /// 在assembly_index对应的Assembly中解析TypeDef或TypeRef，返回(assembly_index, type_def_index)
+    fn resolve_type(&mut self, assembly_index: usize, type_def_or_ref_token: u32) -> Result<(usize, usize), RuntimeError> {
+        let mut ctx = Context::new(self.assemblies.index_get(assembly_index).unwrap(), assembly_index);
+        let type_def_index = self.resolve_type_def_or_ref(&mut ctx, type_def_or_ref_token)?;
+        Ok((ctx.assembly_index, type_def_index))
+    }
+
+    /// 在assembly_index对应的Assembly中解析TypeDef、TypeRef或TypeSpec，generic用于替换TypeSpec中的!n和!!n
+    fn resolve_runtime_type(&mut self, assembly_index: usize, type_token: u32, generic: &GenericContext) -> Result<RuntimeType, RuntimeError> {
+        if type_token >> 24 != 0x1B {
+            return Ok(RuntimeType::Type(self.resolve_type(assembly_index, type_token)?, Vec::new()));
         }
-        let local_sig = &ctx.assembly.standalone_sigs[method.local_var_rid as usize - 1].signature;
-        if let Some(CallingConventionSig::LocalSig(sig)) = local_sig {
-            return ILType::from_type_sigs(sig.locals.iter().collect());
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        let signature = assembly.type_specs.get(((type_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+            .and_then(|type_spec| type_spec.signature.as_ref())
+            .ok_or(RuntimeErrorKind::InvalidToken(type_token))?;
+        self.runtime_type_from_sig(assembly_index, signature, generic)
+    }
+
+    /// 把assembly_index中的类型签名转换为RuntimeType
+    fn runtime_type_from_sig(&mut self, assembly_index: usize, signature: &TypeSig, generic: &GenericContext) -> Result<RuntimeType, RuntimeError> {
+        let next = |interpreter: &mut Self, next_sig: &Option<Box<TypeSig>>| match next_sig {
+            Some(next_sig) => interpreter.runtime_type_from_sig(assembly_index, next_sig, generic),
+            None => Err(RuntimeError::from(RuntimeErrorKind::BadImage(format!("incomplete type signature {:?}", signature)))),
+        };
+        match signature {
+            TypeSig::CorLibTypeSig(cor_lib_type) => self.get_cor_lib_type(cor_lib_type.full_name()),
+            TypeSig::ClassSig(class) | TypeSig::ValueTypeSig(class) => {
+                let token = class.base.as_ref().map(|base| base.token)
+                    .ok_or_else(|| RuntimeErrorKind::BadImage(format!("incomplete type signature {:?}", signature)))?;
+                Ok(RuntimeType::Type(self.resolve_type(assembly_index, token)?, Vec::new()))
+            },
+            TypeSig::GenericInstSig(generic_inst) => {
+                let type_def = self.resolve_type(assembly_index, generic_inst.unwarp_token())?;
+                let args = generic_inst.generic_args.iter()
+                    .map(|arg| self.runtime_type_from_sig(assembly_index, arg, generic))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let instantiation = GenericContext::from_type_args(args);
+                self.check_instantiation(type_def.0, 0x02000001 + type_def.1 as u32, &instantiation)?;
+                Ok(RuntimeType::Type(type_def, instantiation.type_args))
+            },
+            TypeSig::SZArraySig(array) => Ok(RuntimeType::SZArray(Box::new(next(self, &array.base.nextSig)?))),
+            TypeSig::ArraySig(array) => Ok(RuntimeType::Array(Box::new(next(self, &array.base.base.nextSig)?), array.base.rank)),
+            TypeSig::ByRefSig(by_ref) => Ok(RuntimeType::ByRef(Box::new(next(self, &by_ref.nextSig)?))),
+            TypeSig::PtrSig(ptr) => Ok(RuntimeType::Pointer(Box::new(next(self, &ptr.nextSig)?))),
+            TypeSig::GenericVar(var) => generic.type_args.get(var.number as usize).cloned()
+                .ok_or_else(|| RuntimeErrorKind::TypeLoad(format!("!{} has no type argument in the current generic context", var.number)).into()),
+            TypeSig::GenericMVar(var) => generic.method_args.get(var.number as usize).cloned()
+                .ok_or_else(|| RuntimeErrorKind::TypeLoad(format!("!!{} has no type argument in the current generic context", var.number)).into()),
+            TypeSig::PinnedSig(pinned) => next(self, &pinned.nextSig),
+            TypeSig::CModReqdSig(modifier) | TypeSig::CModOptSig(modifier) => next(self, &modifier.base.nextSig),
+            _ => Err(RuntimeErrorKind::UnresolvedType(format!("{:?}", signature)).into()),
+        }
+    }
+
+    /// 核心库中的类型，例如System.String
+    fn get_cor_lib_type(&mut self, full_name: &str) -> Result<RuntimeType, RuntimeError> {
+        let assembly_index = self.get_cor_lib_index()?;
+        let type_def_index = self.assemblies.index_get(assembly_index).unwrap().type_defs.key_get_index(&full_name.to_string())
+            .ok_or_else(|| RuntimeErrorKind::UnresolvedType(full_name.to_string()))?;
+        Ok(RuntimeType::Type((assembly_index, type_def_index), Vec::new()))
+    }
+
+    /// 引用的运行时类型，null和非引用的值返回None
+    fn get_runtime_type(&mut self, value: &ILType) -> Result<Option<RuntimeType>, RuntimeError> {
+        match value {
+            ILType::Ref(ILRefType::String(_)) => Ok(Some(self.get_cor_lib_type("System.String")?)),
+            ILType::Ref(ILRefType::Array(array)) => {
+                let element = match self.arrays[*array].element_type.clone() {
+                    Some(element_type) => element_type,
+                    None => self.get_cor_lib_type("System.Object")?,
+                };
+                if self.arrays[*array].is_sz_array() {
+                    Ok(Some(RuntimeType::SZArray(Box::new(element))))
+                } else {
+                    Ok(Some(RuntimeType::Array(Box::new(element), self.arrays[*array].rank())))
+                }
+            },
+            ILType::Ref(ILRefType::Object(object)) => {
+                let (assembly_index, type_token) = (self.objects[*object].assembly_index, self.objects[*object].get_type());
+                Ok(Some(RuntimeType::Type(self.resolve_type(assembly_index, type_token)?, self.objects[*object].generic_args.clone())))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn get_type_def_full_name(&self, type_def: (usize, usize)) -> String {
+        self.assemblies.index_get(type_def.0).unwrap().get_type_full_name(0x02000001 + type_def.1 as u32).unwrap_or_default()
+    }
+
+    /// 类型的文字描述，和.NET的Type.ToString()相同，例如System.Collections.Generic.List`1[System.Int32]
+    fn format_runtime_type(&self, runtime_type: &RuntimeType) -> String {
+        match runtime_type {
+            RuntimeType::Type(type_def, args) if args.is_empty() => self.get_type_def_full_name(*type_def),
+            RuntimeType::Type(type_def, args) => {
+                let args = args.iter().map(|arg| self.format_runtime_type(arg)).collect::<Vec<_>>();
+                format!("{}[{}]", self.get_type_def_full_name(*type_def), args.join(","))
+            },
+            RuntimeType::SZArray(element) => format!("{}[]", self.format_runtime_type(element)),
+            RuntimeType::Array(element, 1) => format!("{}[*]", self.format_runtime_type(element)),
+            RuntimeType::Array(element, rank) => format!("{}[{}]", self.format_runtime_type(element), ",".repeat(*rank as usize - 1)),
+            RuntimeType::ByRef(element) => format!("{}&", self.format_runtime_type(element)),
+            RuntimeType::Pointer(element) => format!("{}*", self.format_runtime_type(element)),
+            RuntimeType::Var(number) => format!("!{}", number),
+            RuntimeType::MVar(number) => format!("!!{}", number),
         }
-        panic!("Method has no locals")
     }
 
-    /// 尝试获取一个字段的cctor，前提是它是static并且他的cctor尚未被调用过
-    fn try_get_cctor(&mut self, ctx: &Context, field: &Field) -> Option<u32> {
-        if field.is_static() {
-            if self.static_fields[ctx.assembly_index].get(&field.token).is_none() {
-                let owner_type = ctx.assembly.type_defs.index_get(field.owner_type as usize).unwrap();
-                for rid in owner_type.method_list.iter() {
-                    let method = &ctx.assembly.methods[rid as usize - 1];
-                    if method.name == ".cctor" {
-                        return Some(method.token);
+    /// 是否为值类型，即基类为System.ValueType或者System.Enum（System.Enum本身除外）
+    fn is_value_type(&self, runtime_type: &RuntimeType) -> bool {
+        let type_def = match runtime_type {
+            RuntimeType::Type(type_def, _) => *type_def,
+            _ => return false,
+        };
+        let assembly = self.assemblies.index_get(type_def.0).unwrap();
+        let extends = match assembly.type_defs.index_get(type_def.1) {
+            Some(type_def_row) => type_def_row.extends,
+            None => return false,
+        };
+        let base_name = assembly.get_type_full_name(extends).unwrap_or_default();
+        (base_name == "System.ValueType" || base_name == "System.Enum") && self.get_type_def_full_name(type_def) != "System.Enum"
+    }
+
+    /// 委托类型都直接继承System.MulticastDelegate，它们的.ctor和Invoke由运行时实现
+    fn is_delegate_type(&self, type_def: (usize, usize)) -> bool {
+        let assembly = self.assemblies.index_get(type_def.0).unwrap();
+        assembly.type_defs.index_get(type_def.1)
+            .is_some_and(|type_def_row| assembly.get_type_full_name(type_def_row.extends).is_some_and(|name| name == "System.MulticastDelegate"))
+    }
+
+    /// 判断from类型的值能否赋给to类型，包括基类、接口、数组协变、装箱的值类型和Nullable<T>
+    fn is_assignable(&mut self, from: &RuntimeType, to: &RuntimeType) -> Result<bool, RuntimeError> {
+        if from.is_same_type(to) {
+            return Ok(true);
+        }
+        if self.is_variant_compatible(from, to)? {
+            return Ok(true);
+        }
+        if let RuntimeType::Type(to_type_def, to_args) = to {
+            match self.get_type_def_full_name(*to_type_def).as_str() {
+                "System.Object" => return Ok(true),
+                "System.Nullable`1" if to_args.len() == 1 => return Ok(from.is_same_type(&to_args[0])),  // 装箱的T可以转换为Nullable<T>
+                _ => {},
+            }
+        }
+        match from {
+            RuntimeType::Type(type_def, args) => {
+                let assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+                let type_def_row = assembly.type_defs.index_get(type_def.1)
+                    .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def.1 as u32))?;
+                if type_def_row.extends & 0x00FFFFFF != 0 {
+                    let base_type = self.resolve_runtime_type(type_def.0, type_def_row.extends, &GenericContext::from_type_args(args.clone()))?;
+                    if self.is_assignable(&base_type, to)? {
+                        return Ok(true);
+                    }
+                }
+                for interface_token in type_def_row.interfaces.iter() {
+                    let interface = self.resolve_runtime_type(type_def.0, *interface_token, &GenericContext::from_type_args(args.clone()))?;
+                    if self.is_assignable(&interface, to)? {
+                        return Ok(true);
                     }
                 }
+                Ok(false)
+            },
+            RuntimeType::SZArray(element) | RuntimeType::Array(element, _) => {
+                match (from, to) {
+                    (RuntimeType::SZArray(_), RuntimeType::SZArray(to_element)) => return self.is_array_element_compatible(element, to_element),
+                    (RuntimeType::Array(_, rank), RuntimeType::Array(to_element, to_rank)) if rank == to_rank => return self.is_array_element_compatible(element, to_element),
+                    (RuntimeType::SZArray(_), RuntimeType::Type(to_type_def, to_args)) if to_args.len() == 1 => {
+                        // 一维数组T[]还实现了IList<T>等泛型接口
+                        const ARRAY_INTERFACES: [&str; 5] = [
+                            "System.Collections.Generic.IList`1",
+                            "System.Collections.Generic.ICollection`1",
+                            "System.Collections.Generic.IEnumerable`1",
+                            "System.Collections.Generic.IReadOnlyList`1",
+                            "System.Collections.Generic.IReadOnlyCollection`1",
+                        ];
+                        if ARRAY_INTERFACES.contains(&self.get_type_def_full_name(*to_type_def).as_str()) {
+                            return self.is_array_element_compatible(element, &to_args[0]);
+                        }
+                    },
+                    _ => {},
+                }
+                let array_type = self.get_cor_lib_type("System.Array")?;
+                self.is_assignable(&array_type, to)
+            },
+            RuntimeType::ByRef(_) | RuntimeType::Pointer(_) | RuntimeType::Var(_) | RuntimeType::MVar(_) => Ok(false),
+        }
+    }
+
+    /// 同一个泛型接口或委托的两个实例化之间的协变（out T）和逆变（in T），例如IEnumerable<string>可以转换为IEnumerable<object>
+    fn is_variant_compatible(&mut self, from: &RuntimeType, to: &RuntimeType) -> Result<bool, RuntimeError> {
+        let (type_def, from_args, to_args) = match (from, to) {
+            (RuntimeType::Type(type_def, from_args), RuntimeType::Type(to_type_def, to_args))
+                if type_def == to_type_def && !from_args.is_empty() && from_args.len() == to_args.len() => (*type_def, from_args, to_args),
+            _ => return Ok(false),
+        };
+        let assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+        let generic_params = match assembly.type_defs.index_get(type_def.1) {
+            Some(type_def_row) if type_def_row.generic_params.len() == from_args.len() => &type_def_row.generic_params,
+            _ => return Ok(false),
+        };
+        for ((generic_param, from_arg), to_arg) in generic_params.iter().zip(from_args.iter()).zip(to_args.iter()) {
+            // 只有引用类型的类型实参可以变体转换
+            let compatible = from_arg.is_same_type(to_arg)
+                || (generic_param.is_covariant() && !self.is_value_type(from_arg) && self.is_assignable(from_arg, to_arg)?)
+                || (generic_param.is_contravariant() && !self.is_value_type(to_arg) && self.is_assignable(to_arg, from_arg)?);
+            if !compatible {
+                return Ok(false);
             }
         }
-        None
+        Ok(true)
     }
 
-    /// 将owner_type（从Field获取）中的所有static字段初始化
-    fn init_static_fields(&mut self, ctx: &Context, owner_type: u32) {
-        let owner_type = ctx.assembly.type_defs.index_get(owner_type as usize).unwrap();
-        for rid in owner_type.field_list.iter() {
-            let field = &ctx.assembly.fields[rid as usize - 1];
-            if field.is_static() {
-                self.static_fields[ctx.assembly_index].insert(field.token, ILType::from_signature(field.signature.as_ref().unwrap()));
+    /// 检查泛型类型或泛型方法（owner_token）的实例化是否满足类型参数的约束，被检查的类型实参在generic中
+    /// 类型的约束中的!n和方法的约束中的!!n都按照generic替换
+    fn check_instantiation(&mut self, assembly_index: usize, owner_token: u32, generic: &GenericContext) -> Result<(), RuntimeError> {
+        let type_args = if owner_token >> 24 == 0x06 { &generic.method_args } else { &generic.type_args };
+        if type_args.iter().any(RuntimeType::contains_generic_param) {
+            return Ok(());
+        }
+        let key = (assembly_index, owner_token, generic.clone());
+        if !self.validated_instantiations.insert(key.clone()) {  // 先记录下来，约束中引用了自身时不会无限递归
+            return Ok(());
+        }
+        let result = self.check_generic_constraints(assembly_index, owner_token, generic);
+        if result.is_err() {
+            self.validated_instantiations.remove(&key);
+        }
+        result
+    }
+
+    fn check_generic_constraints(&mut self, assembly_index: usize, owner_token: u32, generic: &GenericContext) -> Result<(), RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        let owner_index = ((owner_token & 0x00FFFFFF) as usize).wrapping_sub(1);
+        let (generic_params, type_args, owner_name) = if owner_token >> 24 == 0x06 {
+            let method = assembly.methods.get(owner_index).ok_or(RuntimeErrorKind::InvalidToken(owner_token))?;
+            (&method.generic_params, &generic.method_args, method.to_string(&assembly))
+        } else {
+            let type_def_row = assembly.type_defs.index_get(owner_index).ok_or(RuntimeErrorKind::InvalidToken(owner_token))?;
+            (&type_def_row.generic_params, &generic.type_args, self.get_type_def_full_name((assembly_index, owner_index)))
+        };
+        if generic_params.len() != type_args.len() {
+            return Err(RuntimeErrorKind::TypeLoad(format!("'{}' requires {} type arguments, but {} were given.", owner_name, generic_params.len(), type_args.len())).into());
+        }
+        for (generic_param, type_arg) in generic_params.iter().zip(type_args.iter()) {
+            let is_value_type = self.is_value_type(type_arg);
+            let is_nullable = matches!(type_arg, RuntimeType::Type(type_def, _) if self.get_type_def_full_name(*type_def) == "System.Nullable`1");
+            let mut satisfied = !(generic_param.has_reference_type_constraint() && is_value_type)
+                && !(generic_param.has_not_nullable_value_type_constraint() && (!is_value_type || is_nullable))
+                && !(generic_param.has_default_constructor_constraint() && !is_value_type && self.find_default_constructor(type_arg).is_none());
+            for constraint_token in generic_param.constraints.iter() {
+                if !satisfied {
+                    break;
+                }
+                let constraint = self.resolve_runtime_type(assembly_index, *constraint_token, generic)?;
+                satisfied = self.is_assignable(type_arg, &constraint)?;
+            }
+            if !satisfied {
+                return Err(RuntimeErrorKind::TypeLoad(format!("GenericArguments[{}], '{}', on '{}' violates the constraint of type parameter '{}'.",
+                    generic_param.number, self.format_runtime_type(type_arg), owner_name, generic_param.name)).into());
             }
         }
+        Ok(())
     }
 
-    fn get_field_list(&mut self, ctx: &Context, type_def_or_ref: u32, field_map: &mut HashVec<u32, ILType>) {
-        let mut type_def_or_ref = type_def_or_ref;
-        let mut ctx = ctx.make_temp();
-        loop {
-            let type_def_index = self.resolve_type_def_or_ref(&mut ctx, type_def_or_ref);
-            if type_def_index == 0 {
-                return;
+    /// 类型的public无参实例构造函数，返回(assembly_index, method_index)，抽象类和接口没有
+    fn find_default_constructor(&self, runtime_type: &RuntimeType) -> Option<(usize, usize)> {
+        let type_def = match runtime_type {
+            RuntimeType::Type(type_def, _) => *type_def,
+            _ => return None,
+        };
+        let assembly = self.assemblies.index_get(type_def.0).unwrap();
+        let type_def_row = assembly.type_defs.index_get(type_def.1)?;
+        if type_def_row.flags & 0x80 != 0 {  // abstract，接口也是abstract
+            return None;
+        }
+        type_def_row.method_list.iter().map(|rid| rid as usize - 1).find(|method_index| {
+            let method = &assembly.methods[*method_index];
+            let param_count = method.param_count();
+            method.name == ".ctor" && !method.is_static() && method.access() == 6 && param_count == 0
+        }).map(|method_index| (type_def.0, method_index))
+    }
+
+    /// 数组协变：引用类型的元素可以按照赋值兼容性转换，值类型的元素必须是同一个类型
+    fn is_array_element_compatible(&mut self, from: &RuntimeType, to: &RuntimeType) -> Result<bool, RuntimeError> {
+        if from.is_same_type(to) {
+            return Ok(true);
+        }
+        if self.is_value_type(from) {
+            return Ok(false);
+        }
+        self.is_assignable(from, to)
+    }
+
+    /// 判断引用value能否转换为type_token（在assembly_index中）表示的类型，null总是可以
+    fn is_assignable_to(&mut self, value: &ILType, assembly_index: usize, type_token: u32, generic: &GenericContext) -> Result<bool, RuntimeError> {
+        let to = self.resolve_runtime_type(assembly_index, type_token, generic)?;
+        self.is_value_assignable(value, &to)
+    }
+
+    /// 判断引用value能否转换为to类型，null总是可以
+    fn is_value_assignable(&mut self, value: &ILType, to: &RuntimeType) -> Result<bool, RuntimeError> {
+        match self.get_runtime_type(value)? {
+            Some(from) => self.is_assignable(&from, to),
+            None => Ok(true),
+        }
+    }
+
+    /// castclass失败时的异常消息
+    fn describe_invalid_cast(&mut self, value: &ILType, to: &RuntimeType) -> Result<String, RuntimeError> {
+        let from = self.get_runtime_type(value)?.map(|from| self.format_runtime_type(&from)).unwrap_or_default();
+        Ok(format!("Unable to cast object of type '{}' to type '{}'.", from, self.format_runtime_type(to)))
+    }
+
+    /// 在assembly_index对应的Assembly中解析MethodDef、MemberRef或者MethodSpec，返回(assembly_index, method_index)
+    fn resolve_method(&mut self, assembly_index: usize, token: u32) -> Result<(usize, usize), RuntimeError> {
+        let mut ctx = Context::new(self.assemblies.index_get(assembly_index).unwrap(), assembly_index);
+        let method_index = self.get_method_index(&mut ctx, token)?;
+        Ok((ctx.assembly_index, method_index))
+    }
+
+    /// 通过token调用方法时被调用方法的泛型上下文，generic为调用方的泛型上下文，用于替换TypeSpec中的!n和!!n
+    fn resolve_method_context(&mut self, assembly_index: usize, token: u32, generic: &GenericContext) -> Result<GenericContext, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        match token >> 24 {
+            0x0A => {  // 父类为TypeSpec的MemberRef是泛型类型的实例化中的方法
+                let member_ref = assembly.member_refs.get(((token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                    .ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                if member_ref.class >> 24 != 0x1B {
+                    return Ok(GenericContext::default());
+                }
+                match self.resolve_runtime_type(assembly_index, member_ref.class, generic)? {
+                    RuntimeType::Type(_, type_args) => Ok(GenericContext::from_type_args(type_args)),
+                    _ => Ok(GenericContext::default()),
+                }
+            },
+            0x2B => {  // 泛型方法的实例化，方法的类型实参中的!n和!!n按照调用方替换
+                let method_spec = assembly.method_specs.get(((token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                    .ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                let instantiation = match &method_spec.instantiation {
+                    Some(CallingConventionSig::GenericInstMethodSig(instantiation)) => instantiation,
+                    _ => return Err(RuntimeErrorKind::BadImage(format!("MethodSpec 0x{:08X} has no instantiation", token)).into()),
+                };
+                let mut callee_generic = self.resolve_method_context(assembly_index, method_spec.method, generic)?;
+                callee_generic.method_args = instantiation.generic_args.iter()
+                    .map(|arg| self.runtime_type_from_sig(assembly_index, arg, generic))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let method = self.resolve_method(assembly_index, method_spec.method)?;
+                self.check_instantiation(method.0, 0x06000001 + method.1 as u32, &callee_generic)?;
+                Ok(callee_generic)
+            },
+            _ => Ok(GenericContext::default()),
+        }
+    }
+
+    /// runtime_type或者它的基类、接口中type_def的实例化的类型实参，找不到时返回空
+    fn get_base_type_args(&mut self, runtime_type: &RuntimeType, type_def: (usize, usize)) -> Result<Vec<RuntimeType>, RuntimeError> {
+        let (current, args) = match runtime_type {
+            RuntimeType::Type(current, args) => (*current, args),
+            _ => return Ok(Vec::new()),
+        };
+        if current == type_def {
+            return Ok(args.clone());
+        }
+        let assembly = Rc::clone(self.assemblies.index_get(current.0).unwrap());
+        let type_def_row = assembly.type_defs.index_get(current.1)
+            .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + current.1 as u32))?;
+        let generic = GenericContext::from_type_args(args.clone());
+        let extends = Some(type_def_row.extends).filter(|extends| extends & 0x00FFFFFF != 0);
+        for parent_token in extends.iter().chain(type_def_row.interfaces.iter()) {
+            let parent = self.resolve_runtime_type(current.0, *parent_token, &generic)?;
+            let parent_args = self.get_base_type_args(&parent, type_def)?;
+            if !parent_args.is_empty() {
+                return Ok(parent_args);
             }
-            let type_def = ctx.assembly.type_defs.index_get(type_def_index).unwrap();
-            for rid in type_def.field_list.iter() {
-                if ctx.assembly.fields[rid as usize - 1].is_static() {
+        }
+        Ok(Vec::new())
+    }
+
+    /// 解析FieldDef或者MemberRef，返回字段的(assembly_index, field_index)和字段所属类型的实例化
+    fn resolve_field(&mut self, assembly_index: usize, token: u32, generic: &GenericContext) -> Result<((usize, usize), RuntimeType), RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        let rid = (token & 0x00FFFFFF) as usize;
+        match token >> 24 {
+            0x04 => {
+                let field = assembly.fields.get(rid.wrapping_sub(1)).ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                Ok(((assembly_index, rid - 1), RuntimeType::Type((assembly_index, field.owner_type as usize), Vec::new())))
+            },
+            0x0A => {  // 其他Assembly中的字段，或者泛型类型的实例化中的字段
+                let member_ref = assembly.member_refs.get(rid.wrapping_sub(1)).ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                let owner = self.resolve_runtime_type(assembly_index, member_ref.class, generic)?;
+                let type_def = match &owner {
+                    RuntimeType::Type(type_def, _) => *type_def,
+                    _ => return Err(RuntimeErrorKind::InvalidToken(member_ref.class).into()),
+                };
+                let owner_assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+                let type_def_row = owner_assembly.type_defs.index_get(type_def.1)
+                    .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def.1 as u32))?;
+                let field_rid = type_def_row.field_list.iter().find(|rid| owner_assembly.fields[*rid as usize - 1].name == member_ref.name)
+                    .ok_or_else(|| RuntimeErrorKind::UnresolvedMember(format!("{}::{}", self.get_type_def_full_name(type_def), member_ref.name)))?;
+                Ok(((type_def.0, field_rid as usize - 1), owner))
+            },
+            _ => Err(RuntimeErrorKind::InvalidToken(token).into()),
+        }
+    }
+
+    /// 获取类型的虚表，如果尚未构建则先构建基类的虚表，再在其上构建这个类型的虚表和接口映射
+    fn get_vtable(&mut self, type_def: (usize, usize)) -> Result<Rc<VTable>, RuntimeError> {
+        if let Some(vtable) = self.vtables.get(&type_def) {
+            return Ok(Rc::clone(vtable));
+        }
+        let (assembly_index, type_def_index) = type_def;
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        let type_def_row = assembly.type_defs.index_get(type_def_index)
+            .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def_index as u32))?;
+        // 未实例化的类型，签名中的!n替换为Var(n)
+        let open_args = (0..type_def_row.generic_params.len() as u32).map(RuntimeType::Var).collect::<Vec<_>>();
+        let open_generic = GenericContext::from_type_args(open_args.clone());
+        let open_type = RuntimeType::Type(type_def, open_args);
+        let base_vtable = if type_def_row.extends & 0x00FFFFFF == 0 {
+            None
+        } else {
+            match self.resolve_runtime_type(assembly_index, type_def_row.extends, &open_generic)? {
+                RuntimeType::Type(base_type, base_args) => Some((self.get_vtable(base_type)?, base_args)),
+                base_type => return Err(RuntimeErrorKind::TypeLoad(format!("{} cannot be a base type", self.format_runtime_type(&base_type))).into()),
+            }
+        };
+        let mut vtable = base_vtable.as_ref().map(|(base_vtable, base_args)| base_vtable.instantiate(base_args)).unwrap_or_default();
+        let base_vtable = base_vtable.map(|(base_vtable, _)| base_vtable);
+
+        for rid in type_def_row.method_list.iter() {
+            let method_index = rid as usize - 1;
+            let method = &assembly.methods[method_index];
+            if !method.is_virtual() {
+                continue;
+            }
+            let overridden = if method.is_new_slot() {
+                None
+            } else {
+                self.find_overridden_slot(&vtable, &open_type, (assembly_index, method_index))?
+            };
+            match overridden {
+                Some(slot) => vtable.slots[slot].body = (assembly_index, method_index),
+                None => vtable.slots.push(VTableSlot {
+                    declaration: (assembly_index, method_index),
+                    body: (assembly_index, method_index),
+                }),
+            }
+        }
+
+        // MethodImpl中的显式重写优先于按名称的匹配，不在虚表中的是显式实现的接口方法
+        let class_token = 0x02000001 + type_def_index as u32;
+        let mut explicit_interface_impls = Vec::new();
+        for method_impl in assembly.method_impls.iter().filter(|method_impl| method_impl.class == class_token) {
+            let body = self.resolve_method(assembly_index, method_impl.body)?;
+            let declaration = self.resolve_method(assembly_index, method_impl.declaration)?;
+            match vtable.find_slot(declaration) {
+                Some(slot) => vtable.slots[slot].body = body,
+                None => {
+                    let interface_owner = (declaration.0, self.assemblies.index_get(declaration.0).unwrap().methods[declaration.1].owner_type as usize);
+                    let interface_args = self.resolve_method_context(assembly_index, method_impl.declaration, &open_generic)?.type_args;
+                    explicit_interface_impls.push(((RuntimeType::Type(interface_owner, interface_args), declaration), body));
+                },
+            }
+        }
+
+        // 从基类继承的接口映射，如果实现方法被这个类型重写了，就改为重写后的方法
+        if let Some(base_vtable) = &base_vtable {
+            for body in vtable.interface_impls.values_mut() {
+                if let Some(slot) = base_vtable.slots.iter().position(|slot| slot.body == *body) {
+                    *body = vtable.slots[slot].body;
+                }
+            }
+        }
+
+        // 直接声明的接口以及这些接口继承的接口
+        let mut declared_interfaces = Vec::new();
+        for interface_token in type_def_row.interfaces.iter() {
+            let interface = self.resolve_runtime_type(assembly_index, *interface_token, &open_generic)?;
+            let (interface_type, interface_args) = match &interface {
+                RuntimeType::Type(interface_type, interface_args) => (*interface_type, interface_args.clone()),
+                _ => return Err(RuntimeErrorKind::TypeLoad(format!("{} is not an interface", self.format_runtime_type(&interface))).into()),
+            };
+            let interface_vtable = self.get_vtable(interface_type)?;
+            let inherited = interface_vtable.interfaces.iter().map(|inherited| inherited.substitute(&interface_args));
+            for interface in std::iter::once(interface.clone()).chain(inherited) {
+                if !declared_interfaces.contains(&interface) {
+                    declared_interfaces.push(interface);
+                }
+            }
+        }
+        for interface in declared_interfaces {
+            if !vtable.interfaces.contains(&interface) {
+                vtable.interfaces.push(interface.clone());
+            }
+            let (interface_type, interface_generic) = match &interface {
+                RuntimeType::Type(interface_type, interface_args) => (*interface_type, GenericContext::from_type_args(interface_args.clone())),
+                _ => continue,
+            };
+            let interface_assembly = Rc::clone(self.assemblies.index_get(interface_type.0).unwrap());
+            let interface_row = interface_assembly.type_defs.index_get(interface_type.1).unwrap();
+            for rid in interface_row.method_list.iter() {
+                let interface_method = (interface_type.0, rid as usize - 1);
+                if !interface_assembly.methods[interface_method.1].is_virtual() {
+                    continue;
+                }
+                let key = (interface.clone(), interface_method);
+                // 依次查找：这个类型声明的public虚方法、基类已有的映射、继承来的public虚方法，都没有时使用接口的默认实现
+                let mut body = None;
+                for method_index in type_def_row.method_list.iter().map(|rid| rid as usize - 1) {
+                    if self.is_interface_implementation(interface_method, &interface_generic, (assembly_index, method_index), &open_generic)? {
+                        body = Some((assembly_index, method_index));
+                        break;
+                    }
+                }
+                body = body.or_else(|| vtable.interface_impls.get(&key).copied());
+                if body.is_none() {
+                    for slot in vtable.slots.iter().rev() {
+                        let owner = (slot.body.0, self.assemblies.index_get(slot.body.0).unwrap().methods[slot.body.1].owner_type as usize);
+                        let generic = GenericContext::from_type_args(self.get_base_type_args(&open_type, owner)?);
+                        if self.is_interface_implementation(interface_method, &interface_generic, slot.body, &generic)? {
+                            body = Some(slot.body);
+                            break;
+                        }
+                    }
+                }
+                vtable.interface_impls.insert(key, body.unwrap_or(interface_method));
+            }
+        }
+        vtable.interface_impls.extend(explicit_interface_impls);
+
+        let vtable = Rc::new(vtable);
+        self.vtables.insert(type_def, Rc::clone(&vtable));
+        Ok(vtable)
+    }
+
+    /// 没有newslot的虚方法重写基类中名称和签名都相同的最近的槽位
+    /// open_type为method所属的未实例化的类型，基类方法的签名按照基类在open_type的继承链中的实例化替换!n
+    fn find_overridden_slot(&mut self, vtable: &VTable, open_type: &RuntimeType, method: (usize, usize)) -> Result<Option<usize>, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(method.0).unwrap());
+        let method_row = &assembly.methods[method.1];
+        let open_generic = GenericContext::from_type_args(match open_type {
+            RuntimeType::Type(_, args) => args.clone(),
+            _ => Vec::new(),
+        });
+        for slot in (0..vtable.slots.len()).rev() {
+            let base = vtable.slots[slot].body;
+            let base_assembly = Rc::clone(self.assemblies.index_get(base.0).unwrap());
+            let base_method = &base_assembly.methods[base.1];
+            let accessible = base.0 == method.0 || !(1..=3).contains(&base_method.access());  // private、famANDassem和assembly不能跨Assembly访问
+            if base_method.name != method_row.name || base_method.is_final() || (base_method.is_strict() && !accessible) {
+                continue;
+            }
+            let base_generic = GenericContext::from_type_args(self.get_base_type_args(open_type, (base.0, base_method.owner_type as usize))?);
+            if self.is_same_method_sig(base, &base_generic, method, &open_generic)? {
+                return Ok(Some(slot));
+            }
+        }
+        Ok(None)
+    }
+
+    /// method能否隐式实现接口方法interface_method：public的虚方法，名称相同并且签名按照各自的泛型上下文替换后相同
+    fn is_interface_implementation(&mut self, interface_method: (usize, usize), interface_generic: &GenericContext, method: (usize, usize), generic: &GenericContext) -> Result<bool, RuntimeError> {
+        let (interface_assembly, assembly) = (Rc::clone(self.assemblies.index_get(interface_method.0).unwrap()), Rc::clone(self.assemblies.index_get(method.0).unwrap()));
+        let method_row = &assembly.methods[method.1];
+        if !method_row.is_virtual() || method_row.access() != 6 || method_row.name != interface_assembly.methods[interface_method.1].name {
+            return Ok(false);
+        }
+        self.is_same_method_sig(interface_method, interface_generic, method, generic)
+    }
+
+    /// 两个方法的签名解析为RuntimeType之后是否相同，generic和other_generic分别替换两个签名中的!n，!!n都替换为MVar(n)
+    fn is_same_method_sig(&mut self, method: (usize, usize), generic: &GenericContext, other: (usize, usize), other_generic: &GenericContext) -> Result<bool, RuntimeError> {
+        let (assembly, other_assembly) = (Rc::clone(self.assemblies.index_get(method.0).unwrap()), Rc::clone(self.assemblies.index_get(other.0).unwrap()));
+        let (sig, other_sig) = match (&assembly.methods[method.1].signature, &other_assembly.methods[other.1].signature) {
+            (Some(CallingConventionSig::MethodSig(sig)), Some(CallingConventionSig::MethodSig(other_sig))) => (&sig.base, &other_sig.base),
+            _ => return Ok(false),
+        };
+        if sig.gen_param_count != other_sig.gen_param_count || sig.parameters.len() != other_sig.parameters.len() {
+            return Ok(false);
+        }
+        let method_args = (0..sig.gen_param_count).map(RuntimeType::MVar).collect::<Vec<_>>();
+        let generic = GenericContext { type_args: generic.type_args.clone(), method_args: method_args.clone() };
+        let other_generic = GenericContext { type_args: other_generic.type_args.clone(), method_args };
+        for (param, other_param) in sig.ret_type.iter().chain(sig.parameters.iter()).zip(other_sig.ret_type.iter().chain(other_sig.parameters.iter())) {
+            if self.runtime_type_from_sig(method.0, param, &generic)? != self.runtime_type_from_sig(other.0, other_param, &other_generic)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// 调用实例方法method（(assembly_index, method_index)）时this在求值栈上的位置
+    fn this_index(&self, method: (usize, usize)) -> Result<usize, RuntimeError> {
+        let method_row = &self.assemblies.index_get(method.0).unwrap().methods[method.1];
+        let param_count = method_row.param_count();
+        self.stack.len().checked_sub(param_count + 1).ok_or_else(|| RuntimeErrorKind::StackUnderflow.into())
+    }
+
+    /// 调用实例方法method（(assembly_index, method_index)）时求值栈上的this
+    fn peek_this(&self, method: (usize, usize)) -> Result<&ILType, RuntimeError> {
+        let index = self.this_index(method)?;
+        Ok(&self.stack[index])
+    }
+
+    /// callvirt和ldvirtftn时根据this的运行时类型，找到method（(assembly_index, method_index)）实际应该执行的方法
+    /// 同时返回分派所依据的类型：字符串为System.String，数组的虚方法都来自System.Array
+    /// type_args为调用处method所属类型的类型实参，用于区分同一个泛型接口的不同实例化
+    fn resolve_virtual_method(&mut self, this: &ILType, method: (usize, usize), type_args: &[RuntimeType]) -> Result<((usize, usize), Option<RuntimeType>), RuntimeError> {
+        let runtime_type = match this {
+            ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+            ILType::Ref(ILRefType::Array(_)) => Some(self.get_cor_lib_type("System.Array")?),
+            _ => self.get_runtime_type(this)?,
+        };
+        let is_virtual = self.assemblies.index_get(method.0).unwrap().methods[method.1].is_virtual();
+        match &runtime_type {
+            Some(runtime_type @ RuntimeType::Type(..)) if is_virtual => Ok((self.find_override(runtime_type, method, type_args)?, Some(runtime_type.clone()))),
+            _ => Ok((method, runtime_type)),
+        }
+    }
+
+    /// 在runtime_type的虚表或接口映射中找到虚方法method实际执行的方法，type_args为method所属类型在调用处的类型实参
+    fn find_override(&mut self, runtime_type: &RuntimeType, method: (usize, usize), type_args: &[RuntimeType]) -> Result<(usize, usize), RuntimeError> {
+        let (runtime_type, runtime_args) = match runtime_type {
+            RuntimeType::Type(type_def, args) => (*type_def, args),
+            _ => return Ok(method),
+        };
+        let assembly = Rc::clone(self.assemblies.index_get(method.0).unwrap());
+        let method_row = &assembly.methods[method.1];
+        let declaring_type = (method.0, method_row.owner_type as usize);
+        if assembly.type_defs.index_get(declaring_type.1).is_some_and(|type_def| type_def.flags & 0x20 != 0) {  // 接口方法按照接口的实例化在接口映射中查找
+            let interface = RuntimeType::Type(declaring_type, type_args.to_vec());
+            let body = self.get_vtable(runtime_type)?.interface_impls.iter()
+                .find(|((implemented, implemented_method), _)| *implemented_method == method && implemented.substitute(runtime_args).is_same_type(&interface))
+                .map(|(_, body)| *body);
+            let runtime_type_name = self.assemblies.index_get(runtime_type.0).unwrap().get_type_full_name(0x02000001 + runtime_type.1 as u32).unwrap_or_default();
+            return body.ok_or_else(|| RuntimeErrorKind::UnresolvedMember(format!("{} on {}", method_row.to_string(&assembly), runtime_type_name)).into());
+        }
+        let slot = match self.get_vtable(declaring_type)?.find_slot(method) {
+            Some(slot) => slot,
+            None => return Ok(method),
+        };
+        let vtable = self.get_vtable(runtime_type)?;
+        Ok(vtable.slots.get(slot).map_or(method, |slot| slot.body))
+    }
+
+    /// 获取一个method的local列表
+    fn get_method_locals(&mut self, ctx: &Context, method: &Method, generic: &GenericContext) -> Result<Vec<ILType>, RuntimeError> {
+        if method.local_var_rid == 0 {
+            return Ok(Vec::default());
+        }
+        let assembly = Rc::clone(&ctx.assembly);
+        let local_sig = &assembly.standalone_sigs.get(method.local_var_rid as usize - 1)
+            .ok_or(RuntimeErrorKind::InvalidToken(0x11000000 + method.local_var_rid))?.signature;
+        if let Some(CallingConventionSig::LocalSig(sig)) = local_sig {
+            return sig.locals.iter().map(|local| self.get_default_value(ctx.assembly_index, local, generic)).collect();
+        }
+        Err(RuntimeErrorKind::BadImage(format!("Method {} has no locals", method.name)).into())
+    }
+
+    /// 字段所属类型的实例化第一次被访问时，初始化它的所有静态字段，然后调用.cctor
+    fn ensure_static_fields(&mut self, ctx: &mut Context, owner: &RuntimeType) -> Result<(), RuntimeError> {
+        if self.static_fields.contains_key(owner) {
+            return Ok(());
+        }
+        let (type_def, type_args) = match owner {
+            RuntimeType::Type(type_def, type_args) => (*type_def, type_args),
+            _ => return Err(RuntimeErrorKind::UnresolvedType(self.format_runtime_type(owner)).into()),
+        };
+        let assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+        let type_def_row = assembly.type_defs.index_get(type_def.1)
+            .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def.1 as u32))?;
+        let generic = Rc::new(GenericContext::from_type_args(type_args.clone()));
+        let mut fields = HashMap::new();
+        for rid in type_def_row.field_list.iter() {
+            let field = &assembly.fields[rid as usize - 1];
+            if field.is_static() {
+                fields.insert(field.token, self.get_field_default(type_def.0, field, &generic)?);
+            }
+        }
+        self.static_fields.insert(owner.clone(), fields);  // 先放入默认值，.cctor中访问静态字段时不会再次初始化
+        if let Some(rid) = type_def_row.method_list.iter().find(|rid| assembly.methods[*rid as usize - 1].name == ".cctor") {
+            self.il_invoke_method(ctx, (type_def.0, rid as usize - 1), generic)?;
+        }
+        Ok(())
+    }
+
+    /// 沿继承链收集runtime_type的所有实例字段，字段类型中的!n按照每一层的实例化替换
+    fn get_field_list(&mut self, runtime_type: &RuntimeType, field_map: &mut HashVec<u32, ILType>) -> Result<(), RuntimeError> {
+        let mut current = runtime_type.clone();
+        while let RuntimeType::Type(type_def, type_args) = current {
+            let assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+            let type_def_row = assembly.type_defs.index_get(type_def.1)
+                .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def.1 as u32))?;
+            let generic = GenericContext::from_type_args(type_args);
+            for rid in type_def_row.field_list.iter() {
+                let field = &assembly.fields[rid as usize - 1];
+                if field.is_static() {
                     continue;
                 }
-                field_map.insert(rid, ILType::from_signature(ctx.assembly.fields[rid as usize - 1].signature.as_ref().unwrap()));
+                field_map.insert(rid, self.get_field_default(type_def.0, field, &generic)?);
+            }
+            if type_def_row.extends & 0x00FFFFFF == 0 {
+                break;
             }
-            type_def_or_ref = type_def.extends;
+            current = self.resolve_runtime_type(type_def.0, type_def_row.extends, &generic)?;
         }
+        Ok(())
     }
 
     /// 解析member_ref，如果需要则自动加载Assembly并更改ctx，返回为type_defs的index
-    fn resolve_member_ref(&mut self, ctx: &mut Context, member_ref_token: u32) -> usize {
+    fn resolve_member_ref(&mut self, ctx: &mut Context, member_ref_token: u32) -> Result<usize, RuntimeError> {
         let assembly = Rc::clone(&ctx.assembly);
-        let member_ref = &assembly.member_refs[(member_ref_token & 0x00FFFFFF) as usize - 1];
+        let member_ref = assembly.member_refs.get(((member_ref_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+            .ok_or(RuntimeErrorKind::InvalidToken(member_ref_token))?;
         let name = member_ref.name.clone();
         let class = member_ref.class;
 
         let type_def = match class >> 24 { 
             0x01 => {  // TypeRef
-                let resolve_result = self.resolve_type_ref(ctx, class);
+                let resolve_result = self.resolve_type_ref(ctx, class)?;
                 ctx.assembly = Rc::clone(self.assemblies.index_get(resolve_result.1).unwrap());
                 ctx.assembly_index = resolve_result.1;
                 resolve_result.0
@@ -415,57 +1295,526 @@ impl Interpreter {
                 let type_spec = &ctx.assembly.type_specs[(class & 0x00FFFFFF) as usize - 1];
                 if let Some(TypeSig::GenericInstSig(sig)) = &type_spec.signature {
                     let type_def_or_ref_token = sig.unwarp_token();
-                    self.resolve_type_def_or_ref(ctx, type_def_or_ref_token)
+                    self.resolve_type_def_or_ref(ctx, type_def_or_ref_token)?
                 } else {
-                    panic!("Invalid TypeSpec")
+                    return Err(RuntimeErrorKind::InvalidToken(class).into());
                 }
             },
-            _ => panic!("Invalid member_ref class"),
+            _ => return Err(RuntimeErrorKind::InvalidToken(class).into()),
         };
         let dest_type = ctx.assembly.type_defs.index_get(type_def as usize).unwrap();
         for dest_method_rid in dest_type.method_list.iter() {
             let dest_method = &ctx.assembly.methods[dest_method_rid as usize - 1];
             if dest_method.name == name && dest_method.signature == member_ref.signature {
-                return dest_method_rid as usize - 1;
+                return Ok(dest_method_rid as usize - 1);
             }
         }
-        panic!("Invalid member_ref_token");
+        Err(RuntimeErrorKind::UnresolvedMember(format!("{}.{}::{}", dest_type.namespace, dest_type.name, name)).into())
     }
 
     /// 通过method_token或者member_ref_token获取method的index
-    fn get_method_index(&mut self, ctx: &mut Context, token: u32) -> usize {
+    fn get_method_index(&mut self, ctx: &mut Context, token: u32) -> Result<usize, RuntimeError> {
         match token >> 24 {
             0x06 => {  // 表示是当前Assembly内的方法
-                (token & 0x00FFFFFF) as usize - 1
+                let rid = (token & 0x00FFFFFF) as usize;
+                if rid == 0 || rid > ctx.assembly.methods.len() {
+                    return Err(RuntimeErrorKind::InvalidToken(token).into());
+                }
+                Ok(rid - 1)
             },
             0x0A => {  // 需要先找到MemberRef，再找到TypeRef，最后定位到AssemblyRef
                 self.resolve_member_ref(ctx, token)
             },
-            0x2B => {  // 泛型方法
-                self.get_method_index(ctx, ctx.assembly.method_specs[(token & 0x00FFFFFF) as usize - 1].method)
+            0x2B => {  // 泛型方法，方法的类型实参由resolve_method_context解析
+                let method_spec = ctx.assembly.method_specs.get(((token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                    .ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                self.get_method_index(ctx, method_spec.method)
             },
-            _ => panic!("Invalid method_token")
+            _ => Err(RuntimeErrorKind::InvalidToken(token).into())
         }
     }
 
-    fn il_internal_call(&mut self, method: &Method) {
-        if method.name == "WriteLine" {
-            let value = self.stack.pop_back().unwrap();
-            println!("{}", self.format_il_type(&value).green());
+    /// 从求值栈弹出一个值
+    fn pop(&mut self) -> Result<ILType, RuntimeError> {
+        self.stack.pop_back().ok_or_else(|| RuntimeErrorKind::StackUnderflow.into())
+    }
+
+    /// 从求值栈弹出一个托管指针
+    fn pop_ptr(&mut self) -> Result<ILPtr, RuntimeError> {
+        match self.pop()? {
+            ILType::Ptr(ptr) => Ok(ptr),
+            ILType::Ref(ILRefType::Null) => Err(RuntimeErrorKind::NullReference.into()),
+            value => Err(RuntimeErrorKind::TypeMismatch(format!("expected a managed pointer, found {:?}", value)).into()),
         }
     }
 
-    fn il_box_obj(&mut self, type_token: u32, value: ILType) {
-        self.objects.push(Object::new_box(type_token, value));
-        self.stack.push_back(ILType::Ref(ILRefType::Object(self.objects.len() - 1)));
+    /// 从求值栈弹出一个数组下标，可以是int32或者native int
+    fn pop_index(&mut self) -> Result<usize, RuntimeError> {
+        match self.pop()? {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => usize::try_from(i).map_err(|_| RuntimeErrorKind::IndexOutOfRange.into()),
+                ILValType::Isize(i) => usize::try_from(i).map_err(|_| RuntimeErrorKind::IndexOutOfRange.into()),
+                v => Err(RuntimeErrorKind::TypeMismatch(format!("array index must be int32 or native int, found {:?}", v)).into()),
+            },
+            value => Err(RuntimeErrorKind::TypeMismatch(format!("array index must be int32 or native int, found {:?}", value)).into()),
+        }
     }
 
-    fn il_new_obj(&mut self, ctx: &Context, type_token: u32) {
-        // 由于类存在继承，所以FieldList可能是不连续的
-        let mut field_map = HashVec::new();
-        self.get_field_list(ctx, type_token, &mut field_map);
-        self.objects.push(Object::new(type_token, field_map));
+    /// 依次弹出下标和数组，检查边界后返回指向该元素的指针
+    fn pop_element(&mut self) -> Result<ILPtr, RuntimeError> {
+        let index = self.pop_index()?;
+        match self.pop()? {
+            ILType::Ref(ILRefType::Array(array)) => {
+                if index >= self.arrays[array].len() {
+                    return Err(RuntimeErrorKind::IndexOutOfRange.into());
+                }
+                Ok(ILPtr::Element((array, index)))
+            },
+            ILType::Ref(ILRefType::Null) => Err(RuntimeErrorKind::NullReference.into()),
+            value => Err(RuntimeErrorKind::TypeMismatch(format!("expected an array, found {:?}", value)).into()),
+        }
+    }
+
+    /// 从求值栈弹出一个int32，用于多维数组的下标、下界和长度
+    fn pop_i32(&mut self) -> Result<i32, RuntimeError> {
+        match self.pop()? {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => Ok(i),
+                ILValType::Isize(i) => i32::try_from(i).map_err(|_| RuntimeErrorKind::IndexOutOfRange.into()),
+                v => Err(RuntimeErrorKind::TypeMismatch(format!("expected int32, found {:?}", v)).into()),
+            },
+            value => Err(RuntimeErrorKind::TypeMismatch(format!("expected int32, found {:?}", value)).into()),
+        }
+    }
+
+    /// 多维数组的方法由运行时提供：.ctor、Get、Set和Address，它们通过父类为ArraySig的TypeSpec的MemberRef引用
+    /// token不是这样的方法时返回Ok(false)
+    fn il_array_method(&mut self, ctx: &mut Context, token: u32, is_newobj: bool, generic: &GenericContext) -> Result<bool, RuntimeError> {
+        if token >> 24 != 0x0A {
+            return Ok(false);
+        }
+        let assembly = Rc::clone(&ctx.assembly);
+        let member_ref = match assembly.member_refs.get(((token & 0x00FFFFFF) as usize).wrapping_sub(1)) {
+            Some(member_ref) if member_ref.class >> 24 == 0x1B => member_ref,
+            _ => return Ok(false),
+        };
+        let array_sig = match assembly.type_specs.get(((member_ref.class & 0x00FFFFFF) as usize).wrapping_sub(1)).and_then(|t| t.signature.as_ref()) {
+            Some(TypeSig::ArraySig(sig)) => sig,
+            _ => return Ok(false),
+        };
+        let param_count = match &member_ref.signature {
+            Some(CallingConventionSig::MethodSig(sig)) => sig.base.parameters.len(),
+            _ => return Err(RuntimeErrorKind::InvalidToken(token).into()),
+        };
+        let rank = array_sig.base.rank as usize;
+        let element_sig = array_sig.base.base.nextSig.as_deref();
+
+        match member_ref.name.as_str() {
+            ".ctor" if is_newobj => {
+                // 参数为每一维的长度，或者每一维的(下界, 长度)
+                let mut args = Vec::with_capacity(param_count);
+                for _ in 0..param_count {
+                    args.push(self.pop_i32()?);
+                }
+                args.reverse();
+                let dimensions = if param_count == rank {
+                    args.iter().map(|length| (0, *length)).collect::<Vec<_>>()
+                } else if param_count == rank * 2 {
+                    args.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+                } else {
+                    return Err(RuntimeErrorKind::BadImage(format!("Array constructor with {} parameters for rank {}", param_count, rank)).into());
+                };
+                let dimensions = dimensions.into_iter()
+                    .map(|(lower_bound, length)| usize::try_from(length).map(|length| (lower_bound, length)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| RuntimeErrorKind::Overflow)?;
+                let (element_type, default) = match element_sig {
+                    Some(element_sig) => {
+                        let element_type = self.runtime_type_from_sig(ctx.assembly_index, element_sig, generic)?;
+                        let default = self.get_runtime_type_default(&element_type)?;
+                        (Some(element_type), default)
+                    },
+                    None => (None, ILType::Ref(ILRefType::Null)),
+                };
+                let array = Array::new_multi_dim(element_type, dimensions, default).ok_or(RuntimeErrorKind::Overflow)?;
+                self.arrays.push(array);
+                self.stack.push_back(ILType::Ref(ILRefType::Array(self.arrays.len() - 1)));
+            },
+            "Get" | "Set" | "Address" if !is_newobj => {
+                let value = if member_ref.name == "Set" { Some(self.pop()?) } else { None };
+                let mut indices = Vec::with_capacity(rank);
+                for _ in 0..rank {
+                    indices.push(self.pop_i32()?);
+                }
+                indices.reverse();
+                let array = match self.pop()? {
+                    ILType::Ref(ILRefType::Array(array)) => array,
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("expected an array, found {:?}", value)).into()),
+                };
+                let ptr = ILPtr::Element((array, self.arrays[array].flatten_index(&indices).ok_or(RuntimeErrorKind::IndexOutOfRange)?));
+                match value {
+                    Some(value) => {
+                        if !self.is_assignable_to_element(array, &value)? {
+                            return Err(RuntimeErrorKind::ArrayTypeMismatch.into());
+                        }
+                        self.ptr_store(ctx, ptr, value)?;
+                    },
+                    None if member_ref.name == "Get" => {
+                        let value = self.ptr_load(ctx, ptr)?;
+                        self.stack.push_back(value);
+                    },
+                    None => self.stack.push_back(ILType::Ptr(ptr)),
+                }
+            },
+            _ => return Err(RuntimeErrorKind::UnresolvedMember(format!("array of rank {}::{}", rank, member_ref.name)).into()),
+        }
+        Ok(true)
+    }
+
+    /// 由解释器直接实现的CoreLib方法，通过MemberRef的类型全名和方法名识别，所以不需要加载CoreLib
+    /// token不是这样的方法时返回Ok(false)
+    fn il_intrinsic(&mut self, ctx: &mut Context, token: u32, generic: &GenericContext) -> Result<bool, RuntimeError> {
+        let assembly = Rc::clone(&ctx.assembly);
+        let member_ref_token = match token >> 24 {
+            0x0A => token,
+            0x2B => assembly.method_specs.get(((token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                .ok_or(RuntimeErrorKind::InvalidToken(token))?.method,
+            _ => return Ok(false),
+        };
+        if member_ref_token >> 24 != 0x0A {
+            return Ok(false);
+        }
+        let member_ref = assembly.member_refs.get(((member_ref_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+            .ok_or(RuntimeErrorKind::InvalidToken(member_ref_token))?;
+        let type_name = assembly.get_type_full_name(member_ref.class).unwrap_or_default();
+        let param_count = match &member_ref.signature {
+            Some(CallingConventionSig::MethodSig(sig)) => sig.base.parameters.len(),
+            _ => 0,
+        };
+        match (type_name.as_str(), member_ref.name.as_str()) {
+            ("System.Runtime.CompilerServices.RuntimeHelpers", "InitializeArray") => {
+                let handle = match self.pop()? {
+                    ILType::Handle(handle) if handle.token >> 24 == 0x04 => handle,
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("InitializeArray requires a RuntimeFieldHandle, found {:?}", value)).into()),
+                };
+                let array = match self.pop()? {
+                    ILType::Ref(ILRefType::Array(array)) => array,
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("InitializeArray requires an array, found {:?}", value)).into()),
+                };
+                self.il_initialize_array(array, handle)?;
+            },
+            ("System.Delegate", "Combine") | ("System.Delegate", "Remove") if param_count == 2 => {
+                let value = self.pop()?;
+                let source = self.pop()?;
+                let result = if member_ref.name == "Combine" {
+                    self.il_combine_delegates(source, value)?
+                } else {
+                    self.il_remove_delegate(source, value)?
+                };
+                self.stack.push_back(result);
+            },
+            ("System.Activator", "CreateInstance") if token >> 24 == 0x2B => {  // new T()
+                let method_args = self.resolve_method_context(ctx.assembly_index, token, generic)?.method_args;
+                let runtime_type = method_args.first().ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                self.il_create_instance(ctx, runtime_type)?;
+            },
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// RuntimeHelpers.InitializeArray：把字段在FieldRVA中的初始数据按元素类型复制到数组中
+    fn il_initialize_array(&mut self, array: usize, handle: ILHandle) -> Result<(), RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(handle.assembly_index).unwrap());
+        let field = assembly.fields.get(((handle.token & 0x00FFFFFF) as usize).wrapping_sub(1))
+            .ok_or(RuntimeErrorKind::InvalidToken(handle.token))?;
+        if field.rva == 0 {
+            return Err(RuntimeErrorKind::BadImage(format!("Field {} has no RVA", field.name)).into());
+        }
+        let mut position = assembly.pe.rva_to_file_offset(field.rva);
+        let array = &mut self.arrays[array];
+        for index in 0..array.len() {
+            let element = array.get_mut(index).unwrap();
+            let value = match element {
+                ILType::Val(value) => *value,
+                _ => return Err(RuntimeErrorKind::TypeMismatch(String::from("InitializeArray requires an array of primitive types")).into()),
+            };
+            let bytes = assembly.reader.read_bytes_vec_exact_immut(&mut position, value.size())?;
+            *element = ILType::Val(value.read_le_bytes(&bytes));
+        }
+        Ok(())
+    }
+
+    /// TypeDef、TypeRef或TypeSpec表示的类型的默认值，见get_default_value
+    fn get_type_default(&mut self, assembly_index: usize, type_token: u32, generic: &GenericContext) -> Result<ILType, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        if type_token >> 24 == 0x1B {
+            let signature = assembly.type_specs.get(((type_token & 0x00FFFFFF) as usize).wrapping_sub(1))
+                .and_then(|type_spec| type_spec.signature.as_ref())
+                .ok_or(RuntimeErrorKind::InvalidToken(type_token))?;
+            return self.get_default_value(assembly_index, signature, generic);
+        }
+        // 基元类型不需要加载核心库
+        if let Some(cor_lib_type) = assembly.get_type_full_name(type_token).and_then(|name| CorLibType::from_full_name(&name)) {
+            return Ok(ILType::from_type_sig(&TypeSig::CorLibTypeSig(cor_lib_type)));
+        }
+        let type_def = self.resolve_type(assembly_index, type_token)?;
+        self.get_type_def_default(type_def, &[])
+    }
+
+    /// 类型签名的默认值，值类型为所有字段都是默认值的实例，枚举为基础类型的0，引用类型为null
+    /// 签名中的!n和!!n按照generic替换
+    fn get_default_value(&mut self, assembly_index: usize, signature: &TypeSig, generic: &GenericContext) -> Result<ILType, RuntimeError> {
+        match signature {
+            TypeSig::ValueTypeSig(value_type) => {
+                let token = value_type.base.as_ref().map(|base| base.token)
+                    .ok_or_else(|| RuntimeErrorKind::BadImage(format!("incomplete type signature {:?}", signature)))?;
+                self.get_type_default(assembly_index, token, generic)
+            },
+            TypeSig::GenericInstSig(generic_inst) => {
+                // 引用类型不需要解析类型实参
+                let type_def = self.resolve_type(assembly_index, generic_inst.unwarp_token())?;
+                if !self.is_value_type(&RuntimeType::Type(type_def, Vec::new())) {
+                    return Ok(ILType::Ref(ILRefType::Null));
+                }
+                let type_args = generic_inst.generic_args.iter()
+                    .map(|arg| self.runtime_type_from_sig(assembly_index, arg, generic))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.get_type_def_default(type_def, &type_args)
+            },
+            TypeSig::GenericVar(_) | TypeSig::GenericMVar(_) => {
+                let runtime_type = self.runtime_type_from_sig(assembly_index, signature, generic)?;
+                self.get_runtime_type_default(&runtime_type)
+            },
+            TypeSig::CModReqdSig(ModifierSig { base: NoLeafSig { nextSig: Some(next_sig), .. }, .. })
+            | TypeSig::CModOptSig(ModifierSig { base: NoLeafSig { nextSig: Some(next_sig), .. }, .. })
+            | TypeSig::PinnedSig(NoLeafSig { nextSig: Some(next_sig), .. }) => self.get_default_value(assembly_index, next_sig, generic),
+            _ => Ok(ILType::from_type_sig(signature)),
+        }
+    }
+
+    /// 字段声明的类型的默认值，generic为字段所属类型的实例化
+    fn get_field_default(&mut self, assembly_index: usize, field: &Field, generic: &GenericContext) -> Result<ILType, RuntimeError> {
+        match &field.signature {
+            Some(CallingConventionSig::FieldSig(FieldSig { type_sig: Some(type_sig), .. })) => self.get_default_value(assembly_index, type_sig, generic),
+            _ => Ok(ILType::Ref(ILRefType::Null)),
+        }
+    }
+
+    /// 运行时类型的默认值，未知的泛型参数按引用类型处理
+    fn get_runtime_type_default(&mut self, runtime_type: &RuntimeType) -> Result<ILType, RuntimeError> {
+        match runtime_type {
+            RuntimeType::Type(type_def, type_args) => self.get_type_def_default(*type_def, type_args),
+            _ => Ok(ILType::Ref(ILRefType::Null)),
+        }
+    }
+
+    /// type_def以type_args实例化后的默认值
+    fn get_type_def_default(&mut self, type_def: (usize, usize), type_args: &[RuntimeType]) -> Result<ILType, RuntimeError> {
+        // 基元类型的实例字段是它自己，不能展开
+        if let Some(cor_lib_type) = CorLibType::from_full_name(&self.get_type_def_full_name(type_def)) {
+            return Ok(ILType::from_type_sig(&TypeSig::CorLibTypeSig(cor_lib_type)));
+        }
+        if !self.is_value_type(&RuntimeType::Type(type_def, Vec::new())) {
+            return Ok(ILType::Ref(ILRefType::Null));
+        }
+        let assembly = Rc::clone(self.assemblies.index_get(type_def.0).unwrap());
+        let type_def_row = assembly.type_defs.index_get(type_def.1)
+            .ok_or(RuntimeErrorKind::InvalidToken(0x02000001 + type_def.1 as u32))?;
+        let is_enum = assembly.get_type_full_name(type_def_row.extends).is_some_and(|name| name == "System.Enum");
+        let generic = GenericContext::from_type_args(type_args.to_vec());
+        let mut fields = HashVec::new();
+        for rid in type_def_row.field_list.iter() {
+            let field = &assembly.fields[rid as usize - 1];
+            if field.is_static() {
+                continue;
+            }
+            let value = self.get_field_default(type_def.0, field, &generic)?;
+            if is_enum {  // 枚举唯一的实例字段是value__
+                return Ok(value);
+            }
+            fields.insert(rid, value);
+        }
+        Ok(ILType::Struct(ILStruct::new(type_def.0, type_def_row.token, fields)))
+    }
+
+    /// 值类型实例中名为name的字段的rid
+    fn find_struct_field(&self, value: &ILStruct, name: &str) -> Option<u32> {
+        let assembly = self.assemblies.index_get(value.assembly_index).unwrap();
+        let type_def = assembly.type_defs.index_get((value.type_token & 0x00FFFFFF) as usize - 1)?;
+        type_def.field_list.iter().find(|rid| {
+            let field = &assembly.fields[*rid as usize - 1];
+            !field.is_static() && field.name == name
+        })
+    }
+
+    fn is_nullable(&self, value: &ILStruct) -> bool {
+        self.get_type_def_full_name((value.assembly_index, (value.type_token & 0x00FFFFFF) as usize - 1)) == "System.Nullable`1"
+    }
+
+    /// unbox和unbox.any的类型检查，object必须是to类型的box
+    fn check_unbox(&mut self, object: usize, to: &RuntimeType) -> Result<(), RuntimeError> {
+        let value = ILType::Ref(ILRefType::Object(object));
+        let from = self.get_runtime_type(&value)?;
+        if self.objects[object].box_value.is_none() || !from.is_some_and(|from| from.is_same_type(to)) {
+            let message = self.describe_invalid_cast(&value, to)?;
+            return Err(RuntimeErrorKind::InvalidCast(message).into());
+        }
+        Ok(())
+    }
+
+    /// stelem.ref的协变检查：value能否存入元素类型为array.element_type的数组
+    fn is_assignable_to_element(&mut self, array: usize, value: &ILType) -> Result<bool, RuntimeError> {
+        match self.arrays[array].element_type.clone() {
+            Some(element_type) => self.is_value_assignable(value, &element_type),
+            None => Ok(true),
+        }
+    }
+
+    /// ldelema和ldelem的类型检查，exact时元素类型必须和expected相同，否则只要求元素能赋值给expected
+    fn check_element_type(&mut self, ptr: &ILPtr, expected: &RuntimeType, exact: bool) -> Result<(), RuntimeError> {
+        if let ILPtr::Element((array, _)) = ptr {
+            if let Some(element_type) = self.arrays[*array].element_type.clone() {
+                let matches = if exact { element_type.is_same_type(expected) } else { self.is_assignable(&element_type, expected)? };
+                if !matches {
+                    return Err(RuntimeErrorKind::ArrayTypeMismatch.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 托管指针指向的位置，Param和Local通过stack_id找到对应的栈帧，栈帧已经返回时出错
+    fn ptr_slot<'a>(&'a mut self, ctx: &'a mut Context, ptr: ILPtr) -> Result<&'a mut ILType, RuntimeError> {
+        let slot = match &ptr {
+            ILPtr::Param((stack_id, index)) | ILPtr::Local((stack_id, index)) => {
+                let frame = ctx.call_stack.iter_mut().rev().find(|frame| frame.stack_id == *stack_id)
+                    .ok_or_else(|| RuntimeErrorKind::InvalidPointer(format!("{:?} points to a frame that has returned", ptr)))?;
+                match ptr {
+                    ILPtr::Param(_) => frame.params.get_mut(*index),
+                    _ => frame.locals.get_mut(*index),
+                }
+            },
+            ILPtr::Static(field) => self.static_fields.get_mut(&field.0).and_then(|fields| fields.get_mut(&field.1)),
+            ILPtr::Field((object, rid)) => self.objects.get_mut(*object).and_then(|object| object.get_field_mut(*rid)),
+            ILPtr::Element((array, index)) => self.arrays.get_mut(*array).and_then(|array| array.get_mut(*index)),
+            ILPtr::Boxed(object) => self.objects.get_mut(*object).and_then(|object| object.box_value.as_mut()),
+            ILPtr::StructField(field) => match self.ptr_slot(ctx, field.0.clone())? {
+                ILType::Struct(value) => value.get_field_mut(field.1),
+                _ => None,
+            },
+        };
+        slot.ok_or_else(|| RuntimeErrorKind::InvalidPointer(format!("{:?} does not point to a valid location", ptr)).into())
+    }
+
+    /// ldind、ldobj
+    fn ptr_load(&mut self, ctx: &mut Context, ptr: ILPtr) -> Result<ILType, RuntimeError> {
+        Ok(self.ptr_slot(ctx, ptr)?.to_stack_value())
+    }
+
+    /// stind、stobj，按照指向位置声明的类型截断
+    fn ptr_store(&mut self, ctx: &mut Context, ptr: ILPtr, value: ILType) -> Result<(), RuntimeError> {
+        let slot = self.ptr_slot(ctx, ptr)?;
+        *slot = value.coerce_to(slot);
+        Ok(())
+    }
+
+    /// 弹出两个值并比较，unsigned为true时整数按无符号数比较，浮点数中有NaN时返回None
+    fn pop_compare(&mut self, unsigned: bool) -> Result<Option<Ordering>, RuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        Ok(if unsigned { a.try_cmp_un(&b)? } else { a.try_cmp(&b)? })
+    }
+
+    /// 返回调用堆栈的文字描述，栈顶在前
+    fn capture_stack_trace(&self, ctx: &Context) -> Vec<String> {
+        ctx.call_stack.iter().rev().map(|frame| {
+            let assembly = self.assemblies.index_get(frame.assembly_index).unwrap();
+            format!("{} IL_{:04X}", assembly.methods[frame.method_index].to_string(assembly), frame.offset)
+        }).collect()
+    }
+
+    fn il_internal_call(&mut self, method: &Method) -> Result<(), RuntimeError> {
+        if method.name == "WriteLine" {
+            let mut value = self.pop()?;
+            if let Some(CallingConventionSig::MethodSig(sig)) = &method.signature {  // 例如WriteLine(bool)需要按照声明的类型输出
+                if let Some(param_sig) = sig.base.parameters.first() {
+                    value = value.coerce_to(&ILType::from_type_sig(param_sig));
+                }
+            }
+            println!("{}", self.format_il_type(&value).green());
+        }
+        Ok(())
+    }
+
+    fn il_box_obj(&mut self, runtime_type: &RuntimeType, value: ILType) -> Result<(), RuntimeError> {
+        let (type_def, type_args) = match runtime_type {
+            RuntimeType::Type(type_def, type_args) => (*type_def, type_args),
+            _ => return Err(RuntimeErrorKind::UnresolvedType(self.format_runtime_type(runtime_type)).into()),
+        };
+        let mut object = Object::new_box(type_def.0, 0x02000001 + type_def.1 as u32, value);
+        object.generic_args = type_args.clone();
+        self.objects.push(object);
+        self.stack.push_back(ILType::Ref(ILRefType::Object(self.objects.len() - 1)));
+        Ok(())
+    }
+
+    fn il_new_obj(&mut self, runtime_type: &RuntimeType) -> Result<(), RuntimeError> {
+        let (type_def, type_args) = match runtime_type {
+            RuntimeType::Type(type_def, type_args) => (*type_def, type_args),
+            _ => return Err(RuntimeErrorKind::UnresolvedType(self.format_runtime_type(runtime_type)).into()),
+        };
+        // 由于类存在继承，所以FieldList可能是不连续的
+        let mut field_map = HashVec::new();
+        self.get_field_list(runtime_type, &mut field_map)?;
+        let mut object = Object::new(type_def.0, 0x02000001 + type_def.1 as u32, field_map);
+        object.generic_args = type_args.clone();
+        self.objects.push(object);
         self.stack.push_back(ILType::Ref(ILRefType::Object(self.objects.len() - 1)));
+        Ok(())
+    }
+
+    /// 创建owner的实例，以它为this调用constructor，constructor的参数已经在求值栈上，最后把实例压入求值栈
+    fn il_construct(&mut self, ctx: &mut Context, owner: &RuntimeType, constructor: (usize, usize), generic: Rc<GenericContext>) -> Result<(), RuntimeError> {
+        let constructor_row = &self.assemblies.index_get(constructor.0).unwrap().methods[constructor.1];
+        let param_count = constructor_row.param_count();
+        let args_start = self.stack.len().checked_sub(param_count).ok_or(RuntimeErrorKind::StackUnderflow)?;
+        // .ctor的this放在参数的下面，值类型的this是指向实例的指针，实例暂时放在一个box对象里
+        let this = if self.is_value_type(owner) {
+            let value = self.get_runtime_type_default(owner)?;
+            self.il_box_obj(owner, value)?;
+            ILType::Ptr(ILPtr::Boxed(self.pop()?.get_ref().ok_or(RuntimeErrorKind::StackUnderflow)?))
+        } else {
+            self.il_new_obj(owner)?;
+            self.pop()?
+        };
+        self.stack.insert(args_start, this.clone());
+        self.il_invoke_method(ctx, constructor, generic)?;
+        let value = match this {
+            ILType::Ptr(ILPtr::Boxed(object)) => self.objects[object].box_value.clone().ok_or(RuntimeErrorKind::StackUnderflow)?,
+            this => this,
+        };
+        self.stack.push_back(value);
+        Ok(())
+    }
+
+    /// Activator.CreateInstance<T>()：值类型没有无参构造函数时为默认值，其他情况调用public无参构造函数
+    fn il_create_instance(&mut self, ctx: &mut Context, runtime_type: &RuntimeType) -> Result<(), RuntimeError> {
+        let type_args = match runtime_type {
+            RuntimeType::Type(_, type_args) => type_args.clone(),
+            _ => return Err(RuntimeErrorKind::MissingMethod(format!("Cannot dynamically create an instance of type '{}'.", self.format_runtime_type(runtime_type))).into()),
+        };
+        match self.find_default_constructor(runtime_type) {
+            Some(constructor) => self.il_construct(ctx, runtime_type, constructor, Rc::new(GenericContext::from_type_args(type_args))),
+            None if self.is_value_type(runtime_type) => {
+                let value = self.get_runtime_type_default(runtime_type)?;
+                self.stack.push_back(value);
+                Ok(())
+            },
+            None => Err(RuntimeErrorKind::MissingMethod(format!("No parameterless constructor defined for type '{}'.", self.format_runtime_type(runtime_type))).into()),
+        }
     }
 
     fn il_new_string(&mut self, string: String) {
@@ -473,913 +1822,1640 @@ impl Interpreter {
         self.stack.push_back(ILType::Ref(ILRefType::String(self.strings.len() - 1)));
     }
 
-    fn il_call(&mut self, ctx: &mut Context, method_or_member_ref: u32) {
+    /// generic为调用方的泛型上下文
+    fn il_call(&mut self, ctx: &mut Context, method_or_member_ref: u32, generic: &GenericContext) -> Result<(), RuntimeError> {
+        let method = self.resolve_method(ctx.assembly_index, method_or_member_ref)?;
+        let callee_generic = self.resolve_method_context(ctx.assembly_index, method_or_member_ref, generic)?;
+        self.il_invoke_method(ctx, method, Rc::new(callee_generic))
+    }
+
+    /// 和il_call相同，但是虚方法按照this的运行时类型分派
+    fn il_callvirt(&mut self, ctx: &mut Context, method_or_member_ref: u32, generic: &GenericContext) -> Result<(), RuntimeError> {
+        let method = self.resolve_method(ctx.assembly_index, method_or_member_ref)?;
+        let mut callee_generic = self.resolve_method_context(ctx.assembly_index, method_or_member_ref, generic)?;
+        let this = self.peek_this(method)?.clone();
+        let (body, runtime_type) = self.resolve_virtual_method(&this, method, &callee_generic.type_args)?;
+        if let Some(runtime_type) = runtime_type.filter(|_| body != method) {  // 实际执行的方法可能声明在基类中，它的类型实参要从this的运行时类型中找
+            let owner_type = self.assemblies.index_get(body.0).unwrap().methods[body.1].owner_type as usize;
+            callee_generic.type_args = self.get_base_type_args(&runtime_type, (body.0, owner_type))?;
+        }
+        self.il_invoke_method(ctx, body, Rc::new(callee_generic))
+    }
+
+    /// constrained. T callvirt method，求值栈上的this是指向T的托管指针
+    /// T是引用类型时解引用后正常callvirt；T是值类型并且自己实现了method时直接调用，this仍是托管指针；否则装箱后callvirt
+    fn il_constrained_callvirt(&mut self, ctx: &mut Context, method_or_member_ref: u32, constrained: &RuntimeType, generic: &GenericContext) -> Result<(), RuntimeError> {
+        let method = self.resolve_method(ctx.assembly_index, method_or_member_ref)?;
+        let this_index = self.this_index(method)?;
+        let ptr = match &self.stack[this_index] {
+            ILType::Ptr(ptr) => ptr.clone(),
+            value => return Err(RuntimeErrorKind::TypeMismatch(format!("constrained. callvirt requires a managed pointer as this, found {:?}", value)).into()),
+        };
+        let this = self.ptr_load(ctx, ptr)?;
+        if !self.is_value_type(constrained) {
+            self.stack[this_index] = this;
+            return self.il_callvirt(ctx, method_or_member_ref, generic);
+        }
+        if let RuntimeType::Type(type_def, _) = constrained {
+            let mut callee_generic = self.resolve_method_context(ctx.assembly_index, method_or_member_ref, generic)?;
+            let is_virtual = self.assemblies.index_get(method.0).unwrap().methods[method.1].is_virtual();
+            let body = if is_virtual { self.find_override(constrained, method, &callee_generic.type_args)? } else { method };
+            let body_owner = (body.0, self.assemblies.index_get(body.0).unwrap().methods[body.1].owner_type as usize);
+            if body_owner == *type_def {  // 值类型自己的实现，不需要装箱
+                callee_generic.type_args = self.get_base_type_args(constrained, body_owner)?;
+                return self.il_invoke_method(ctx, body, Rc::new(callee_generic));
+            }
+        }
+        // 继承自ValueType或Object的方法，以及接口的默认实现，需要装箱后的对象作为this
+        self.il_box_obj(constrained, this)?;
+        self.stack[this_index] = self.pop()?;
+        self.il_callvirt(ctx, method_or_member_ref, generic)
+    }
+
+    /// ldftn和ldvirtftn，this不为None时按照它的运行时类型找到虚方法实际执行的方法
+    fn resolve_fn_ptr(&mut self, ctx: &Context, method_or_member_ref: u32, generic: &GenericContext, this: Option<&ILType>) -> Result<ILFnPtr, RuntimeError> {
+        let method = self.resolve_method(ctx.assembly_index, method_or_member_ref)?;
+        let mut callee_generic = self.resolve_method_context(ctx.assembly_index, method_or_member_ref, generic)?;
+        let (body, runtime_type) = match this {
+            Some(this @ ILType::Ref(_)) => self.resolve_virtual_method(this, method, &callee_generic.type_args)?,
+            _ => (method, None),
+        };
+        if let Some(runtime_type) = runtime_type.filter(|_| body != method) {  // 和callvirt相同，实际执行的方法的类型实参要从this的运行时类型中找
+            let owner_type = self.assemblies.index_get(body.0).unwrap().methods[body.1].owner_type as usize;
+            callee_generic.type_args = self.get_base_type_args(&runtime_type, (body.0, owner_type))?;
+        }
+        Ok(ILFnPtr { method: body, generic: Rc::new(callee_generic) })
+    }
+
+    /// 委托类型由运行时实现的方法：.ctor(object, IntPtr)和Invoke，参数已经在求值栈上
+    fn il_delegate_method(&mut self, ctx: &mut Context, method_index: usize) -> Result<(), RuntimeError> {
+        let assembly = Rc::clone(&ctx.assembly);
+        let method = &assembly.methods[method_index];
+        match method.name.as_str() {
+            ".ctor" => {
+                let fn_ptr = match self.pop()? {
+                    ILType::FnPtr(fn_ptr) => fn_ptr,
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("delegate constructor requires a method pointer, found {:?}", value)).into()),
+                };
+                let target = self.pop()?;
+                let this = self.pop()?.get_ref().ok_or(RuntimeErrorKind::NullReference)?;
+                let invoke = assembly.type_defs.index_get(method.owner_type as usize)
+                    .and_then(|type_def| type_def.method_list.iter().map(|rid| &assembly.methods[rid as usize - 1]).find(|method| method.name == "Invoke"))
+                    .ok_or_else(|| RuntimeErrorKind::UnresolvedMember(format!("Invoke of {}", method.to_string(&assembly))))?;
+                let target_assembly = Rc::clone(self.assemblies.index_get(fn_ptr.method.0).unwrap());
+                let target_method = &target_assembly.methods[fn_ptr.method.1];
+                // 目标方法（实例方法包括this）比Invoke多一个参数时为封闭委托，第一个参数绑定为target
+                let target_param_count = target_method.param_count() + !target_method.is_static() as usize;
+                let target = if target_param_count == invoke.param_count() + 1 {
+                    let owner = RuntimeType::Type((fn_ptr.method.0, target_method.owner_type as usize), Vec::new());
+                    match target {
+                        // 值类型的实例方法需要指向装箱值的托管指针作为this
+                        ILType::Ref(ILRefType::Object(object)) if !target_method.is_static() && self.is_value_type(&owner) => Some(ILType::Ptr(ILPtr::Boxed(object))),
+                        target => Some(target),
+                    }
+                } else {
+                    None
+                };
+                self.objects[this].invocation_list = vec![DelegateEntry { target, method: fn_ptr }];
+                Ok(())
+            },
+            "Invoke" => {
+                let args_start = self.stack.len().checked_sub(method.param_count()).ok_or(RuntimeErrorKind::StackUnderflow)?;
+                let args = self.stack.drain(args_start..).collect::<Vec<_>>();
+                let this = self.pop()?.get_ref().ok_or(RuntimeErrorKind::NullReference)?;
+                let invocation_list = self.objects[this].invocation_list.clone();
+                for (index, entry) in invocation_list.iter().enumerate() {
+                    let stack_len = self.stack.len();
+                    self.stack.extend(entry.target.iter().chain(args.iter()).cloned());
+                    self.il_invoke_method(ctx, entry.method.method, Rc::clone(&entry.method.generic))?;
+                    if index + 1 < invocation_list.len() {  // 多播委托只返回最后一个方法的返回值
+                        self.stack.truncate(stack_len);
+                    }
+                }
+                Ok(())
+            },
+            _ => Err(RuntimeErrorKind::UnresolvedMember(format!("{} is not supported by the runtime", method.to_string(&assembly))).into()),
+        }
+    }
+
+    /// 委托的调用列表，null返回None
+    fn get_invocation_list(&self, value: &ILType) -> Result<Option<(usize, Vec<DelegateEntry>)>, RuntimeError> {
+        match value {
+            ILType::Ref(ILRefType::Null) => Ok(None),
+            ILType::Ref(ILRefType::Object(object)) if !self.objects[*object].invocation_list.is_empty() => Ok(Some((*object, self.objects[*object].invocation_list.clone()))),
+            value => Err(RuntimeErrorKind::TypeMismatch(format!("expected a delegate, found {:?}", value)).into()),
+        }
+    }
+
+    /// 创建一个和template同类型的委托，调用列表为invocation_list
+    fn il_new_delegate(&mut self, template: usize, invocation_list: Vec<DelegateEntry>) -> Result<ILType, RuntimeError> {
+        let runtime_type = self.resolve_type(self.objects[template].assembly_index, self.objects[template].get_type())?;
+        self.il_new_obj(&RuntimeType::Type(runtime_type, self.objects[template].generic_args.clone()))?;
+        let delegate = self.pop()?;
+        self.objects[delegate.get_ref().unwrap()].invocation_list = invocation_list;
+        Ok(delegate)
+    }
+
+    /// Delegate.Combine(a, b)：b的调用列表接在a的后面
+    fn il_combine_delegates(&mut self, a: ILType, b: ILType) -> Result<ILType, RuntimeError> {
+        let ((a_object, a_list), (b_object, b_list)) = match (self.get_invocation_list(&a)?, self.get_invocation_list(&b)?) {
+            (None, _) => return Ok(b),
+            (_, None) => return Ok(a),
+            (Some(a), Some(b)) => (a, b),
+        };
+        if self.objects[a_object].get_type() != self.objects[b_object].get_type() || self.objects[a_object].generic_args != self.objects[b_object].generic_args {
+            return Err(RuntimeErrorKind::Argument(String::from("Delegates must be of the same type.")).into());
+        }
+        self.il_new_delegate(a_object, a_list.into_iter().chain(b_list).collect())
+    }
+
+    /// Delegate.Remove(source, value)：移除最后一次出现的value的调用列表，全部移除后为null
+    fn il_remove_delegate(&mut self, source: ILType, value: ILType) -> Result<ILType, RuntimeError> {
+        let ((source_object, source_list), (_, value_list)) = match (self.get_invocation_list(&source)?, self.get_invocation_list(&value)?) {
+            (Some(source), Some(value)) => (source, value),
+            _ => return Ok(source),
+        };
+        match remove_invocation_list(&source_list, &value_list) {
+            None => Ok(source),
+            Some(remaining) if remaining.is_empty() => Ok(ILType::Ref(ILRefType::Null)),
+            Some(remaining) => self.il_new_delegate(source_object, remaining),
+        }
+    }
+
+    /// 切换到method（(assembly_index, method_index)）所在的Assembly执行它，返回后恢复ctx
+    fn il_invoke_method(&mut self, ctx: &mut Context, method: (usize, usize), generic: Rc<GenericContext>) -> Result<(), RuntimeError> {
+        let caller_assembly = Rc::clone(&ctx.assembly);
+        let caller_assembly_index = ctx.assembly_index;
+        ctx.assembly = Rc::clone(self.assemblies.index_get(method.0).unwrap());
+        ctx.assembly_index = method.0;
+        let result = self.il_invoke(ctx, method.1, generic);
+        ctx.assembly = caller_assembly;
+        ctx.assembly_index = caller_assembly_index;
+        result
+    }
+
+    /// 执行ctx.assembly中的第method_index个方法，参数已经在求值栈上，generic为这个方法的泛型上下文
+    fn il_invoke(&mut self, ctx: &mut Context, method_index: usize, generic: Rc<GenericContext>) -> Result<(), RuntimeError> {
+        if ctx.call_stack.len() >= self.max_call_depth {
+            return Err(RuntimeErrorKind::StackOverflow.into());
+        }
         ctx.stack_id += 1;
-        let param_count;
-        let method_index = self.get_method_index(ctx, method_or_member_ref);
-        let method = &ctx.assembly.methods[method_index];
-        ctx.call_stack.push((ctx.assembly_index, method_or_member_ref));
+        let assembly = Rc::clone(&ctx.assembly);
+        let method = &assembly.methods[method_index];
+        let is_internal_call = method.check_impl_flag_info(ImplAttrFlagInfo::CommonImplAttrFlagInfo(CommonImplAttrFlagInfo::InternalCall));
+        if method.check_impl_flag_info(ImplAttrFlagInfo::CodeType(CodeType::Runtime)) && self.is_delegate_type((ctx.assembly_index, method.owner_type as usize)) {
+            return self.il_delegate_method(ctx, method_index);
+        }
+
+        let mut frame = CallFrame::new(ctx.assembly_index, method_index, ctx.stack_id, Rc::clone(&generic));
+        if !is_internal_call {  // InternalCall自己从求值栈上取参数
+            let param_count = if method.is_static() {
+                method.param_count()
+            } else {
+                method.param_count() + 1  // 实例方法第一个参数是this
+            };
+            for _ in 0..param_count {
+                frame.params.push(self.pop()?);
+            }
+            frame.params.reverse();  // 参数是逆向出栈的
+            if let Some(CallingConventionSig::MethodSig(sig)) = &method.signature {  // 按照参数声明的类型截断
+                let this_count = if method.is_static() { 0 } else { 1 };
+                for (param, param_sig) in frame.params.iter_mut().skip(this_count).zip(sig.base.parameters.iter()) {
+                    let declared = match param_sig {
+                        TypeSig::GenericVar(_) | TypeSig::GenericMVar(_) => self.get_default_value(ctx.assembly_index, param_sig, &generic)?,
+                        _ => ILType::from_type_sig(param_sig),
+                    };
+                    *param = param.clone().coerce_to(&declared);
+                }
+            }
+            frame.locals = self.get_method_locals(ctx, method, &generic)?;
+            frame.stack_base = self.stack.len();
+        }
+        ctx.call_stack.push(frame);
         let call_depth = ctx.call_stack.len();
-        let method_name = method.to_string(&ctx.assembly);
+        let method_name = method.to_string(&assembly);
         for _ in 0..call_depth {
             print!("-");
         }
         println!("call: {}", method_name);
-        if method.is_static() {
-            param_count = method.param_list.count as usize;
+
+        let result = if is_internal_call {
+            self.il_internal_call(method)
         } else {
-            param_count = method.param_list.count as usize + 1;  // 实例方法第一个参数是this
+            match self.il_run(ctx, call_depth - 1, 0, (0, usize::MAX)) {
+                Ok(BlockExit::Ret) => Ok(()),
+                Ok(_) => Err(RuntimeErrorKind::BadImage(format!("{} has endfinally or endfilter outside of a handler", method_name)).into()),
+                Err(e) => Err(e),
+            }
+        }.map_err(|mut e| {
+            if e.stack_trace.is_empty() {  // 只在最内层记录调用堆栈
+                e.stack_trace = self.capture_stack_trace(ctx);
+            }
+            e
+        });
+
+        ctx.call_stack.pop();
+        for _ in 0..call_depth {
+            print!("-");
         }
+        println!("exit: {}", method_name);
+        result
+    }
 
-        if method.check_impl_flag_info(ImplAttrFlagInfo::CommonImplAttrFlagInfo(CommonImplAttrFlagInfo::InternalCall)) {
-            self.il_internal_call(method);
-            ctx.call_stack.pop();
-            ctx.assembly_index = ctx.call_stack.last().unwrap().0;
-            ctx.assembly = Rc::clone(&self.assemblies.index_get(ctx.assembly_index).unwrap());
-            for _ in 0..call_depth {
-                print!("-");
+    /// 从start（相对于方法体起始的偏移）开始执行call_stack[frame_index]的代码，直到ret、endfinally或者endfilter
+    /// region为这一层负责的代码范围，只有try块位于region内的异常处理子句才会在这一层处理
+    fn il_run(&mut self, ctx: &mut Context, frame_index: usize, start: usize, region: (usize, usize)) -> Result<BlockExit, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(ctx.call_stack[frame_index].assembly_index).unwrap());
+        let method = &assembly.methods[ctx.call_stack[frame_index].method_index];
+        let mut rip = method.code_position + start;
+        loop {
+            match self.il_step(ctx, frame_index, &mut rip) {
+                Ok(None) => {},
+                Ok(Some(exit)) => return Ok(exit),
+                Err(e) => {
+                    let e = self.raise_managed_exception(ctx, e);
+                    rip = method.code_position + self.il_handle_exception(ctx, frame_index, region, e)?;
+                },
             }
-            println!("exit: {}", method_name);
-            return;
         }
+    }
 
-        let assembly = Rc::clone(&ctx.assembly);
+    /// 第二遍：在这一层中执行finally和fault块，如果处理异常的catch块也在这一层，那么返回handler的偏移，否则继续向上传递
+    fn il_handle_exception(&mut self, ctx: &mut Context, frame_index: usize, region: (usize, usize), e: RuntimeError) -> Result<usize, RuntimeError> {
+        let (object, handler) = match e.kind {
+            RuntimeErrorKind::Exception { object, handler: Some(handler) } => (object, handler),
+            _ => return Err(e),  // 不是托管异常，或者没有任何catch能处理，此时不执行finally
+        };
+        let assembly = Rc::clone(self.assemblies.index_get(ctx.call_stack[frame_index].assembly_index).unwrap());
+        let method = &assembly.methods[ctx.call_stack[frame_index].method_index];
+        let offset = ctx.call_stack[frame_index].offset;
+        let (from, to) = ctx.call_stack[frame_index].unwind_range.take().unwrap_or((offset, offset + 1));
+        self.stack.truncate(ctx.call_stack[frame_index].stack_base);
+        for (clause_index, clause) in method.exception_clauses.iter().enumerate() {
+            if clause.try_offset < region.0 || clause.try_offset >= region.1 {
+                continue;
+            }
+            if handler == (frame_index, clause_index) {
+                ctx.call_stack[frame_index].caught_exceptions.insert(clause_index, object);
+                self.stack.push_back(ILType::Ref(ILRefType::Object(object)));
+                return Ok(clause.handler_offset);
+            }
+            if clause.is_finally_or_fault() && clause.try_offset <= from && to <= clause.try_offset + clause.try_length {
+                if let Err(e) = self.il_run(ctx, frame_index, clause.handler_offset, (clause.handler_offset, clause.handler_offset + clause.handler_length)) {
+                    return self.il_handle_exception(ctx, frame_index, region, e);  // finally中抛出了新的异常，替换掉当前的异常
+                }
+                self.stack.truncate(ctx.call_stack[frame_index].stack_base);
+            }
+        }
+        ctx.call_stack[frame_index].unwind_range = Some(region);
+        Err(e)
+    }
+
+    /// 第一遍：从栈顶开始寻找能处理异常的catch或者filter，返回(frame index, clause index)
+    fn find_exception_handler(&mut self, ctx: &mut Context, object: usize) -> Result<Option<(usize, usize)>, RuntimeError> {
+        for frame_index in (ctx.filter_floor..ctx.call_stack.len()).rev() {
+            let assembly_index = ctx.call_stack[frame_index].assembly_index;
+            let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+            let method = &assembly.methods[ctx.call_stack[frame_index].method_index];
+            let offset = ctx.call_stack[frame_index].offset;
+            let generic = Rc::clone(&ctx.call_stack[frame_index].generic_context);
+            for (clause_index, clause) in method.exception_clauses.iter().enumerate() {
+                if !clause.try_contains(offset) {
+                    continue;
+                }
+                let handled = match clause.kind {
+                    ExceptionClauseKind::Catch(class_token) => self.is_assignable_to(&ILType::Ref(ILRefType::Object(object)), assembly_index, class_token, &generic)?,
+                    ExceptionClauseKind::Filter(_) => self.il_run_filter(ctx, frame_index, clause, object)?,
+                    _ => false,
+                };
+                if handled {
+                    return Ok(Some((frame_index, clause_index)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// 在frame_index对应的方法中执行filter代码，filter中抛出的异常视为返回false
+    fn il_run_filter(&mut self, ctx: &mut Context, frame_index: usize, clause: &ExceptionClause, object: usize) -> Result<bool, RuntimeError> {
+        let (filter_start, filter_end) = clause.filter_range().unwrap();
+        let saved_assembly = Rc::clone(&ctx.assembly);
+        let saved_assembly_index = ctx.assembly_index;
+        let saved_filter_floor = ctx.filter_floor;
+        let saved_offset = ctx.call_stack[frame_index].offset;
+        let saved_stack_len = self.stack.len();
+
+        ctx.assembly_index = ctx.call_stack[frame_index].assembly_index;
+        ctx.assembly = Rc::clone(self.assemblies.index_get(ctx.assembly_index).unwrap());
+        ctx.filter_floor = ctx.call_stack.len();
+        self.stack.push_back(ILType::Ref(ILRefType::Object(object)));
+        let result = self.il_run(ctx, frame_index, filter_start, (filter_start, filter_end));
+
+        ctx.assembly = saved_assembly;
+        ctx.assembly_index = saved_assembly_index;
+        ctx.filter_floor = saved_filter_floor;
+        ctx.call_stack[frame_index].offset = saved_offset;
+        self.stack.truncate(saved_stack_len);
+        match result {
+            Ok(BlockExit::EndFilter(handled)) => Ok(handled),
+            Ok(_) => Err(RuntimeErrorKind::BadImage(String::from("Filter block must end with endfilter")).into()),
+            Err(RuntimeError { kind: RuntimeErrorKind::Exception { .. }, .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 如果错误是运行时产生的异常（例如空引用），那么创建对应的托管异常对象并抛出，否则原样返回
+    fn raise_managed_exception(&mut self, ctx: &mut Context, e: RuntimeError) -> RuntimeError {
+        let (type_name, message) = match e.kind.to_managed_exception() {
+            Some(exception) => exception,
+            None => return e,
+        };
+        match self.new_cor_lib_exception(type_name, message) {
+            Ok(object) => self.il_throw(ctx, object),
+            Err(_) => e,  // 无法加载核心库时，保留原来的错误
+        }
+    }
+
+    /// 返回核心库在assemblies中的index，尚未加载时按名称加载，不检查版本
+    fn get_cor_lib_index(&mut self) -> Result<usize, RuntimeError> {
+        for name in Self::COR_LIB_NAMES.iter() {
+            if let Some(index) = self.assemblies.key_get_index(&name.to_string()) {
+                return Ok(index);
+            }
+        }
+        let assembly_name = AssemblyName {
+            major_version: 0,
+            minor_version: 0,
+            build_number: 0,
+            revision_number: 0,
+            flags: 0,
+            public_key_or_token: Vec::new(),
+            name: Self::COR_LIB_NAMES[0].to_string(),
+            culture: String::new(),
+        };
+        self.load_assembly(&assembly_name, BindingPolicy::IgnoreVersion).map_err(|e| RuntimeErrorKind::UnresolvedAssembly(e.to_string()))?;
+        Ok(self.assemblies.len() - 1)
+    }
+
+    /// 创建一个核心库中的异常对象，不调用构造函数，直接设置_message字段
+    fn new_cor_lib_exception(&mut self, type_name: &str, message: String) -> Result<usize, RuntimeError> {
+        let assembly_index = self.get_cor_lib_index()?;
+        let assembly = Rc::clone(self.assemblies.index_get(assembly_index).unwrap());
+        let type_def_index = assembly.type_defs.key_get_index(&type_name.to_string())
+            .ok_or_else(|| RuntimeErrorKind::UnresolvedType(type_name.to_string()))?;
+        self.il_new_obj(&RuntimeType::Type((assembly_index, type_def_index), Vec::new()))?;
+        let object = self.pop()?.get_ref().ok_or(RuntimeErrorKind::NullReference)?;
+        if let Some(field_rid) = self.find_instance_field(object, "_message") {
+            self.strings.push(message);
+            self.objects[object].set_field(field_rid, ILType::Ref(ILRefType::String(self.strings.len() - 1)));
+        }
+        Ok(object)
+    }
+
+    /// 沿继承链在object的类型中寻找实例字段，返回字段的rid，只查找和object类型位于同一Assembly的基类
+    fn find_instance_field(&self, object: usize, name: &str) -> Option<u32> {
+        let assembly = self.assemblies.index_get(self.objects[object].assembly_index).unwrap();
+        let mut type_token = self.objects[object].get_type();
+        while type_token >> 24 == 0x02 && type_token & 0x00FFFFFF != 0 {
+            let type_def = assembly.type_defs.index_get((type_token & 0x00FFFFFF) as usize - 1)?;
+            for rid in type_def.field_list.iter() {
+                let field = &assembly.fields[rid as usize - 1];
+                if !field.is_static() && field.name == name {
+                    return Some(rid);
+                }
+            }
+            type_token = type_def.extends;
+        }
+        None
+    }
+
+    /// 返回异常对象的类型全名和消息，用于输出未处理的异常
+    fn describe_exception(&self, object: usize) -> (String, String) {
+        let assembly = self.assemblies.index_get(self.objects[object].assembly_index).unwrap();
+        let type_token = self.objects[object].get_type();
+        let type_name = assembly.get_type_full_name(type_token).unwrap_or_else(|| format!("0x{:08X}", type_token));
+        let message = match self.find_instance_field(object, "_message").and_then(|rid| self.objects[object].get_field(rid)) {
+            Some(ILType::Ref(ILRefType::String(index))) => self.strings[*index].clone(),
+            _ => format!("Exception of type '{}' was thrown.", type_name),
+        };
+        (type_name, message)
+    }
+
+    /// 抛出托管异常：记录调用堆栈并完成第一遍搜索，返回的错误由il_run逐层处理
+    fn il_throw(&mut self, ctx: &mut Context, object: usize) -> RuntimeError {
+        let stack_trace = self.capture_stack_trace(ctx);
+        match self.find_exception_handler(ctx, object) {
+            Ok(handler) => RuntimeError {
+                kind: RuntimeErrorKind::Exception { object, handler },
+                stack_trace,
+            },
+            Err(e) => e,
+        }
+    }
+
+    /// leave指令：清空求值栈，依次执行被跳出的try块对应的finally，offset和target都相对于方法体起始
+    fn il_leave(&mut self, ctx: &mut Context, frame_index: usize, offset: usize, target: usize) -> Result<(), RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(ctx.call_stack[frame_index].assembly_index).unwrap());
+        let method = &assembly.methods[ctx.call_stack[frame_index].method_index];
+        self.stack.truncate(ctx.call_stack[frame_index].stack_base);
+        for (clause_index, clause) in method.exception_clauses.iter().enumerate() {
+            if clause.handler_contains(offset) && !clause.handler_contains(target) {  // 离开catch块
+                ctx.call_stack[frame_index].caught_exceptions.remove(&clause_index);
+            }
+            if clause.kind == ExceptionClauseKind::Finally && clause.try_contains(offset) && !clause.try_contains(target) {
+                match self.il_run(ctx, frame_index, clause.handler_offset, (clause.handler_offset, clause.handler_offset + clause.handler_length))? {
+                    BlockExit::EndFinally => {},
+                    _ => return Err(RuntimeErrorKind::BadImage(String::from("Finally block must end with endfinally")).into()),
+                }
+                ctx.call_stack[frame_index].offset = offset;
+            }
+        }
+        Ok(())
+    }
+
+    /// 执行一条指令，rip指向下一条指令，返回Some表示当前代码块执行结束
+    fn il_step(&mut self, ctx: &mut Context, frame_index: usize, rip: &mut usize) -> Result<Option<BlockExit>, RuntimeError> {
+        let assembly = Rc::clone(self.assemblies.index_get(ctx.call_stack[frame_index].assembly_index).unwrap());
+        let method = &assembly.methods[ctx.call_stack[frame_index].method_index];
         let reader = &assembly.reader;
-        let mut rip = method.code_position;  // 当前函数指针
+        let op_offset = *rip - method.code_position;
+        ctx.call_stack[frame_index].offset = op_offset;
+        let generic = Rc::clone(&ctx.call_stack[frame_index].generic_context);
+        let constrained = ctx.call_stack[frame_index].constrained.take();  // 前缀只作用于下一条指令
+        let readonly = std::mem::take(&mut ctx.call_stack[frame_index].readonly);
 
-        let mut params = VecDeque::new();
-        for _ in 0..param_count {
-            params.push_front(self.stack.pop_back().unwrap());  // 逆向出栈，获取参数
+        macro_rules! unsupported {
+            ($op_code:expr) => {
+                return Err(RuntimeErrorKind::UnsupportedOpCode {
+                    op_code: $op_code.map(|op| format!("{:?}", op)).unwrap_or_default(),
+                    method: method.to_string(&assembly),
+                    offset: op_offset,
+                }.into())
+            };
         }
 
-        let mut locals = self.get_method_locals(ctx, method);
-        'root: loop {  // 禁止使用return脱离循环
-            let op = reader.read_u8_immut(&mut rip).unwrap();
-            match FromPrimitive::from_u8(op) {
-                Some(OpCode::Nop) => {},
-                Some(OpCode::Break) => {
-                    println!("break");
-                },
-                Some(OpCode::Ldarg0) => {
-                    self.stack.push_back(params[0].clone());
-                },
-                Some(OpCode::Ldarg1) => {
-                    self.stack.push_back(params[1].clone());
-                },
-                Some(OpCode::Ldarg2) => {
-                    self.stack.push_back(params[2].clone());
-                },
-                Some(OpCode::Ldarg3) => {
-                    self.stack.push_back(params[3].clone());
-                },
-                Some(OpCode::Ldloc0) => {
-                    self.stack.push_back(locals[0].clone());
-                },
-                Some(OpCode::Ldloc1) => {
-                    self.stack.push_back(locals[1].clone());
-                },
-                Some(OpCode::Ldloc2) => {
-                    self.stack.push_back(locals[2].clone());
-                },
-                Some(OpCode::Ldloc3) => {
-                    self.stack.push_back(locals[3].clone());
-                },
-                Some(OpCode::Stloc0) => {
-                    locals[0] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Stloc1) => {
-                    locals[1] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Stloc2) => {
-                    locals[2] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Stloc3) => {
-                    locals[3] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Ldargs) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    self.stack.push_back(params[index as usize].clone());
-                },
-                Some(OpCode::Ldargas) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Ptr(ILPtr::Param((ctx.stack_id, index as usize))));
-                },
-                Some(OpCode::Stargs) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    params[index as usize] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Ldlocs) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    self.stack.push_back(locals[index as usize].clone());
-                },
-                Some(OpCode::Ldlocas) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Ptr(ILPtr::Local((ctx.stack_id, index as usize))));
-                },
-                Some(OpCode::Stlocs) => {
-                    let index = reader.read_u8_immut(&mut rip).unwrap();
-                    locals[index as usize] = self.stack.pop_back().unwrap();
-                },
-                Some(OpCode::Ldnull) => {
-                    self.stack.push_back(ILType::Ref(ILRefType::Null));
-                },
-                Some(OpCode::Ldci4m1) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(-1)));
-                },
-                Some(OpCode::Ldci40) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(0)));
-                },
-                Some(OpCode::Ldci41) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(1)));
-                },
-                Some(OpCode::Ldci42) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(2)));
-                },
-                Some(OpCode::Ldci43) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(3)));
-                },
-                Some(OpCode::Ldci44) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(4)));
-                },
-                Some(OpCode::Ldci45) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(5)));
-                },
-                Some(OpCode::Ldci46) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(6)));
-                },
-                Some(OpCode::Ldci47) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(7)));
-                },
-                Some(OpCode::Ldci48) => {
-                    self.stack.push_back(ILType::Val(ILValType::Int32(8)));
-                },
-                Some(OpCode::Ldci4s) => {
-                    let val = reader.read_u8_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Val(ILValType::Int32(val as i32)));
-                },
-                Some(OpCode::Ldci4) => {
-                    let val = reader.read_u32_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Val(ILValType::Int32(val as i32)));
-                },
-                Some(OpCode::Ldci8) => {
-                    let val = reader.read_u64_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Val(ILValType::Int64(val as i64)));
-                },
-                Some(OpCode::Ldcr4) => {
-                    let val = reader.read_f32_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Val(ILValType::Single(val)));
-                },
-                Some(OpCode::Ldcr8) => {
-                    let val = reader.read_f64_immut(&mut rip).unwrap();
-                    self.stack.push_back(ILType::Val(ILValType::Double(val)));
-                },
-                Some(OpCode::Dup) => {
-                    self.stack.push_back(self.stack.back().unwrap().clone());
-                },
-                Some(OpCode::Pop) => {
-                    self.stack.pop_back();
-                },
-                Some(OpCode::Jmp) => {
-                    assert_eq!(assembly.params.len(), 0);
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    self.il_call(ctx, token);
-                    break;
-                },
-                Some(OpCode::Call) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    self.il_call(ctx, token);
-                },
-                Some(OpCode::Calli) => {
-                    todo!();
-                },
-                Some(OpCode::Ret) => {
-                    break;
-                },
-                Some(OpCode::Brs) => {
-                    let target = reader.read_u8_immut(&mut rip).unwrap() as i8;
-                    rip = (rip as isize + target as isize) as usize;
-                },
-                Some(OpCode::Brfalses) => {
-                    let target = reader.read_u8_immut(&mut rip).unwrap() as i8;
-                    let val = self.stack.pop_back().unwrap();
-                    if val.is_false_type() {
-                        rip = (rip as isize + target as isize) as usize;
-                    }
-                },
-                Some(OpCode::Brtrues) => {
-                    let target = reader.read_u8_immut(&mut rip).unwrap() as i8;
-                    let val = self.stack.pop_back().unwrap();
-                    if !val.is_false_type() {
-                        rip = (rip as isize + target as isize) as usize;
-                    }
-                },
-                Some(OpCode::Beqs) => {
-                    todo!();
-                },
-                Some(OpCode::Bges) => {
-                    todo!();
-                },
-                Some(OpCode::Bgts) => {
-                    todo!();
-                },
-                Some(OpCode::Bles) => {
-                    todo!();
-                },
-                Some(OpCode::Blts) => {
-                    todo!();
-                },
-                Some(OpCode::Bneuns) => {
-                    todo!();
-                },
-                Some(OpCode::Bgeuns) => {
-                    todo!();
-                },
-                Some(OpCode::Bgtuns) => {
-                    todo!();
-                },
-                Some(OpCode::Bleuns) => {
-                    todo!();
-                },
-                Some(OpCode::Bltuns) => {
-                    todo!();
-                },
-                Some(OpCode::Br) => {
-                    todo!();
-                },
-                Some(OpCode::Brfalse) => {
-                    todo!();
-                },
-                Some(OpCode::Brtrue) => {
-                    todo!();
-                },
-                Some(OpCode::Beq) => {
-                    todo!();
-                },
-                Some(OpCode::Bge) => {
-                    todo!();
-                },
-                Some(OpCode::Bgt) => {
-                    todo!();
-                },
-                Some(OpCode::Ble) => {
-                    todo!();
-                },
-                Some(OpCode::Blt) => {
-                    todo!();
-                },
-                Some(OpCode::Bneun) => {
-                    todo!();
-                },
-                Some(OpCode::Bgeun) => {
-                    todo!();
-                },
-                Some(OpCode::Bgtun) => {
-                    todo!();
-                },
-                Some(OpCode::Bleun) => {
-                    todo!();
-                },
-                Some(OpCode::Bltun) => {
-                    todo!();
-                },
-                Some(OpCode::Switch) => {
-                    let n = reader.read_u32_immut(&mut rip).unwrap();
-                    let val = self.stack.pop_back().unwrap();
-                    if let ILType::Val(val) = val {
-                        let val = val.to_u32();
-                        if val < n {
-                            rip += val as usize * 4;
-                            let target = reader.read_u32_immut(&mut rip).unwrap() as i32;
-                            rip = (rip as isize + target as isize) as usize;
-                        } else {
-                            rip += n as usize * 4;
-                        }
+        /// 当前帧的第index个参数（params）或局部变量（locals），下标越界说明IL损坏
+        macro_rules! frame_slot {
+            ($slots:ident, $index:expr) => {{
+                let index = $index as usize;
+                match ctx.call_stack[frame_index].$slots.get_mut(index) {
+                    Some(slot) => slot,
+                    None => return Err(RuntimeErrorKind::BadImage(format!("{} IL_{:04X}: {} index {} is out of range",
+                        method.to_string(&assembly), op_offset, stringify!($slots), index)).into()),
+                }
+            }};
+        }
+
+        /// 读取short（int8）或long（int32）形式的跳转偏移，条件成立时跳转
+        macro_rules! branch {
+            (short, $cond:expr) => {{
+                let target = reader.read_u8_immut(rip)? as i8 as isize;
+                if $cond {
+                    *rip = (*rip as isize + target) as usize;
+                }
+            }};
+            (long, $cond:expr) => {{
+                let target = reader.read_u32_immut(rip)? as i32 as isize;
+                if $cond {
+                    *rip = (*rip as isize + target) as usize;
+                }
+            }};
+        }
+
+        let op = reader.read_u8_immut(rip)?;
+        let op_code: Option<OpCode> = FromPrimitive::from_u8(op);
+        match op_code {
+            Some(OpCode::Nop) => {},
+            Some(OpCode::Break) => {
+                println!("break");
+            },
+            Some(OpCode::Ldarg0) => {
+                self.stack.push_back(frame_slot!(params, 0).to_stack_value());
+            },
+            Some(OpCode::Ldarg1) => {
+                self.stack.push_back(frame_slot!(params, 1).to_stack_value());
+            },
+            Some(OpCode::Ldarg2) => {
+                self.stack.push_back(frame_slot!(params, 2).to_stack_value());
+            },
+            Some(OpCode::Ldarg3) => {
+                self.stack.push_back(frame_slot!(params, 3).to_stack_value());
+            },
+            Some(OpCode::Ldloc0) => {
+                self.stack.push_back(frame_slot!(locals, 0).to_stack_value());
+            },
+            Some(OpCode::Ldloc1) => {
+                self.stack.push_back(frame_slot!(locals, 1).to_stack_value());
+            },
+            Some(OpCode::Ldloc2) => {
+                self.stack.push_back(frame_slot!(locals, 2).to_stack_value());
+            },
+            Some(OpCode::Ldloc3) => {
+                self.stack.push_back(frame_slot!(locals, 3).to_stack_value());
+            },
+            Some(OpCode::Stloc0) => {
+                let value = self.pop()?;
+                let local = frame_slot!(locals, 0);
+                *local = value.coerce_to(local);
+            },
+            Some(OpCode::Stloc1) => {
+                let value = self.pop()?;
+                let local = frame_slot!(locals, 1);
+                *local = value.coerce_to(local);
+            },
+            Some(OpCode::Stloc2) => {
+                let value = self.pop()?;
+                let local = frame_slot!(locals, 2);
+                *local = value.coerce_to(local);
+            },
+            Some(OpCode::Stloc3) => {
+                let value = self.pop()?;
+                let local = frame_slot!(locals, 3);
+                *local = value.coerce_to(local);
+            },
+            Some(OpCode::Ldargs) => {
+                let index = reader.read_u8_immut(rip)?;
+                self.stack.push_back(frame_slot!(params, index).to_stack_value());
+            },
+            Some(OpCode::Ldargas) => {
+                let index = reader.read_u8_immut(rip)?;
+                frame_slot!(params, index);  // 只检查下标
+                self.stack.push_back(ILType::Ptr(ILPtr::Param((ctx.call_stack[frame_index].stack_id, index as usize))));
+            },
+            Some(OpCode::Stargs) => {
+                let index = reader.read_u8_immut(rip)?;
+                let value = self.pop()?;
+                let param = frame_slot!(params, index);
+                *param = value.coerce_to(param);
+            },
+            Some(OpCode::Ldlocs) => {
+                let index = reader.read_u8_immut(rip)?;
+                self.stack.push_back(frame_slot!(locals, index).to_stack_value());
+            },
+            Some(OpCode::Ldlocas) => {
+                let index = reader.read_u8_immut(rip)?;
+                frame_slot!(locals, index);  // 只检查下标
+                self.stack.push_back(ILType::Ptr(ILPtr::Local((ctx.call_stack[frame_index].stack_id, index as usize))));
+            },
+            Some(OpCode::Stlocs) => {
+                let index = reader.read_u8_immut(rip)?;
+                let value = self.pop()?;
+                let local = frame_slot!(locals, index);
+                *local = value.coerce_to(local);
+            },
+            Some(OpCode::Ldnull) => {
+                self.stack.push_back(ILType::Ref(ILRefType::Null));
+            },
+            Some(OpCode::Ldci4m1) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(-1)));
+            },
+            Some(OpCode::Ldci40) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(0)));
+            },
+            Some(OpCode::Ldci41) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(1)));
+            },
+            Some(OpCode::Ldci42) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(2)));
+            },
+            Some(OpCode::Ldci43) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(3)));
+            },
+            Some(OpCode::Ldci44) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(4)));
+            },
+            Some(OpCode::Ldci45) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(5)));
+            },
+            Some(OpCode::Ldci46) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(6)));
+            },
+            Some(OpCode::Ldci47) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(7)));
+            },
+            Some(OpCode::Ldci48) => {
+                self.stack.push_back(ILType::Val(ILValType::Int32(8)));
+            },
+            Some(OpCode::Ldci4s) => {
+                let val = reader.read_u8_immut(rip)?;
+                self.stack.push_back(ILType::Val(ILValType::Int32(val as i32)));
+            },
+            Some(OpCode::Ldci4) => {
+                let val = reader.read_u32_immut(rip)?;
+                self.stack.push_back(ILType::Val(ILValType::Int32(val as i32)));
+            },
+            Some(OpCode::Ldci8) => {
+                let val = reader.read_u64_immut(rip)?;
+                self.stack.push_back(ILType::Val(ILValType::Int64(val as i64)));
+            },
+            Some(OpCode::Ldcr4) => {
+                let val = reader.read_f32_immut(rip)?;
+                self.stack.push_back(ILType::Val(ILValType::Single(val)));
+            },
+            Some(OpCode::Ldcr8) => {
+                let val = reader.read_f64_immut(rip)?;
+                self.stack.push_back(ILType::Val(ILValType::Double(val)));
+            },
+            Some(OpCode::Dup) => {
+                let value = self.stack.back().ok_or(RuntimeErrorKind::StackUnderflow)?.clone();
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Pop) => {
+                self.pop()?;
+            },
+            Some(OpCode::Jmp) => {  // 以当前的参数调用目标方法，然后直接返回
+                let token = reader.read_u32_immut(rip)?;
+                let params = ctx.call_stack[frame_index].params.clone();
+                self.stack.truncate(ctx.call_stack[frame_index].stack_base);
+                self.stack.extend(params);
+                self.il_call(ctx, token, &generic)?;
+                return Ok(Some(BlockExit::Ret));
+            },
+            Some(OpCode::Call) => {
+                let token = reader.read_u32_immut(rip)?;
+                if !self.il_array_method(ctx, token, false, &generic)? && !self.il_intrinsic(ctx, token, &generic)? {
+                    self.il_call(ctx, token, &generic)?;
+                }
+            },
+            Some(OpCode::Calli) => {
+                unsupported!(op_code);
+            },
+            Some(OpCode::Ret) => {
+                return Ok(Some(BlockExit::Ret));
+            },
+            Some(OpCode::Brs) => {
+                branch!(short, true);
+            },
+            Some(OpCode::Brfalses) => {
+                branch!(short, self.pop()?.is_false_type());
+            },
+            Some(OpCode::Brtrues) => {
+                branch!(short, !self.pop()?.is_false_type());
+            },
+            Some(OpCode::Beqs) => {
+                branch!(short, self.pop_compare(false)? == Some(Ordering::Equal));
+            },
+            Some(OpCode::Bges) => {
+                branch!(short, matches!(self.pop_compare(false)?, Some(Ordering::Greater | Ordering::Equal)));
+            },
+            Some(OpCode::Bgts) => {
+                branch!(short, self.pop_compare(false)? == Some(Ordering::Greater));
+            },
+            Some(OpCode::Bles) => {
+                branch!(short, matches!(self.pop_compare(false)?, Some(Ordering::Less | Ordering::Equal)));
+            },
+            Some(OpCode::Blts) => {
+                branch!(short, self.pop_compare(false)? == Some(Ordering::Less));
+            },
+            Some(OpCode::Bneuns) => {
+                branch!(short, self.pop_compare(true)? != Some(Ordering::Equal));
+            },
+            Some(OpCode::Bgeuns) => {
+                branch!(short, !matches!(self.pop_compare(true)?, Some(Ordering::Less)));
+            },
+            Some(OpCode::Bgtuns) => {
+                branch!(short, !matches!(self.pop_compare(true)?, Some(Ordering::Less | Ordering::Equal)));
+            },
+            Some(OpCode::Bleuns) => {
+                branch!(short, !matches!(self.pop_compare(true)?, Some(Ordering::Greater)));
+            },
+            Some(OpCode::Bltuns) => {
+                branch!(short, !matches!(self.pop_compare(true)?, Some(Ordering::Greater | Ordering::Equal)));
+            },
+            Some(OpCode::Br) => {
+                branch!(long, true);
+            },
+            Some(OpCode::Brfalse) => {
+                branch!(long, self.pop()?.is_false_type());
+            },
+            Some(OpCode::Brtrue) => {
+                branch!(long, !self.pop()?.is_false_type());
+            },
+            Some(OpCode::Beq) => {
+                branch!(long, self.pop_compare(false)? == Some(Ordering::Equal));
+            },
+            Some(OpCode::Bge) => {
+                branch!(long, matches!(self.pop_compare(false)?, Some(Ordering::Greater | Ordering::Equal)));
+            },
+            Some(OpCode::Bgt) => {
+                branch!(long, self.pop_compare(false)? == Some(Ordering::Greater));
+            },
+            Some(OpCode::Ble) => {
+                branch!(long, matches!(self.pop_compare(false)?, Some(Ordering::Less | Ordering::Equal)));
+            },
+            Some(OpCode::Blt) => {
+                branch!(long, self.pop_compare(false)? == Some(Ordering::Less));
+            },
+            Some(OpCode::Bneun) => {
+                branch!(long, self.pop_compare(true)? != Some(Ordering::Equal));
+            },
+            Some(OpCode::Bgeun) => {
+                branch!(long, !matches!(self.pop_compare(true)?, Some(Ordering::Less)));
+            },
+            Some(OpCode::Bgtun) => {
+                branch!(long, !matches!(self.pop_compare(true)?, Some(Ordering::Less | Ordering::Equal)));
+            },
+            Some(OpCode::Bleun) => {
+                branch!(long, !matches!(self.pop_compare(true)?, Some(Ordering::Greater)));
+            },
+            Some(OpCode::Bltun) => {
+                branch!(long, !matches!(self.pop_compare(true)?, Some(Ordering::Greater | Ordering::Equal)));
+            },
+            Some(OpCode::Switch) => {
+                let n = reader.read_u32_immut(rip)?;
+                let val = self.pop()?;
+                if let ILType::Val(val) = val {
+                    let val = val.to_u32();
+                    if val < n {
+                        *rip += val as usize * 4;
+                        let target = reader.read_u32_immut(rip)? as i32;
+                        *rip = (*rip as isize + target as isize) as usize;
                     } else {
-                        panic!("switch value must be a val");
-                    }
-                },
-                Some(OpCode::Ldindi1) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindu1) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindi2) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindu2) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindi4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindu4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindi8) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindi) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindr4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindr8) => {
-                    todo!();
-                },
-                Some(OpCode::Ldindref) => {
-                    todo!();
-                },
-                Some(OpCode::Stindref) => {
-                    todo!();
-                },
-                Some(OpCode::Stindi1) => {
-                    todo!();
-                },
-                Some(OpCode::Stindi2) => {
-                    todo!();
-                },
-                Some(OpCode::Stindi4) => {
-                    todo!();
-                },
-                Some(OpCode::Stindi8) => {
-                    todo!();
-                },
-                Some(OpCode::Stindr4) => {
-                    todo!();
-                },
-                Some(OpCode::Stindr8) => {
-                    todo!();
-                },
-                Some(OpCode::Add) => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    self.stack.push_back(a + b);
-                },
-                Some(OpCode::Sub) => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    self.stack.push_back(a - b);
-                },
-                Some(OpCode::Mul) => {
-                    // let a = self.stack.pop_back().unwrap();
-                    // let b = self.stack.pop_back().unwrap();
-                    // self.stack.push_back(a * b);
-                    todo!();
-                },
-                Some(OpCode::Div) => {
-                    // let a = self.stack.pop_back().unwrap();
-                    // let b = self.stack.pop_back().unwrap();
-                    // self.stack.push_back(a / b);
-                    todo!();
-                },
-                Some(OpCode::Divun) => {
-                    todo!();
-                },
-                Some(OpCode::Rem) => {
-                    todo!();
-                },
-                Some(OpCode::Remun) => {
-                    todo!();
-                },
-                Some(OpCode::And) => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    self.stack.push_back(a & b);
-                },
-                Some(OpCode::Or) => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    self.stack.push_back(a | b);
-                },
-                Some(OpCode::Xor) => {
-                    let b = self.stack.pop_back().unwrap();
-                    let a = self.stack.pop_back().unwrap();
-                    self.stack.push_back(a ^ b);
-                },
-                Some(OpCode::Shl) => {
-                    todo!();
-                },
-                Some(OpCode::Shr) => {
-                    todo!();
-                },
-                Some(OpCode::Shrun) => {
-                    todo!();
-                },
-                Some(OpCode::Neg) => {
-                    todo!();
-                },
-                Some(OpCode::Not) => {
-                    todo!();
-                },
-                Some(OpCode::Convi1) => {
-                    todo!();
-                },
-                Some(OpCode::Convi2) => {
-                    todo!();
-                },
-                Some(OpCode::Convi4) => {
-                    todo!();
-                },
-                Some(OpCode::Convi8) => {
-                    todo!();
-                },
-                Some(OpCode::Convr4) => {
-                    todo!();
-                },
-                Some(OpCode::Convr8) => {
-                    todo!();
-                },
-                Some(OpCode::Convu4) => {
-                    todo!();
-                },
-                Some(OpCode::Convu8) => {
-                    todo!();
-                },
-                Some(OpCode::Callvirt) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    self.il_call(ctx, token);
-                },
-                Some(OpCode::Cpobj) => {
-                    todo!();
-                },
-                Some(OpCode::Ldobj) => {
-                    todo!();
-                },
-                Some(OpCode::Ldstr) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let str = assembly.metadata.get_us_string(token).unwrap();
-                    self.il_new_string(str);
-                },
-                Some(OpCode::Newobj) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let method_index = self.get_method_index(ctx, token);
-                    let type_token = ctx.assembly.methods[method_index].owner_type + 0x02000001;
-                    self.il_new_obj(ctx, type_token);  // 根据.ctor找到类，new出来推送到栈上，作为.ctor的this
-                    self.stack.push_back(self.stack.back().unwrap().clone());
-                    self.il_call(ctx, token);
-                },
-                Some(OpCode::Castclass) => {
-                    let type_token = reader.read_u32_immut(&mut rip).unwrap();
-                    let obj = self.stack.pop_back().unwrap();
-                    match obj {
-                        ILType::Ref(ILRefType::Null) => {
-                            self.stack.push_back(ILType::Ref(ILRefType::Null));
-                        },
-                        ILType::Ref(ILRefType::Object(index)) => {
-                            let obj_type_token = self.objects[index].get_type();
-                            let mut temp_ctx = ctx.make_temp();
-                            let obj_type_index = self.resolve_type_def_or_ref(&mut temp_ctx, obj_type_token);
-                            let obj_type = ctx.assembly.type_defs.index_get(obj_type_index).expect("TypeLoadException");
-                            let mut extends = obj_type.extends;
-                            while extends != 0 {
-                                let extends_index = self.resolve_type_def_or_ref(&mut temp_ctx, obj_type_token);
-                                let extends_type = ctx.assembly.type_defs.index_get(extends_index).expect("TypeLoadException");
-                                if extends_type.token == type_token {
-                                    self.objects[index].type_token = type_token;
-                                    self.stack.push_back(ILType::Ref(ILRefType::Object(index)));
-                                    break 'root;
-                                }
-                                extends = extends_type.extends;
-                            }
-                            panic!("InvalidCastException");
-                        },
-                        ILType::Ref(ILRefType::String(index)) => {
-                            self.stack.push_back(ILType::Ref(ILRefType::String(index)));
-                        },
-                        _ => panic!("InvalidCastException"),
+                        *rip += n as usize * 4;
                     }
-                },
-                Some(OpCode::Isinst) => {
-                    todo!();
-                },
-                Some(OpCode::Convrun) => {
-                    todo!();
-                },
-                Some(OpCode::Unbox) => {
-                    todo!();
-                },
-                Some(OpCode::Throw) => {
-                    todo!();
-                },
-                Some(OpCode::Ldfld) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let rid = token - 0x04000000;
-                    let this = self.stack.pop_back().unwrap();
-                    match this {
-                        ILType::Ref(ref_type) => {
-                            match ref_type {
-                                ILRefType::Null => {
-                                    panic!("Null reference exception.");
-                                },
-                                ILRefType::String(_) => {
-                                    todo!();
-                                },
-                                ILRefType::Object(index) => {
-                                    let obj = &self.objects[index];
-                                    self.stack.push_back(obj.get_field(rid).unwrap().clone());
-                                },
-                            }
-                        },
-                        _ => {
-                            panic!("Invalid this reference.");
+                } else {
+                    return Err(RuntimeErrorKind::TypeMismatch(String::from("switch value must be a val")).into());
+                }
+            },
+            Some(OpCode::Ldindi1) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I1)?);
+            },
+            Some(OpCode::Ldindu1) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U1)?);
+            },
+            Some(OpCode::Ldindi2) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I2)?);
+            },
+            Some(OpCode::Ldindu2) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U2)?);
+            },
+            Some(OpCode::Ldindi4) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I4)?);
+            },
+            Some(OpCode::Ldindu4) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U4)?);
+            },
+            Some(OpCode::Ldindi8) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I8)?);
+            },
+            Some(OpCode::Ldindi) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I)?);
+            },
+            Some(OpCode::Ldindr4) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv_r4()?);
+            },
+            Some(OpCode::Ldindr8) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv_r8()?);
+            },
+            Some(OpCode::Ldindref) => {
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Stindref) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value)?;
+            },
+            Some(OpCode::Stindi1) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I1)?)?;
+            },
+            Some(OpCode::Stindi2) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I2)?)?;
+            },
+            Some(OpCode::Stindi4) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I4)?)?;
+            },
+            Some(OpCode::Stindi8) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I8)?)?;
+            },
+            Some(OpCode::Stindr4) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv_r4()?)?;
+            },
+            Some(OpCode::Stindr8) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv_r8()?)?;
+            },
+            Some(OpCode::Add) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a + b)?);
+            },
+            Some(OpCode::Sub) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a - b)?);
+            },
+            Some(OpCode::Mul) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a * b)?);
+            },
+            Some(OpCode::Div) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a / b)?);
+            },
+            Some(OpCode::Divun) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.div_un(b)?);
+            },
+            Some(OpCode::Rem) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a % b)?);
+            },
+            Some(OpCode::Remun) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.rem_un(b)?);
+            },
+            Some(OpCode::And) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a & b)?);
+            },
+            Some(OpCode::Or) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a | b)?);
+            },
+            Some(OpCode::Xor) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a ^ b)?);
+            },
+            Some(OpCode::Shl) => {
+                let amount = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a << amount)?);
+            },
+            Some(OpCode::Shr) => {
+                let amount = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back((a >> amount)?);
+            },
+            Some(OpCode::Shrun) => {
+                let amount = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.shr_un(amount)?);
+            },
+            Some(OpCode::Neg) => {
+                let a = self.pop()?;
+                self.stack.push_back((-a)?);
+            },
+            Some(OpCode::Not) => {
+                let a = self.pop()?;
+                self.stack.push_back((!a)?);
+            },
+            Some(OpCode::Convi1) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::I1)?);
+            },
+            Some(OpCode::Convi2) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::I2)?);
+            },
+            Some(OpCode::Convi4) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::I4)?);
+            },
+            Some(OpCode::Convi8) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::I8)?);
+            },
+            Some(OpCode::Convr4) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_r4()?);
+            },
+            Some(OpCode::Convr8) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_r8()?);
+            },
+            Some(OpCode::Convu4) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::U4)?);
+            },
+            Some(OpCode::Convu8) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::U8)?);
+            },
+            Some(OpCode::Callvirt) => {
+                let token = reader.read_u32_immut(rip)?;
+                if let Some(constrained) = constrained {
+                    self.il_constrained_callvirt(ctx, token, &constrained, &generic)?;
+                } else if !self.il_array_method(ctx, token, false, &generic)? && !self.il_intrinsic(ctx, token, &generic)? {
+                    self.il_callvirt(ctx, token, &generic)?;
+                }
+            },
+            Some(OpCode::Cpobj) => {
+                reader.read_u32_immut(rip)?;
+                let src = self.pop_ptr()?;
+                let dest = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, src)?;
+                self.ptr_store(ctx, dest, value)?;
+            },
+            Some(OpCode::Ldobj) => {
+                reader.read_u32_immut(rip)?;  // 值类型的复制和基元类型相同
+                let ptr = self.pop_ptr()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Ldstr) => {
+                let token = reader.read_u32_immut(rip)?;
+                let str = assembly.metadata.get_us_string(token).map_err(|_| RuntimeErrorKind::InvalidToken(token))?;
+                self.il_new_string(str);
+            },
+            Some(OpCode::Newobj) => {
+                let token = reader.read_u32_immut(rip)?;
+                if self.il_array_method(ctx, token, true, &generic)? {
+                    return Ok(None);
+                }
+                let method = self.resolve_method(ctx.assembly_index, token)?;
+                let callee_generic = self.resolve_method_context(ctx.assembly_index, token, &generic)?;
+                let owner_type = self.assemblies.index_get(method.0).unwrap().methods[method.1].owner_type as usize;
+                let owner = RuntimeType::Type((method.0, owner_type), callee_generic.type_args.clone());
+                self.il_construct(ctx, &owner, method, Rc::new(callee_generic))?;
+            },
+            Some(OpCode::Castclass) => {
+                let type_token = reader.read_u32_immut(rip)?;
+                let value = self.stack.back().ok_or(RuntimeErrorKind::StackUnderflow)?.clone();
+                if !matches!(value, ILType::Ref(_)) {
+                    return Err(RuntimeErrorKind::TypeMismatch(format!("castclass requires an object reference, found {:?}", value)).into());
+                }
+                let target = self.resolve_runtime_type(ctx.assembly_index, type_token, &generic)?;
+                if !self.is_value_assignable(&value, &target)? {
+                    let message = self.describe_invalid_cast(&value, &target)?;
+                    return Err(RuntimeErrorKind::InvalidCast(message).into());
+                }
+            },
+            Some(OpCode::Isinst) => {
+                let type_token = reader.read_u32_immut(rip)?;
+                let value = self.pop()?;
+                if !matches!(value, ILType::Ref(_)) {
+                    return Err(RuntimeErrorKind::TypeMismatch(format!("isinst requires an object reference, found {:?}", value)).into());
+                }
+                if self.is_assignable_to(&value, ctx.assembly_index, type_token, &generic)? {
+                    self.stack.push_back(value);
+                } else {
+                    self.stack.push_back(ILType::Ref(ILRefType::Null));
+                }
+            },
+            Some(OpCode::Convrun) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_r_un()?);
+            },
+            Some(OpCode::Unbox) => {
+                let token = reader.read_u32_immut(rip)?;
+                let object = self.pop()?.get_ref().ok_or(RuntimeErrorKind::NullReference)?;
+                let target = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                self.check_unbox(object, &target)?;
+                self.stack.push_back(ILType::Ptr(ILPtr::Boxed(object)));
+            },
+            Some(OpCode::Throw) => {
+                match self.pop()? {
+                    ILType::Ref(ILRefType::Object(object)) => return Err(self.il_throw(ctx, object)),
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("throw requires an object reference, found {:?}", value)).into()),
+                }
+            },
+            Some(OpCode::Ldfld) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), _) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                let rid = field_index as u32 + 1;
+                let value = match self.pop()? {
+                    ILType::Ref(ILRefType::Object(index)) => self.objects[index].get_field(rid).cloned(),
+                    ILType::Struct(value) => value.get_field(rid).cloned(),
+                    ILType::Ptr(ptr) => match self.ptr_slot(ctx, ptr)? {  // 指向值类型实例的指针，例如值类型方法中的this
+                        ILType::Struct(value) => value.get_field(rid).cloned(),
+                        value => return Err(RuntimeErrorKind::TypeMismatch(format!("ldfld through a pointer to {:?}", value)).into()),
+                    },
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    ILType::Ref(ILRefType::String(_)) | ILType::Ref(ILRefType::Array(_)) => unsupported!(op_code),
+                    _ => return Err(RuntimeErrorKind::TypeMismatch(String::from("field access requires an object reference")).into()),
+                };
+                let value = value.ok_or(RuntimeErrorKind::InvalidToken(token))?.to_stack_value();
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Ldflda) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), _) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                let rid = field_index as u32 + 1;
+                let ptr = match self.pop()? {
+                    ILType::Ref(ILRefType::Object(index)) => {
+                        if self.objects[index].get_field(rid).is_none() {
+                            return Err(RuntimeErrorKind::InvalidToken(token).into());
                         }
-                    }
-                },
-                Some(OpCode::Ldflda) => {
-                    todo!();
-                },
-                Some(OpCode::Stfld) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let value = self.stack.pop_back().unwrap();
-                    let this = self.stack.pop_back().unwrap();
-                    match this {
-                        ILType::Ref(ref_type) => {
-                            match ref_type {
-                                ILRefType::Null => {
-                                    panic!("Null reference exception.");
-                                },
-                                ILRefType::String(_) => {
-                                    todo!();
-                                },
-                                ILRefType::Object(index) => {
-                                    let obj = &mut self.objects[index];
-                                    obj.set_field(token, value);
-                                },
-                            }
+                        ILPtr::Field((index, rid))
+                    },
+                    ILType::Ptr(ptr) => {
+                        match self.ptr_slot(ctx, ptr.clone())? {
+                            ILType::Struct(value) if value.get_field(rid).is_some() => {},
+                            ILType::Struct(_) => return Err(RuntimeErrorKind::InvalidToken(token).into()),
+                            value => return Err(RuntimeErrorKind::TypeMismatch(format!("ldflda through a pointer to {:?}", value)).into()),
+                        }
+                        ILPtr::StructField(Box::new((ptr, rid)))
+                    },
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    _ => return Err(RuntimeErrorKind::TypeMismatch(String::from("field access requires an object reference")).into()),
+                };
+                self.stack.push_back(ILType::Ptr(ptr));
+            },
+            Some(OpCode::Stfld) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), _) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                let rid = field_index as u32 + 1;
+                let value = self.pop()?;
+                match self.pop()? {
+                    ILType::Ref(ILRefType::Object(index)) => {
+                        let field = self.objects[index].get_field_mut(rid).ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                        *field = value.coerce_to(field);
+                    },
+                    ILType::Ptr(ptr) => match self.ptr_slot(ctx, ptr)? {
+                        ILType::Struct(instance) => {
+                            let field = instance.get_field_mut(rid).ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                            *field = value.coerce_to(field);
                         },
-                        _ => {
-                            panic!("Invalid this reference.");
+                        instance => return Err(RuntimeErrorKind::TypeMismatch(format!("stfld through a pointer to {:?}", instance)).into()),
+                    },
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    ILType::Ref(ILRefType::String(_)) | ILType::Ref(ILRefType::Array(_)) => unsupported!(op_code),
+                    _ => return Err(RuntimeErrorKind::TypeMismatch(String::from("field access requires an object reference")).into()),
+                }
+            },
+            Some(OpCode::Ldsfld) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), owner) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                self.ensure_static_fields(ctx, &owner)?;
+                let field_value = self.static_fields.get(&owner).and_then(|fields| fields.get(&(0x04000001 + field_index as u32)))
+                    .ok_or(RuntimeErrorKind::InvalidToken(token))?.to_stack_value();
+                self.stack.push_back(field_value);
+            },
+            Some(OpCode::Ldsflda) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), owner) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                self.ensure_static_fields(ctx, &owner)?;
+                self.stack.push_back(ILType::Ptr(ILPtr::Static(Box::new((owner, 0x04000001 + field_index as u32)))));
+            },
+            Some(OpCode::Stsfld) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ((_, field_index), owner) = self.resolve_field(ctx.assembly_index, token, &generic)?;
+                self.ensure_static_fields(ctx, &owner)?;
+                let value = self.pop()?;
+                let field = self.static_fields.get_mut(&owner).and_then(|fields| fields.get_mut(&(0x04000001 + field_index as u32)))
+                    .ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                *field = value.coerce_to(field);
+            },
+            Some(OpCode::Stobj) => {
+                reader.read_u32_immut(rip)?;
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value)?;
+            },
+            Some(OpCode::Convovfi1un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I1, true)?);
+            },
+            Some(OpCode::Convovfi2un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I2, true)?);
+            },
+            Some(OpCode::Convovfi4un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I4, true)?);
+            },
+            Some(OpCode::Convovfi8un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I8, true)?);
+            },
+            Some(OpCode::Convovfu1un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U1, true)?);
+            },
+            Some(OpCode::Convovfu2un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U2, true)?);
+            },
+            Some(OpCode::Convovfu4un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U4, true)?);
+            },
+            Some(OpCode::Convovfu8un) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U8, true)?);
+            },
+            Some(OpCode::Convovfiun) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I, true)?);
+            },
+            Some(OpCode::Convovfuun) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U, true)?);
+            },
+            Some(OpCode::Box) => {
+                let token = reader.read_u32_immut(rip)?;
+                let value = self.pop()?;
+                let target = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                match self.get_runtime_type_default(&target)? {
+                    ILType::Ref(_) if matches!(value, ILType::Ref(_)) => self.stack.push_back(value),  // 引用类型的box不做任何事
+                    ILType::Struct(default) if self.is_nullable(&default) => {
+                        // Nullable<T>没有值时装箱为null，有值时装箱为T
+                        let instance = match &value {
+                            ILType::Struct(instance) => instance,
+                            _ => return Err(RuntimeErrorKind::TypeMismatch(format!("box Nullable<T> requires a Nullable<T> value, found {:?}", value)).into()),
+                        };
+                        let has_value = self.find_struct_field(instance, "hasValue").and_then(|rid| instance.get_field(rid)).is_some_and(|v| !v.is_false_type());
+                        let inner = self.find_struct_field(instance, "value").and_then(|rid| instance.get_field(rid)).cloned();
+                        match (has_value, inner, &target) {
+                            (false, _, _) => self.stack.push_back(ILType::Ref(ILRefType::Null)),
+                            (true, Some(inner), RuntimeType::Type(_, type_args)) if type_args.len() == 1 => self.il_box_obj(&type_args[0], inner)?,
+                            _ => self.il_box_obj(&target, value)?,
                         }
+                    },
+                    default => self.il_box_obj(&target, value.coerce_to(&default))?,
+                }
+            },
+            Some(OpCode::Newarr) => {
+                let type_token = reader.read_u32_immut(rip)?;
+                let length = match self.pop()? {
+                    ILType::Val(v) => match v.to_stack_type() {
+                        ILValType::Int32(i) => usize::try_from(i).map_err(|_| RuntimeErrorKind::Overflow)?,
+                        ILValType::Isize(i) => usize::try_from(i).map_err(|_| RuntimeErrorKind::Overflow)?,
+                        v => return Err(RuntimeErrorKind::TypeMismatch(format!("newarr length must be int32 or native int, found {:?}", v)).into()),
+                    },
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("newarr length must be int32 or native int, found {:?}", value)).into()),
+                };
+                let element_type = self.resolve_runtime_type(ctx.assembly_index, type_token, &generic)?;
+                let default = self.get_runtime_type_default(&element_type)?;
+                self.arrays.push(Array::with_length(element_type, length, default));
+                self.stack.push_back(ILType::Ref(ILRefType::Array(self.arrays.len() - 1)));
+            },
+            Some(OpCode::Ldlen) => {
+                match self.pop()? {
+                    ILType::Ref(ILRefType::Array(array)) => self.stack.push_back(ILType::Val(ILValType::Isize(self.arrays[array].len() as isize))),
+                    ILType::Ref(ILRefType::Null) => return Err(RuntimeErrorKind::NullReference.into()),
+                    value => return Err(RuntimeErrorKind::TypeMismatch(format!("ldlen requires an array, found {:?}", value)).into()),
+                }
+            },
+            Some(OpCode::Ldelema) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ptr = self.pop_element()?;
+                if !readonly {  // 可写的元素地址要求元素类型和token完全相同，否则通过它可以写入不兼容的值
+                    let expected = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                    self.check_element_type(&ptr, &expected, true)?;
+                }
+                self.stack.push_back(ILType::Ptr(ptr));
+            },
+            Some(OpCode::Ldelemi1) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I1)?);
+            },
+            Some(OpCode::Ldelemu1) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U1)?);
+            },
+            Some(OpCode::Ldelemi2) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I2)?);
+            },
+            Some(OpCode::Ldelemu2) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U2)?);
+            },
+            Some(OpCode::Ldelemi4) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I4)?);
+            },
+            Some(OpCode::Ldelemu4) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::U4)?);
+            },
+            Some(OpCode::Ldelemi8) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I8)?);
+            },
+            Some(OpCode::Ldelemi) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv(ConvTarget::I)?);
+            },
+            Some(OpCode::Ldelemr4) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv_r4()?);
+            },
+            Some(OpCode::Ldelemr8) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value.conv_r8()?);
+            },
+            Some(OpCode::Ldelemref) => {
+                let ptr = self.pop_element()?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Stelemi) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I)?)?;
+            },
+            Some(OpCode::Stelemi1) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I1)?)?;
+            },
+            Some(OpCode::Stelemi2) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I2)?)?;
+            },
+            Some(OpCode::Stelemi4) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I4)?)?;
+            },
+            Some(OpCode::Stelemi8) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I8)?)?;
+            },
+            Some(OpCode::Stelemr4) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv_r4()?)?;
+            },
+            Some(OpCode::Stelemr8) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                self.ptr_store(ctx, ptr, value.conv_r8()?)?;
+            },
+            Some(OpCode::Stelemref) => {
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                if let ILPtr::Element((array, _)) = ptr {
+                    if !self.is_assignable_to_element(array, &value)? {
+                        return Err(RuntimeErrorKind::ArrayTypeMismatch.into());
                     }
-                },
-                Some(OpCode::Ldsfld) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let field = &assembly.fields[token as usize - 0x04000001];
-                    let cctor = self.try_get_cctor(ctx, field);
-                    if cctor.is_some() {  // 如果没有初始化，则调用cctor
-                        self.init_static_fields(ctx, field.owner_type);
-                        self.il_call(ctx, cctor.unwrap());
-                    }
-                    let field_value = self.static_fields[ctx.assembly_index].get(&token).unwrap().clone();
-                    self.stack.push_back(field_value);
-                },
-                Some(OpCode::Ldsflda) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let field = &assembly.fields[token as usize - 0x04000001];
-                    let cctor = self.try_get_cctor(ctx, field);
-                    if cctor.is_some() {  // 如果没有初始化，则调用cctor
-                        self.init_static_fields(ctx, field.owner_type);
-                        self.il_call(ctx, cctor.unwrap());
-                    }
-                    self.stack.push_back(ILType::Ptr(ILPtr::Static((ctx.assembly_index, token))));
-                },
-                Some(OpCode::Stsfld) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let field = &assembly.fields[token as usize - 0x04000001];
-                    let cctor = self.try_get_cctor(ctx, field);
-                    if cctor.is_some() {  // 如果没有初始化，则调用cctor初始化
-                        self.init_static_fields(ctx, field.owner_type);
-                        self.il_call(ctx, cctor.unwrap());
-                    }
-                    self.static_fields[ctx.assembly_index].insert(token, self.stack.pop_back().unwrap());
-                },
-                Some(OpCode::Stobj) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi1un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi2un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi4un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi8un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu1un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu2un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu4un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu8un) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfiun) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfuun) => {
-                    todo!();
-                },
-                Some(OpCode::Box) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let value = self.stack.pop_back().unwrap();
-                    self.il_box_obj(token, value);
-                },
-                Some(OpCode::Newarr) => {
-                    todo!();
-                },
-                Some(OpCode::Ldlen) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelema) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemi1) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemu1) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemi2) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemu2) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemi4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemu4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemi8) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemi) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemr4) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemr8) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelemref) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemi) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemi1) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemi2) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemi4) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemi8) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemr4) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemr8) => {
-                    todo!();
-                },
-                Some(OpCode::Stelemref) => {
-                    todo!();
-                },
-                Some(OpCode::Ldelem) => {
-                    todo!();
-                },
-                Some(OpCode::Stelem) => {
-                    todo!();
-                },
-                Some(OpCode::Unboxany) => {
-                    let token = reader.read_u32_immut(&mut rip).unwrap();
-                    let boxed = self.stack.pop_back().unwrap();
-                    let ref_obj = &self.objects[boxed.get_ref()];
-                    if ref_obj.get_type() != token {
-                        panic!("unboxany: type mismatch");
-                    }
-                    self.stack.push_back(ref_obj.box_value.clone().unwrap());
-                },
-                Some(OpCode::Convovfi1) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu1) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi2) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu2) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi4) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu4) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi8) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu8) => {
-                    todo!();
-                },
-                Some(OpCode::Refanyval) => {
-                    todo!();
-                },
-                Some(OpCode::Ckfinite) => {
-                    todo!();
-                },
-                Some(OpCode::Mkrefany) => {
-                    todo!();
-                },
-                Some(OpCode::Ldtoken) => {
-                    todo!();
-                },
-                Some(OpCode::Convu2) => {
-                    todo!();
-                },
-                Some(OpCode::Convu1) => {
-                    todo!();
-                },
-                Some(OpCode::Convi) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfi) => {
-                    todo!();
-                },
-                Some(OpCode::Convovfu) => {
-                    todo!();
-                },
-                Some(OpCode::Addovf) => {
-                    todo!();
-                },
-                Some(OpCode::Addovfun) => {
-                    todo!();
-                },
-                Some(OpCode::Mulovf) => {
-                    todo!();
-                },
-                Some(OpCode::Mulovfun) => {
-                    todo!();
-                },
-                Some(OpCode::Subovf) => {
-                    todo!();
-                },
-                Some(OpCode::Subovfun) => {
-                    todo!();
-                },
-                Some(OpCode::Endfault) => {
-                    todo!();
-                },
-                Some(OpCode::Leave) => {
-                    todo!();
-                },
-                Some(OpCode::Leaves) => {
-                    todo!();
-                },
-                Some(OpCode::Stindi) => {
-                    todo!();
-                },
-                Some(OpCode::Convu) => {
-                    let val = self.stack.pop_back().unwrap();
-                    if let ILType::Val(val) = val {
-                        self.stack.push_back(ILType::Val(ILValType::Isize(val.to_usize() as isize)));
-                    } else {
-                        panic!("convu: type mismatch");
+                }
+                self.ptr_store(ctx, ptr, value)?;
+            },
+            Some(OpCode::Ldelem) => {
+                let token = reader.read_u32_immut(rip)?;
+                let ptr = self.pop_element()?;
+                let expected = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                self.check_element_type(&ptr, &expected, false)?;
+                let value = self.ptr_load(ctx, ptr)?;
+                self.stack.push_back(value);
+            },
+            Some(OpCode::Stelem) => {
+                reader.read_u32_immut(rip)?;  // 元素为引用类型时和stelem.ref相同
+                let value = self.pop()?;
+                let ptr = self.pop_element()?;
+                if let ILPtr::Element((array, _)) = ptr {
+                    if !self.is_assignable_to_element(array, &value)? {
+                        return Err(RuntimeErrorKind::ArrayTypeMismatch.into());
                     }
-                },
-                Some(OpCode::Next) => {
-                    let op = reader.read_u8_immut(&mut rip).unwrap();
-                    match FromPrimitive::from_u8(op) {
-                        Some(OpCode2::Arglist) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ceq) => {
-                            let v2 = self.stack.pop_back().unwrap();
-                            let v1 = self.stack.pop_back().unwrap();
-                            if v1 == v2 {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(1)));
-                            } else {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(0)));
-                            }
-                        },
-                        Some(OpCode2::Cgt) => {
-                            let v2 = self.stack.pop_back().unwrap();
-                            let v1 = self.stack.pop_back().unwrap();
-                            if v1 > v2 {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(1)));
-                            } else {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(0)));
-                            }
-                        },
-                        Some(OpCode2::Cgtun) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Clt) => {
-                            let v2 = self.stack.pop_back().unwrap();
-                            let v1 = self.stack.pop_back().unwrap();
-                            if v1 < v2 {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(1)));
-                            } else {
-                                self.stack.push_back(ILType::Val(ILValType::Int32(0)));
-                            }
-                        },
-                        Some(OpCode2::Cltun) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldftn) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldvirtftn) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldarg) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldarga) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Starg) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldloc) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Ldloca) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Stloc) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Localloc) => {
-                            let size = self.stack.pop_back().unwrap();
-                            if let ILType::Val(val) = size {
-                                let size = val.to_usize();
-                                self.stack.push_back(ILType::NPtr(ILNPtr::new(size)));
-                            } else {
-                                panic!("localloc: type mismatch");
+                }
+                self.ptr_store(ctx, ptr, value)?;
+            },
+            Some(OpCode::Unboxany) => {
+                let token = reader.read_u32_immut(rip)?;
+                let value = self.pop()?;
+                let target = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                let default = self.get_runtime_type_default(&target)?;
+                match (&value, default) {
+                    // 引用类型的unbox.any和castclass相同
+                    (_, ILType::Ref(_)) => {
+                        if !self.is_value_assignable(&value, &target)? {
+                            let message = self.describe_invalid_cast(&value, &target)?;
+                            return Err(RuntimeErrorKind::InvalidCast(message).into());
+                        }
+                        self.stack.push_back(value);
+                    },
+                    // null拆箱为没有值的Nullable<T>，T的box拆箱为有值的Nullable<T>
+                    (_, ILType::Struct(mut nullable)) if self.is_nullable(&nullable) => {
+                        if let ILType::Ref(ILRefType::Object(object)) = value {
+                            let inner_type = match &target {
+                                RuntimeType::Type(_, type_args) if type_args.len() == 1 => type_args[0].clone(),
+                                _ => return Err(RuntimeErrorKind::InvalidToken(token).into()),
+                            };
+                            self.check_unbox(object, &inner_type)?;
+                            let inner = self.objects[object].box_value.clone().ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                            for (name, field_value) in [("hasValue", ILType::Val(ILValType::Boolean(true))), ("value", inner)] {
+                                if let Some(field) = self.find_struct_field(&nullable, name).and_then(|rid| nullable.get_field_mut(rid)) {
+                                    *field = field_value.coerce_to(field);
+                                }
                             }
-                        },
-                        Some(OpCode2::Endfilter) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Unaligned) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Volatile) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Tail) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Initobj) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Constrained) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Cpblk) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Initblk) => {
-                            todo!();
-                        },
-                        Some(OpCode2::No) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Rethrow) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Sizeof) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Refanytype) => {
-                            todo!();
-                        },
-                        Some(OpCode2::Readonly) => {
-                            todo!();
-                        },
-                        _ => {
-                            panic!("Unknown OpCode: 0x{:02X}", op);
+                        } else if value != ILType::Ref(ILRefType::Null) {
+                            return Err(RuntimeErrorKind::TypeMismatch(format!("unbox.any requires an object reference, found {:?}", value)).into());
                         }
-                    }
+                        self.stack.push_back(ILType::Struct(nullable));
+                    },
+                    (ILType::Ref(ILRefType::Object(object)), _) => {
+                        self.check_unbox(*object, &target)?;
+                        let value = self.objects[*object].box_value.clone().ok_or(RuntimeErrorKind::InvalidToken(token))?;
+                        self.stack.push_back(value.to_stack_value());
+                    },
+                    (ILType::Ref(ILRefType::Null), _) => return Err(RuntimeErrorKind::NullReference.into()),
+                    _ => return Err(RuntimeErrorKind::TypeMismatch(format!("unbox.any requires an object reference, found {:?}", value)).into()),
                 }
-                _ => {
-                    panic!("Unknown OpCode: 0x{:02X}", op);
+            },
+            Some(OpCode::Convovfi1) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I1, false)?);
+            },
+            Some(OpCode::Convovfu1) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U1, false)?);
+            },
+            Some(OpCode::Convovfi2) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I2, false)?);
+            },
+            Some(OpCode::Convovfu2) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U2, false)?);
+            },
+            Some(OpCode::Convovfi4) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I4, false)?);
+            },
+            Some(OpCode::Convovfu4) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U4, false)?);
+            },
+            Some(OpCode::Convovfi8) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I8, false)?);
+            },
+            Some(OpCode::Convovfu8) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U8, false)?);
+            },
+            Some(OpCode::Refanyval) => {
+                unsupported!(op_code);
+            },
+            Some(OpCode::Ckfinite) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.check_finite()?);
+            },
+            Some(OpCode::Mkrefany) => {
+                unsupported!(op_code);
+            },
+            Some(OpCode::Ldtoken) => {
+                let token = reader.read_u32_immut(rip)?;
+                let runtime_type = match token >> 24 {
+                    0x01 | 0x02 | 0x1B => Some(self.resolve_runtime_type(ctx.assembly_index, token, &generic)?),  // typeof(T)得到实例化后的类型
+                    _ => None,
+                };
+                self.stack.push_back(ILType::Handle(ILHandle { assembly_index: ctx.assembly_index, token, runtime_type }));
+            },
+            Some(OpCode::Convu2) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::U2)?);
+            },
+            Some(OpCode::Convu1) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::U1)?);
+            },
+            Some(OpCode::Convi) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::I)?);
+            },
+            Some(OpCode::Convovfi) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::I, false)?);
+            },
+            Some(OpCode::Convovfu) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv_ovf(ConvTarget::U, false)?);
+            },
+            Some(OpCode::Addovf) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.add_ovf(b)?);
+            },
+            Some(OpCode::Addovfun) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.add_ovf_un(b)?);
+            },
+            Some(OpCode::Mulovf) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.mul_ovf(b)?);
+            },
+            Some(OpCode::Mulovfun) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.mul_ovf_un(b)?);
+            },
+            Some(OpCode::Subovf) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.sub_ovf(b)?);
+            },
+            Some(OpCode::Subovfun) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.stack.push_back(a.sub_ovf_un(b)?);
+            },
+            Some(OpCode::Endfault) => {
+                return Ok(Some(BlockExit::EndFinally));
+            },
+            Some(OpCode::Leave) => {
+                let target = reader.read_u32_immut(rip)? as i32;
+                *rip = (*rip as isize + target as isize) as usize;
+                self.il_leave(ctx, frame_index, op_offset, *rip - method.code_position)?;
+            },
+            Some(OpCode::Leaves) => {
+                let target = reader.read_u8_immut(rip)? as i8;
+                *rip = (*rip as isize + target as isize) as usize;
+                self.il_leave(ctx, frame_index, op_offset, *rip - method.code_position)?;
+            },
+            Some(OpCode::Stindi) => {
+                let value = self.pop()?;
+                let ptr = self.pop_ptr()?;
+                self.ptr_store(ctx, ptr, value.conv(ConvTarget::I)?)?;
+            },
+            Some(OpCode::Convu) => {
+                let value = self.pop()?;
+                self.stack.push_back(value.conv(ConvTarget::U)?);
+            },
+            Some(OpCode::Next) => {
+                let op = reader.read_u8_immut(rip)?;
+                let op_code2: Option<OpCode2> = FromPrimitive::from_u8(op);
+                match op_code2 {
+                    Some(OpCode2::Arglist) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Ceq) => {
+                        let result = self.pop_compare(false)? == Some(Ordering::Equal);
+                        self.stack.push_back(ILType::Val(ILValType::Int32(result as i32)));
+                    },
+                    Some(OpCode2::Cgt) => {
+                        let result = self.pop_compare(false)? == Some(Ordering::Greater);
+                        self.stack.push_back(ILType::Val(ILValType::Int32(result as i32)));
+                    },
+                    Some(OpCode2::Cgtun) => {
+                        let result = !matches!(self.pop_compare(true)?, Some(Ordering::Less | Ordering::Equal));
+                        self.stack.push_back(ILType::Val(ILValType::Int32(result as i32)));
+                    },
+                    Some(OpCode2::Clt) => {
+                        let result = self.pop_compare(false)? == Some(Ordering::Less);
+                        self.stack.push_back(ILType::Val(ILValType::Int32(result as i32)));
+                    },
+                    Some(OpCode2::Cltun) => {
+                        let result = !matches!(self.pop_compare(true)?, Some(Ordering::Greater | Ordering::Equal));
+                        self.stack.push_back(ILType::Val(ILValType::Int32(result as i32)));
+                    },
+                    Some(OpCode2::Ldftn) => {
+                        let token = reader.read_u32_immut(rip)?;
+                        let fn_ptr = self.resolve_fn_ptr(ctx, token, &generic, None)?;
+                        self.stack.push_back(ILType::FnPtr(fn_ptr));
+                    },
+                    Some(OpCode2::Ldvirtftn) => {
+                        let token = reader.read_u32_immut(rip)?;
+                        let object = self.pop()?;
+                        let fn_ptr = self.resolve_fn_ptr(ctx, token, &generic, Some(&object))?;
+                        self.stack.push_back(ILType::FnPtr(fn_ptr));
+                    },
+                    Some(OpCode2::Ldarg) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        self.stack.push_back(frame_slot!(params, index).to_stack_value());
+                    },
+                    Some(OpCode2::Ldarga) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        frame_slot!(params, index);  // 只检查下标
+                        self.stack.push_back(ILType::Ptr(ILPtr::Param((ctx.call_stack[frame_index].stack_id, index as usize))));
+                    },
+                    Some(OpCode2::Starg) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        let value = self.pop()?;
+                        let param = frame_slot!(params, index);
+                        *param = value.coerce_to(param);
+                    },
+                    Some(OpCode2::Ldloc) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        self.stack.push_back(frame_slot!(locals, index).to_stack_value());
+                    },
+                    Some(OpCode2::Ldloca) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        frame_slot!(locals, index);  // 只检查下标
+                        self.stack.push_back(ILType::Ptr(ILPtr::Local((ctx.call_stack[frame_index].stack_id, index as usize))));
+                    },
+                    Some(OpCode2::Stloc) => {
+                        let index = reader.read_u16_immut(rip)?;
+                        let value = self.pop()?;
+                        let local = frame_slot!(locals, index);
+                        *local = value.coerce_to(local);
+                    },
+                    Some(OpCode2::Localloc) => {
+                        let size = self.pop()?;
+                        if let ILType::Val(val) = size {
+                            let size = val.to_usize();
+                            self.stack.push_back(ILType::NPtr(ILNPtr::new(size)));
+                        } else {
+                            return Err(RuntimeErrorKind::TypeMismatch(String::from("localloc requires a value type")).into());
+                        }
+                    },
+                    Some(OpCode2::Endfilter) => {
+                        let value = self.pop()?;
+                        return Ok(Some(BlockExit::EndFilter(!value.is_false_type())));
+                    },
+                    Some(OpCode2::Unaligned) => {  // 托管数据没有字节布局，对齐和易失性都不影响解释执行，这些前缀只需跳过
+                        reader.read_u8_immut(rip)?;
+                    },
+                    Some(OpCode2::Volatile) => {},
+                    Some(OpCode2::Tail) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Initobj) => {
+                        let token = reader.read_u32_immut(rip)?;
+                        let ptr = self.pop_ptr()?;
+                        let value = self.get_type_default(ctx.assembly_index, token, &generic)?;
+                        *self.ptr_slot(ctx, ptr)? = value;
+                    },
+                    Some(OpCode2::Constrained) => {
+                        let token = reader.read_u32_immut(rip)?;
+                        let runtime_type = self.resolve_runtime_type(ctx.assembly_index, token, &generic)?;
+                        ctx.call_stack[frame_index].constrained = Some(runtime_type);
+                    },
+                    Some(OpCode2::Cpblk) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Initblk) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::No) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Rethrow) => {  // 重新抛出最内层的catch块捕获的异常
+                        let caught = method.exception_clauses.iter().enumerate()
+                            .filter(|(_, clause)| clause.handler_contains(op_offset))
+                            .find_map(|(clause_index, _)| ctx.call_stack[frame_index].caught_exceptions.get(&clause_index).cloned());
+                        match caught {
+                            Some(object) => return Err(self.il_throw(ctx, object)),
+                            None => return Err(RuntimeErrorKind::BadImage(String::from("rethrow outside of a catch handler")).into()),
+                        }
+                    },
+                    Some(OpCode2::Sizeof) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Refanytype) => {
+                        unsupported!(op_code2);
+                    },
+                    Some(OpCode2::Readonly) => {
+                        ctx.call_stack[frame_index].readonly = true;
+                    },
+                    _ => {
+                        return Err(RuntimeErrorKind::UnknownOpCode(0xFE00 | op as u16).into());
+                    }
                 }
             }
+            _ => {
+                return Err(RuntimeErrorKind::UnknownOpCode(op as u16).into());
+            }
         }
-        ctx.call_stack.pop();
-        ctx.assembly_index = ctx.call_stack.last().unwrap().0;
-        ctx.assembly = Rc::clone(&self.assemblies.index_get(ctx.assembly_index).unwrap());
-        for _ in 0..call_depth {
-            print!("-");
-        }
-        println!("exit: {}", method_name);
+        Ok(None)
     }
 }
@@ -0,0 +1,113 @@
+use std::convert::TryFrom;
+
+use super::{il_type::ILType, runtime_type::RuntimeType};
+
+/// 托管数组，元素按顺序存放，多维数组按行优先展开
+pub struct Array {
+    /// 元素类型，None表示不检查存入的元素类型
+    pub element_type: Option<RuntimeType>,
+    /// 多维数组每一维的(下界, 长度)，SZArray（一维且下界为0）为空
+    dimensions: Vec<(i32, usize)>,
+    elements: Vec<ILType>,
+}
+
+impl Array {
+    pub fn new(element_type: Option<RuntimeType>, elements: Vec<ILType>) -> Array {
+        Array {
+            element_type,
+            dimensions: Vec::new(),
+            elements,
+        }
+    }
+
+    /// 创建长度为length的数组，每个元素都是default
+    pub fn with_length(element_type: RuntimeType, length: usize, default: ILType) -> Array {
+        Array::new(Some(element_type), vec![default; length])
+    }
+
+    /// 创建多维数组，dimensions为每一维的(下界, 长度)，元素总数溢出时返回None
+    pub fn new_multi_dim(element_type: Option<RuntimeType>, dimensions: Vec<(i32, usize)>, default: ILType) -> Option<Array> {
+        let length = dimensions.iter().try_fold(1usize, |total, (_, length)| total.checked_mul(*length))?;
+        Some(Array {
+            element_type,
+            dimensions,
+            elements: vec![default; length],
+        })
+    }
+
+    /// 是否为一维且下界为0的数组
+    pub fn is_sz_array(&self) -> bool {
+        self.dimensions.is_empty()
+    }
+
+    pub fn rank(&self) -> u32 {
+        self.dimensions.len().max(1) as u32
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    /// 把多维数组的下标转换为elements中的index，维数不对或者越界时返回None
+    pub fn flatten_index(&self, indices: &[i32]) -> Option<usize> {
+        if self.dimensions.is_empty() {
+            return match indices {
+                [index] => usize::try_from(*index).ok().filter(|i| *i < self.elements.len()),
+                _ => None,
+            };
+        }
+        if indices.len() != self.dimensions.len() {
+            return None;
+        }
+        let mut flat = 0;
+        for (index, (lower_bound, length)) in indices.iter().zip(self.dimensions.iter()) {
+            let offset = usize::try_from(*index as i64 - *lower_bound as i64).ok().filter(|o| o < length)?;
+            flat = flat * length + offset;
+        }
+        Some(flat)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&ILType> {
+        self.elements.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut ILType> {
+        self.elements.get_mut(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::op_codes::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+
+    /// 对string[]依次执行code中的取元素指令，返回执行结果或者托管异常的类型名
+    fn run_on_string_array(element: impl FnOnce(&mut AssemblyBuilder, Code) -> Code) -> Result<(), String> {
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let string = builder.cor_lib_type("System", "String");
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::void(), vec![]));
+        let code = Code::new().op(OpCode::Ldci41).op_token(OpCode::Newarr, string).op(OpCode::Ldci40);
+        let code = element(&mut builder, code).op(OpCode::Pop).op(OpCode::Ret);
+        builder.body(main, vec![], code);
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        call(&mut interpreter, main).map(|_| ()).map_err(|e| exception_type(&interpreter, &e).unwrap_or_else(|| format!("{:?}", e.kind)))
+    }
+
+    #[test]
+    fn ldelema_requires_the_exact_element_type() {
+        assert!(run_on_string_array(|builder, code| code.op_token(OpCode::Ldelema, builder.cor_lib_type("System", "String"))).is_ok());
+        assert_eq!(run_on_string_array(|builder, code| code.op_token(OpCode::Ldelema, builder.cor_lib_type("System", "Object"))),
+            Err(String::from("System.ArrayTypeMismatchException")));
+        // readonly.的地址不能用来写入，所以不检查
+        assert!(run_on_string_array(|builder, code| code.op2(OpCode2::Readonly).op_token(OpCode::Ldelema, builder.cor_lib_type("System", "Object"))).is_ok());
+    }
+
+    #[test]
+    fn ldelem_requires_an_assignable_element_type() {
+        assert!(run_on_string_array(|builder, code| code.op_token(OpCode::Ldelem, builder.cor_lib_type("System", "Object"))).is_ok());
+        assert_eq!(run_on_string_array(|builder, code| code.op_token(OpCode::Ldelem, builder.cor_lib_type("System", "Int32"))),
+            Err(String::from("System.ArrayTypeMismatchException")));
+    }
+}
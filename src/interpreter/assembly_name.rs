@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter, Result};
 use bitflags::bitflags;
 
 bitflags! {
@@ -10,14 +11,74 @@ bitflags! {
     }
 }
 
+/// 引用的Assembly和实际找到的Assembly版本号不同时如何处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BindingPolicy {
+    /// 版本号必须完全一致
+    Exact,
+    /// 主版本号一致，并且找到的版本不低于引用的版本
+    #[default]
+    RollForwardMinor,
+    /// 不检查版本号
+    IgnoreVersion,
+}
+
 pub struct AssemblyName {
     pub major_version: u16,
     pub minor_version: u16,
     pub build_number: u16,
     pub revision_number: u16,
     pub flags: u32,
-    pub public_key_token: Vec<u8>,
+    /// 如果flags包含PUBLIC_KEY，那么这是完整的公钥，否则是8字节的PublicKeyToken（也可能为空）
+    pub public_key_or_token: Vec<u8>,
     pub name: String,
+    /// 空字符串表示neutral
+    pub culture: String,
+}
+
+impl AssemblyName {
+    pub fn version(&self) -> (u16, u16, u16, u16) {
+        (self.major_version, self.minor_version, self.build_number, self.revision_number)
+    }
+
+    pub fn has_public_key(&self) -> bool {
+        AssemblyNameFlags::from_bits_truncate(self.flags).contains(AssemblyNameFlags::PUBLIC_KEY)
+    }
+
+    /// 返回8字节的PublicKeyToken，如果持有的是完整公钥，则取其SHA-1的最后8字节并逆序，没有强签名时为空
+    pub fn public_key_token(&self) -> Vec<u8> {
+        if self.has_public_key() && !self.public_key_or_token.is_empty() {
+            let digest = sha1_smol::Sha1::from(&self.public_key_or_token).digest().bytes();
+            digest.iter().rev().take(8).cloned().collect()
+        } else {
+            self.public_key_or_token.clone()
+        }
+    }
+
+    fn is_neutral_culture(culture: &str) -> bool {
+        culture.is_empty() || culture.eq_ignore_ascii_case("neutral")
+    }
+
+    /// self为引用方（AssemblyRef），判断definition（实际加载的Assembly）能否满足这个引用
+    pub fn matches(&self, definition: &AssemblyName, policy: BindingPolicy) -> bool {
+        if !self.name.eq_ignore_ascii_case(&definition.name) {
+            return false;
+        }
+        let neutral = Self::is_neutral_culture(&self.culture);
+        if neutral != Self::is_neutral_culture(&definition.culture) || (!neutral && !self.culture.eq_ignore_ascii_case(&definition.culture)) {
+            return false;
+        }
+        // 引用方没有强签名时，不要求被引用方的公钥
+        let token = self.public_key_token();
+        if !token.is_empty() && token != definition.public_key_token() {
+            return false;
+        }
+        match policy {
+            BindingPolicy::Exact => self.version() == definition.version(),
+            BindingPolicy::RollForwardMinor => self.major_version == definition.major_version && definition.version() >= self.version(),
+            BindingPolicy::IgnoreVersion => true,
+        }
+    }
 }
 
 impl Clone for AssemblyName {
@@ -28,21 +89,121 @@ impl Clone for AssemblyName {
             build_number: self.build_number,
             revision_number: self.revision_number,
             flags: self.flags,
-            public_key_token: self.public_key_token.clone(),
+            public_key_or_token: self.public_key_or_token.clone(),
             name: self.name.clone(),
+            culture: self.culture.clone(),
         }
     }
 }
 
 impl PartialEq for AssemblyName {
     fn eq(&self, other: &Self) -> bool {
-        self.name == other.name
-        // if self.name != other.name {
-        //     false
-        // } else if AssemblyName::all_zero(&self.public_key_token) || AssemblyName::all_zero(&other.public_key_token) {
-        //     true
-        // } else {
-        //     self.public_key_token == other.public_key_token
-        // }
+        self.matches(other, BindingPolicy::Exact) && self.public_key_token() == other.public_key_token()
+    }
+}
+
+/// 形如 System.Runtime, Version=5.0.0.0, Culture=neutral, PublicKeyToken=b03f5f7f11d50a3a
+impl Display for AssemblyName {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}, Version={}.{}.{}.{}, Culture={}, PublicKeyToken=",
+            self.name, self.major_version, self.minor_version, self.build_number, self.revision_number,
+            if Self::is_neutral_culture(&self.culture) { "neutral" } else { &self.culture })?;
+        let token = self.public_key_token();
+        if token.is_empty() {
+            write!(f, "null")
+        } else {
+            for b in token {
+                write!(f, "{:02x}", b)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ECMA-335 II.6.2.1.3中的标准公钥，对应的PublicKeyToken为b77a5c561934e089
+    const ECMA_PUBLIC_KEY: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0];
+    const ECMA_PUBLIC_KEY_TOKEN: [u8; 8] = [0xb7, 0x7a, 0x5c, 0x56, 0x19, 0x34, 0xe0, 0x89];
+
+    fn name(name: &str, version: (u16, u16, u16, u16)) -> AssemblyName {
+        AssemblyName {
+            major_version: version.0,
+            minor_version: version.1,
+            build_number: version.2,
+            revision_number: version.3,
+            flags: 0,
+            public_key_or_token: vec![],
+            name: name.to_string(),
+            culture: String::new(),
+        }
+    }
+
+    #[test]
+    fn binding_policy_decides_which_versions_match() {
+        let reference = name("System.Runtime", (5, 0, 0, 0));
+        let same = name("system.runtime", (5, 0, 0, 0));
+        let newer_minor = name("System.Runtime", (5, 1, 0, 0));
+        let older_build = name("System.Runtime", (4, 9, 9, 9));
+        let newer_major = name("System.Runtime", (6, 0, 0, 0));
+
+        assert!(reference.matches(&same, BindingPolicy::Exact));
+        assert!(!reference.matches(&newer_minor, BindingPolicy::Exact));
+
+        assert_eq!(BindingPolicy::default(), BindingPolicy::RollForwardMinor);
+        assert!(reference.matches(&same, BindingPolicy::RollForwardMinor));
+        assert!(reference.matches(&newer_minor, BindingPolicy::RollForwardMinor));
+        assert!(!reference.matches(&older_build, BindingPolicy::RollForwardMinor));
+        assert!(!reference.matches(&newer_major, BindingPolicy::RollForwardMinor));
+        assert!(!newer_minor.matches(&reference, BindingPolicy::RollForwardMinor));
+
+        assert!(reference.matches(&older_build, BindingPolicy::IgnoreVersion));
+        assert!(reference.matches(&newer_major, BindingPolicy::IgnoreVersion));
+        assert!(!reference.matches(&name("System.Console", (5, 0, 0, 0)), BindingPolicy::IgnoreVersion));
+    }
+
+    #[test]
+    fn culture_must_match() {
+        let reference = name("Resources", (1, 0, 0, 0));
+        let mut neutral = name("Resources", (1, 0, 0, 0));
+        neutral.culture = "neutral".to_string();
+        let mut german = name("Resources", (1, 0, 0, 0));
+        german.culture = "de-DE".to_string();
+        let mut german_reference = name("Resources", (1, 0, 0, 0));
+        german_reference.culture = "de-de".to_string();
+
+        assert!(reference.matches(&neutral, BindingPolicy::Exact));
+        assert!(!reference.matches(&german, BindingPolicy::Exact));
+        assert!(!german.matches(&reference, BindingPolicy::Exact));
+        assert!(german_reference.matches(&german, BindingPolicy::Exact));
+    }
+
+    #[test]
+    fn public_key_token_is_compared_when_the_reference_is_strong_named() {
+        let mut definition = name("System.Private.CoreLib", (5, 0, 0, 0));
+        definition.flags = AssemblyNameFlags::PUBLIC_KEY.bits();
+        definition.public_key_or_token = ECMA_PUBLIC_KEY.to_vec();
+        assert_eq!(definition.public_key_token(), ECMA_PUBLIC_KEY_TOKEN);
+
+        let mut reference = name("System.Private.CoreLib", (5, 0, 0, 0));
+        reference.public_key_or_token = ECMA_PUBLIC_KEY_TOKEN.to_vec();
+        assert!(reference.matches(&definition, BindingPolicy::Exact));
+
+        reference.public_key_or_token[0] ^= 0xFF;
+        assert!(!reference.matches(&definition, BindingPolicy::Exact));
+
+        // 引用方没有强签名
+        reference.public_key_or_token.clear();
+        assert!(reference.matches(&definition, BindingPolicy::Exact));
+    }
+
+    #[test]
+    fn display_uses_the_full_assembly_name_format() {
+        let mut reference = name("System.Runtime", (5, 0, 0, 0));
+        assert_eq!(reference.to_string(), "System.Runtime, Version=5.0.0.0, Culture=neutral, PublicKeyToken=null");
+        reference.public_key_or_token = ECMA_PUBLIC_KEY_TOKEN.to_vec();
+        assert_eq!(reference.to_string(), "System.Runtime, Version=5.0.0.0, Culture=neutral, PublicKeyToken=b77a5c561934e089");
     }
 }
@@ -5,7 +5,6 @@ use super::{assembly_name::AssemblyName, metadata::Metadata};
 pub struct AssemblyRef {
     pub token: u32,               // 形如 0x230000001
     pub assembly_name: AssemblyName,
-    pub locale: u16,
     pub hash_value: u16,
 }
 
@@ -19,9 +18,9 @@ impl AssemblyRef {
             let build_number = assembly_ref_table.columns[2].get_cell_u16(row);
             let revision_number = assembly_ref_table.columns[3].get_cell_u16(row);
             let flags = assembly_ref_table.columns[4].get_cell_u32(row);
-            let public_key_token = metadata.blob_stream.read(assembly_ref_table.columns[5].get_cell_u16(row) as u32)?;
-            let locale = assembly_ref_table.columns[7].get_cell_u16(row);
+            let public_key_or_token = metadata.blob_stream.read(assembly_ref_table.columns[5].get_cell_u16_or_u32(row))?;
             let name = metadata.strings_stream.get_string_clone(assembly_ref_table.columns[6].get_cell_u16_or_u32(row))?;
+            let culture = metadata.strings_stream.get_string_clone(assembly_ref_table.columns[7].get_cell_u16_or_u32(row))?;
             let hash_value = assembly_ref_table.columns[8].get_cell_u16(row);
 
             assembly_refs.push(AssemblyRef { 
@@ -32,10 +31,10 @@ impl AssemblyRef {
                     build_number,
                     revision_number,
                     flags,
-                    public_key_token,
+                    public_key_or_token,
                     name,
+                    culture,
                 },
-                locale,
                 hash_value,
             });
         }
@@ -0,0 +1,347 @@
+use std::{env, fs, io};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{Assembly, assembly_name::{AssemblyName, BindingPolicy}};
+
+/// AssemblyResolver找到的Assembly来源
+pub enum ResolvedAssembly {
+    /// 磁盘上的文件
+    Path(PathBuf),
+    /// 内存中的完整PE镜像
+    Bytes(Vec<u8>),
+    /// 已经加载好的Assembly
+    Assembly(Box<Assembly>),
+}
+
+/// Interpreter需要加载被引用的Assembly时，会按添加顺序依次询问每个resolver
+/// 返回ErrorKind::NotFound表示这个resolver不认识该Assembly，会继续询问下一个，其他错误会直接中断加载
+pub trait AssemblyResolver {
+    fn resolve(&self, assembly_name: &AssemblyName) -> io::Result<ResolvedAssembly>;
+
+    /// 按优先级返回所有候选，前面的名称或版本不满足引用时会尝试后面的
+    fn resolve_all(&self, assembly_name: &AssemblyName) -> io::Result<Vec<ResolvedAssembly>> {
+        Ok(vec![self.resolve(assembly_name)?])
+    }
+}
+
+impl<F> AssemblyResolver for F where F: Fn(&AssemblyName) -> io::Result<ResolvedAssembly> {
+    fn resolve(&self, assembly_name: &AssemblyName) -> io::Result<ResolvedAssembly> {
+        self(assembly_name)
+    }
+}
+
+/// 从内存中提供Assembly，例如嵌入的资源或者测试用的镜像 <assembly_name.name, image>
+#[derive(Default)]
+pub struct MemoryResolver {
+    images: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        Default::default()
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, name: S, image: Vec<u8>) {
+        self.images.insert(name.into(), image);
+    }
+}
+
+impl AssemblyResolver for MemoryResolver {
+    fn resolve(&self, assembly_name: &AssemblyName) -> io::Result<ResolvedAssembly> {
+        match self.images.get(&assembly_name.name) {
+            Some(image) => Ok(ResolvedAssembly::Bytes(image.clone())),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("Assembly '{}' is not in memory.", assembly_name.name))),
+        }
+    }
+}
+
+/// 按顺序在一组目录中探测Assembly文件
+/// 探测顺序：入口Assembly所在目录 -> 用户指定的目录 -> 环境变量中的目录 -> 共享框架目录
+pub struct PathResolver {
+    /// 入口Assembly所在的目录
+    base_dir: Option<PathBuf>,
+    /// 用户通过add_probing_path添加的目录
+    user_dirs: Vec<PathBuf>,
+    /// 从PROBING_PATHS_ENV读取的目录
+    env_dirs: Vec<PathBuf>,
+    /// 自动发现的Microsoft.NETCore.App目录
+    framework_dir: Option<PathBuf>,
+    /// 入口Assembly引用的System.Runtime的(主版本号, 次版本号)，即期望的框架版本
+    framework_version: Option<(u16, u16)>,
+}
+
+impl PathResolver {
+    /// 额外探测目录的环境变量，格式和PATH相同
+    pub const PROBING_PATHS_ENV: &'static str = "IL_RUNTIME_PROBING_PATHS";
+    const FRAMEWORK_NAME: &'static str = "Microsoft.NETCore.App";
+    const EXTENSIONS: [&'static str; 2] = ["dll", "exe"];
+
+    /// 以入口Assembly的路径创建，会读取环境变量并按照policy自动寻找共享框架
+    /// framework_version为期望的框架(主版本号, 次版本号)，None表示使用最高版本
+    pub fn new(entry_assembly_path: &str, framework_version: Option<(u16, u16)>, policy: BindingPolicy) -> PathResolver {
+        let base_dir = Path::new(entry_assembly_path).parent().map(|p| {
+            if p.as_os_str().is_empty() {
+                PathBuf::from(".")
+            } else {
+                p.to_path_buf()
+            }
+        });
+        let env_dirs = match env::var_os(Self::PROBING_PATHS_ENV) {
+            Some(paths) => env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()).collect(),
+            None => Vec::new(),
+        };
+        PathResolver {
+            base_dir,
+            user_dirs: Vec::new(),
+            env_dirs,
+            framework_dir: Self::discover_framework_dir(framework_version, policy),
+            framework_version,
+        }
+    }
+
+    /// 绑定策略改变后重新选择共享框架，保证选中的框架中的System.Runtime能通过版本检查
+    pub fn set_binding_policy(&mut self, policy: BindingPolicy) {
+        self.framework_dir = Self::discover_framework_dir(self.framework_version, policy);
+    }
+
+    pub fn add_probing_path<P: Into<PathBuf>>(&mut self, path: P) {
+        self.user_dirs.push(path.into());
+    }
+
+    /// 手动指定共享框架目录，覆盖自动发现的结果
+    pub fn set_framework_dir<P: Into<PathBuf>>(&mut self, path: P) {
+        self.framework_dir = Some(path.into());
+    }
+
+    /// 按探测顺序返回所有目录
+    pub fn probing_dirs(&self) -> Vec<&Path> {
+        self.base_dir.iter()
+            .chain(self.user_dirs.iter())
+            .chain(self.env_dirs.iter())
+            .chain(self.framework_dir.iter())
+            .map(|p| p.as_path())
+            .collect()
+    }
+
+    /// 返回这个Assembly会被探测的所有候选路径
+    pub fn candidate_paths(&self, assembly_name: &AssemblyName) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for dir in self.probing_dirs() {
+            for ext in Self::EXTENSIONS.iter() {
+                paths.push(dir.join(format!("{}.{}", assembly_name.name, ext)));
+            }
+        }
+        paths
+    }
+
+    /// 找到第一个存在的候选文件，找不到时错误信息中列出所有探测过的路径
+    pub fn resolve_path(&self, assembly_name: &AssemblyName) -> io::Result<PathBuf> {
+        self.resolve_paths(assembly_name).map(|mut paths| paths.swap_remove(0))
+    }
+
+    /// 按探测顺序返回所有存在的候选文件，一个也没有时错误信息中列出所有探测过的路径
+    pub fn resolve_paths(&self, assembly_name: &AssemblyName) -> io::Result<Vec<PathBuf>> {
+        let candidates = self.candidate_paths(assembly_name);
+        let existing = candidates.iter().filter(|path| path.is_file()).cloned().collect::<Vec<_>>();
+        if existing.is_empty() {
+            return Err(Self::not_found_error(assembly_name, &candidates));
+        }
+        Ok(existing)
+    }
+
+    pub fn not_found_error(assembly_name: &AssemblyName, probed: &[PathBuf]) -> io::Error {
+        let mut message = format!("Could not resolve assembly '{}'. Probed paths:", assembly_name);
+        if probed.is_empty() {
+            message.push_str(" (none)");
+        }
+        for path in probed {
+            message.push_str("\n    ");
+            message.push_str(&path.to_string_lossy());
+        }
+        io::Error::new(io::ErrorKind::NotFound, message)
+    }
+
+    /// 可能安装了dotnet的根目录，顺序即优先级
+    fn dotnet_roots() -> Vec<PathBuf> {
+        let mut roots = Vec::new();
+        if let Some(root) = env::var_os("DOTNET_ROOT") {
+            roots.push(PathBuf::from(root));
+        }
+        if cfg!(windows) {
+            if let Some(program_files) = env::var_os("ProgramFiles") {
+                roots.push(Path::new(&program_files).join("dotnet"));
+            }
+            roots.push(PathBuf::from(r"C:\Program Files\dotnet"));
+        } else {
+            roots.push(PathBuf::from("/usr/share/dotnet"));
+            roots.push(PathBuf::from("/usr/lib/dotnet"));
+            roots.push(PathBuf::from("/usr/local/share/dotnet"));
+        }
+        if let Some(home) = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+            roots.push(Path::new(&home).join(".dotnet"));
+        }
+        roots
+    }
+
+    /// 在所有dotnet根目录下寻找shared/Microsoft.NETCore.App/<version>，见select_framework
+    pub fn discover_framework_dir(framework_version: Option<(u16, u16)>, policy: BindingPolicy) -> Option<PathBuf> {
+        let mut candidates = Vec::new();
+        for root in Self::dotnet_roots() {
+            let shared = root.join("shared").join(Self::FRAMEWORK_NAME);
+            let entries = match fs::read_dir(&shared) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                if let Some(version) = path.file_name().and_then(|n| n.to_str()).and_then(Self::parse_framework_version) {
+                    candidates.push((version, path));
+                }
+            }
+        }
+        Self::select_framework(candidates, framework_version, policy)
+    }
+
+    /// 框架中System.Runtime的版本为<主版本号>.<次版本号>.0.0，所以按照和绑定相同的规则筛选，再选择其中的最高版本
+    /// Exact要求主次版本号都相同，RollForwardMinor要求主版本号相同并且次版本号不低于引用，
+    /// IgnoreVersion优先选择主版本号相同的，没有的话选择更高主版本中的最高版本
+    fn select_framework(mut candidates: Vec<((u16, u16, u16), PathBuf)>, framework_version: Option<(u16, u16)>, policy: BindingPolicy) -> Option<PathBuf> {
+        if let Some((major, minor)) = framework_version {
+            match policy {
+                BindingPolicy::Exact => candidates.retain(|(v, _)| v.0 == major && v.1 == minor),
+                BindingPolicy::RollForwardMinor => candidates.retain(|(v, _)| v.0 == major && v.1 >= minor),
+                BindingPolicy::IgnoreVersion => {
+                    candidates.retain(|(v, _)| v.0 >= major);
+                    if candidates.iter().any(|(v, _)| v.0 == major) {
+                        candidates.retain(|(v, _)| v.0 == major);
+                    }
+                },
+            }
+        }
+        // 同一版本在多个根目录出现时，保留先出现的（优先级更高）
+        candidates.into_iter().rev().max_by_key(|(v, _)| *v).map(|(_, p)| p)
+    }
+
+    /// 解析形如5.0.11的目录名，预览版（5.0.0-rc.1）不参与选择
+    fn parse_framework_version(name: &str) -> Option<(u16, u16, u16)> {
+        if name.contains('-') {
+            return None;
+        }
+        let mut parts = name.split('.').map(|s| s.parse::<u16>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = parts.next()?.ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some((major, minor, patch))
+    }
+}
+
+impl AssemblyResolver for PathResolver {
+    fn resolve(&self, assembly_name: &AssemblyName) -> io::Result<ResolvedAssembly> {
+        self.resolve_path(assembly_name).map(ResolvedAssembly::Path)
+    }
+
+    fn resolve_all(&self, assembly_name: &AssemblyName) -> io::Result<Vec<ResolvedAssembly>> {
+        Ok(self.resolve_paths(assembly_name)?.into_iter().map(ResolvedAssembly::Path).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use super::super::Interpreter;
+
+    const TEST_IMAGE: &[u8] = include_bytes!("../../ILAssembly/TestCsharp.dll");
+
+    fn read_cell(assembly: &Assembly, table: usize, row: usize, column: usize) -> u32 {
+        assembly.metadata.table_stream.md_tables[table].columns[column].get_cell_u16_or_u32(row as u32)
+    }
+
+    /// 直接修改镜像中元数据表的一个单元格
+    fn patch_cell(image: &mut [u8], assembly: &Assembly, table: usize, row: usize, column: usize, value: u32) {
+        let table = &assembly.metadata.table_stream.md_tables[table];
+        let column = &table.columns[column];
+        let position = table.position + row * table.row_size as usize + column.offset as usize;
+        image[position..position + column.size as usize].copy_from_slice(&value.to_le_bytes()[..column.size as usize]);
+    }
+
+    /// 返回(入口镜像, 冒充System.Runtime的镜像, 入口中被改为指向TestCsharp.Program的TypeRef的token)
+    /// 入口中System.Object的TypeRef被改为System.Runtime中的TestCsharp.Program，库镜像的Assembly行复制自入口对System.Runtime的引用
+    fn cross_assembly_images() -> (Vec<u8>, Vec<u8>, u32) {
+        let original = Assembly::from_bytes(TEST_IMAGE.to_vec(), false).unwrap();
+        let assembly_ref_index = original.assembly_refs.iter().position(|r| r.assembly_name.name == "System.Runtime").unwrap();
+        let type_ref_index = original.type_refs.key_get_index(&String::from("System.Object")).unwrap();
+        let program_index = original.type_defs.key_get_index(&String::from("TestCsharp.Program")).unwrap();
+
+        let mut entry = TEST_IMAGE.to_vec();
+        patch_cell(&mut entry, &original, 0x01, type_ref_index, 0, ((assembly_ref_index as u32 + 1) << 2) | 2);  // ResolutionScope: AssemblyRef
+        patch_cell(&mut entry, &original, 0x01, type_ref_index, 1, read_cell(&original, 0x02, program_index, 1));
+        patch_cell(&mut entry, &original, 0x01, type_ref_index, 2, read_cell(&original, 0x02, program_index, 2));
+
+        // AssemblyRef的版本、flags、公钥、名称和culture正好对应Assembly表的第1到8列
+        let mut library = TEST_IMAGE.to_vec();
+        for column in 0..8 {
+            patch_cell(&mut library, &original, 0x20, 0, column + 1, read_cell(&original, 0x23, assembly_ref_index, column));
+        }
+        (entry, library, 0x01000001 + type_ref_index as u32)
+    }
+
+    fn assert_resolved_to_library(interpreter: &mut Interpreter, type_ref_token: u32) {
+        let (assembly_index, type_def_index) = interpreter.resolve_type(0, type_ref_token).unwrap();
+        assert_ne!(assembly_index, 0);
+        assert_eq!(interpreter.assemblies.index_get(assembly_index).unwrap().assembly_name.name, "System.Runtime");
+        assert_eq!(interpreter.get_type_def_full_name((assembly_index, type_def_index)), "TestCsharp.Program");
+    }
+
+    #[test]
+    fn memory_resolver_resolves_type_ref_across_assemblies() {
+        let (entry, library, type_ref_token) = cross_assembly_images();
+        let mut memory = MemoryResolver::new();
+        memory.insert("System.Runtime", library);
+        let mut interpreter = Interpreter::from_assembly(Assembly::from_bytes(entry, false).unwrap());
+        interpreter.add_resolver(Box::new(memory));
+        assert_resolved_to_library(&mut interpreter, type_ref_token);
+    }
+
+    #[test]
+    fn closure_resolver_provides_loaded_assembly() {
+        let (entry, library, type_ref_token) = cross_assembly_images();
+        let library = RefCell::new(Some(Box::new(Assembly::from_bytes(library, false).unwrap())));
+        let mut interpreter = Interpreter::from_assembly(Assembly::from_bytes(entry, false).unwrap());
+        interpreter.add_resolver(Box::new(move |_: &AssemblyName| library.borrow_mut().take().map(ResolvedAssembly::Assembly)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "already provided"))));
+        assert_resolved_to_library(&mut interpreter, type_ref_token);
+    }
+
+    #[test]
+    fn memory_resolver_reports_unknown_assembly_as_not_found() {
+        let original = Assembly::from_bytes(TEST_IMAGE.to_vec(), false).unwrap();
+        let error = MemoryResolver::new().resolve(&original.assembly_name).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn select_framework_follows_binding_policy() {
+        let candidates = || ["3.1.20", "5.0.11", "5.0.17", "5.2.0", "6.0.5"].iter()
+            .map(|v| (PathResolver::parse_framework_version(v).unwrap(), PathBuf::from(v)))
+            .collect::<Vec<_>>();
+        let select = |version, policy| PathResolver::select_framework(candidates(), version, policy);
+
+        assert_eq!(select(Some((5, 0)), BindingPolicy::Exact), Some(PathBuf::from("5.0.17")));
+        assert_eq!(select(Some((5, 1)), BindingPolicy::Exact), None);
+        assert_eq!(select(Some((5, 1)), BindingPolicy::RollForwardMinor), Some(PathBuf::from("5.2.0")));
+        assert_eq!(select(Some((7, 0)), BindingPolicy::RollForwardMinor), None);
+        assert_eq!(select(Some((5, 3)), BindingPolicy::IgnoreVersion), Some(PathBuf::from("5.2.0")));
+        assert_eq!(select(Some((4, 0)), BindingPolicy::IgnoreVersion), Some(PathBuf::from("6.0.5")));
+        assert_eq!(select(None, BindingPolicy::Exact), Some(PathBuf::from("6.0.5")));
+        assert_eq!(PathResolver::parse_framework_version("6.0.0-rc.1"), None);
+    }
+}
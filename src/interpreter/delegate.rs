@@ -0,0 +1,108 @@
+use super::il_type::{ILFnPtr, ILType};
+
+/// 委托的调用列表中的一项
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelegateEntry {
+    /// 封闭委托绑定的第一个参数，即实例方法的this或者静态方法的第一个参数，开放委托为None
+    pub target: Option<ILType>,
+    pub method: ILFnPtr,
+}
+
+/// Delegate.Remove：从source中移除最后一次出现的value的整个调用列表，没有出现时返回None
+pub fn remove_invocation_list(source: &[DelegateEntry], value: &[DelegateEntry]) -> Option<Vec<DelegateEntry>> {
+    if value.is_empty() || value.len() > source.len() {
+        return None;
+    }
+    let start = (0..=source.len() - value.len()).rev().find(|start| source[*start..*start + value.len()] == *value)?;
+    let mut remaining = source[..start].to_vec();
+    remaining.extend_from_slice(&source[start + value.len()..]);
+    Some(remaining)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::op_codes::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+
+    #[test]
+    fn delegates_bind_targets_combine_remove_and_dispatch_virtual_methods() {
+        // class A { int v; A(int v); int Get() { Program.log = Program.log * 10 + v; return v; } virtual int Virt() => 1; static int StaticGet(A a) => a.v; }
+        // class B : A { override int Virt() => 2; }
+        // delegate int F(); delegate int G(A a);
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let delegate = builder.cor_lib_type("System", "Delegate");
+        let multicast_delegate = builder.cor_lib_type("System", "MulticastDelegate");
+        let combine = builder.member_ref(delegate, "Combine", sig::method(false, sig::class(delegate), vec![sig::class(delegate), sig::class(delegate)]));
+        let remove = builder.member_ref(delegate, "Remove", sig::method(false, sig::class(delegate), vec![sig::class(delegate), sig::class(delegate)]));
+
+        let a = builder.define_type("", "A", CLASS, object);
+        let v = builder.field(FIELD_PUBLIC, "v", sig::int32());
+        let a_ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![sig::int32()]));
+        builder.body(a_ctor, vec![], Code::new().op(OpCode::Ldarg0).op(OpCode::Ldarg1).op_token(OpCode::Stfld, v).op(OpCode::Ret));
+        let get = builder.method(PUBLIC | HIDE_BY_SIG, "Get", sig::method(true, sig::int32(), vec![]));
+        let virt = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Virt", sig::method(true, sig::int32(), vec![]));
+        builder.body(virt, vec![], Code::new().op(OpCode::Ldci41).op(OpCode::Ret));
+        let static_get = builder.method(PUBLIC | STATIC, "StaticGet", sig::method(false, sig::int32(), vec![sig::class(a)]));
+        builder.body(static_get, vec![], Code::new().op(OpCode::Ldarg0).op_token(OpCode::Ldfld, v).op(OpCode::Ret));
+        builder.define_type("", "B", CLASS, a);
+        let b_ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![]));
+        builder.body(b_ctor, vec![], Code::new().op(OpCode::Ret));
+        let b_virt = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL, "Virt", sig::method(true, sig::int32(), vec![]));
+        builder.body(b_virt, vec![], Code::new().op(OpCode::Ldci42).op(OpCode::Ret));
+
+        let f = builder.define_type("", "F", SEALED_CLASS, multicast_delegate);
+        let f_ctor = builder.method_without_body(RUNTIME, CTOR, ".ctor", sig::method(true, sig::void(), vec![sig::object(), sig::native_int()]));
+        let f_invoke = builder.method_without_body(RUNTIME, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Invoke", sig::method(true, sig::int32(), vec![]));
+        builder.define_type("", "G", SEALED_CLASS, multicast_delegate);
+        let g_ctor = builder.method_without_body(RUNTIME, CTOR, ".ctor", sig::method(true, sig::void(), vec![sig::object(), sig::native_int()]));
+        let g_invoke = builder.method_without_body(RUNTIME, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Invoke", sig::method(true, sig::int32(), vec![sig::class(a)]));
+
+        builder.define_type("", "Program", CLASS, object);
+        let log = builder.field(FIELD_PUBLIC | FIELD_STATIC, "log", sig::int32());
+        builder.body(get, vec![], Code::new().op_token(OpCode::Ldsfld, log).ldc_i4(10).op(OpCode::Mul).op(OpCode::Ldarg0).op_token(OpCode::Ldfld, v)
+            .op(OpCode::Add).op_token(OpCode::Stsfld, log).op(OpCode::Ldarg0).op_token(OpCode::Ldfld, v).op(OpCode::Ret));
+
+        // new F(new A(value), &A.Get)
+        let new_f = |code: Code, value: i32| code.ldc_i4(value).op_token(OpCode::Newobj, a_ctor).op2_token(OpCode2::Ldftn, get).op_token(OpCode::Newobj, f_ctor);
+        let combine = |code: Code| code.op_token(OpCode::Call, combine).op_token(OpCode::Castclass, f);
+        // F f1 = new F(new A(1), &A.Get), f2 = new F(new A(2), &A.Get); F f = f1 + f2 + f1;
+        let multicast = |code: Code| {
+            let code = new_f(new_f(code, 1).op(OpCode::Stloc0), 2).op(OpCode::Stloc1);
+            combine(combine(code.op(OpCode::Ldloc0).op(OpCode::Ldloc1)).op(OpCode::Ldloc0))
+        };
+        // log = 0; int r = f.Invoke(); return log * 10 + r;
+        let invoke_logged = |code: Code| code.op(OpCode::Ldci40).op_token(OpCode::Stsfld, log).op_token(OpCode::Callvirt, f_invoke)
+            .op_token(OpCode::Ldsfld, log).ldc_i4(10).op(OpCode::Mul).op(OpCode::Add);
+        let codes = vec![
+            // 封闭的实例方法委托
+            new_f(Code::new(), 3).op_token(OpCode::Callvirt, f_invoke),
+            // 开放的静态方法委托：new G(null, &A.StaticGet).Invoke(new A(4))
+            Code::new().op(OpCode::Ldnull).op2_token(OpCode2::Ldftn, static_get).op_token(OpCode::Newobj, g_ctor)
+                .ldc_i4(4).op_token(OpCode::Newobj, a_ctor).op_token(OpCode::Callvirt, g_invoke),
+            // 封闭在第一个参数上的静态方法委托：new F(new A(5), &A.StaticGet).Invoke()
+            Code::new().ldc_i4(5).op_token(OpCode::Newobj, a_ctor).op2_token(OpCode2::Ldftn, static_get).op_token(OpCode::Newobj, f_ctor)
+                .op_token(OpCode::Callvirt, f_invoke),
+            // 多播按顺序调用f1、f2、f1，返回最后一个的结果
+            invoke_logged(multicast(Code::new())),
+            // Remove移除最后一次出现的f1，剩下f1、f2
+            invoke_logged(multicast(Code::new()).op(OpCode::Ldloc0).op_token(OpCode::Call, remove).op_token(OpCode::Castclass, f)),
+            // new F(b, ldvirtftn A.Virt)调用B的重写，ldftn则调用A.Virt本身
+            Code::new().op_token(OpCode::Newobj, b_ctor).op(OpCode::Dup).op2_token(OpCode2::Ldvirtftn, virt).op_token(OpCode::Newobj, f_ctor)
+                .op_token(OpCode::Callvirt, f_invoke),
+            Code::new().op_token(OpCode::Newobj, b_ctor).op2_token(OpCode2::Ldftn, virt).op_token(OpCode::Newobj, f_ctor)
+                .op_token(OpCode::Callvirt, f_invoke),
+        ];
+        let mains = codes.into_iter().enumerate().map(|(i, code)| {
+            let main = builder.method(PUBLIC | STATIC, &format!("Main{}", i), sig::method(false, sig::int32(), vec![]));
+            builder.body(main, vec![sig::class(f), sig::class(f)], code.op(OpCode::Ret));
+            main
+        }).collect::<Vec<_>>();
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let results = mains.into_iter().map(|main| {
+            let result = call(&mut interpreter, main).unwrap().unwrap();
+            interpreter.format_il_type(&result)
+        }).collect::<Vec<_>>();
+        assert_eq!(results, ["3", "4", "5", "1211", "122", "2", "1"]);
+    }
+}
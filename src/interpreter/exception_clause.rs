@@ -0,0 +1,213 @@
+use std::io;
+
+use super::data_reader::DataReader;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExceptionClauseKind {
+    /// catch块，参数为捕获的类型（TypeDef、TypeRef或TypeSpec）
+    Catch(u32),
+    /// filter块，参数为filter代码相对于方法体起始的偏移
+    Filter(usize),
+    Finally,
+    Fault,
+}
+
+/// 方法体中的一个异常处理子句，所有偏移都相对于方法体起始
+#[derive(Debug, Clone, Copy)]
+pub struct ExceptionClause {
+    pub kind: ExceptionClauseKind,
+    pub try_offset: usize,
+    pub try_length: usize,
+    pub handler_offset: usize,
+    pub handler_length: usize,
+}
+
+impl ExceptionClause {
+    const SECTION_EH_TABLE: u8 = 0x01;
+    const SECTION_FAT_FORMAT: u8 = 0x40;
+    const SECTION_MORE_SECTS: u8 = 0x80;
+
+    /// 读取紧跟在IL代码之后的额外数据段（4字节对齐），只保留异常处理表
+    pub fn read_sections(reader: &mut DataReader, code_end: usize) -> io::Result<Vec<ExceptionClause>> {
+        let mut clauses = Vec::new();
+        let mut position = code_end;
+        loop {
+            position = (position + 3) & !3;
+            reader.set_position(position)?;
+            let kind = reader.read_u8()?;
+            let data_size;
+            let is_fat = kind & Self::SECTION_FAT_FORMAT != 0;
+            if is_fat {
+                let mut size = [0u8; 3];
+                reader.read_bytes(&mut size)?;
+                data_size = size[0] as usize | (size[1] as usize) << 8 | (size[2] as usize) << 16;
+            } else {
+                data_size = reader.read_u8()? as usize;
+                reader.advance(2)?;  // Reserved
+            }
+            if data_size < 4 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid method data section"));
+            }
+
+            if kind & Self::SECTION_EH_TABLE != 0 {
+                if is_fat {
+                    for _ in 0..(data_size - 4) / 24 {
+                        let flags = reader.read_u32()?;
+                        let try_offset = reader.read_u32()? as usize;
+                        let try_length = reader.read_u32()? as usize;
+                        let handler_offset = reader.read_u32()? as usize;
+                        let handler_length = reader.read_u32()? as usize;
+                        let token_or_offset = reader.read_u32()?;
+                        clauses.push(Self::new(flags, try_offset, try_length, handler_offset, handler_length, token_or_offset)?);
+                    }
+                } else {
+                    for _ in 0..(data_size - 4) / 12 {
+                        let flags = reader.read_u16()? as u32;
+                        let try_offset = reader.read_u16()? as usize;
+                        let try_length = reader.read_u8()? as usize;
+                        let handler_offset = reader.read_u16()? as usize;
+                        let handler_length = reader.read_u8()? as usize;
+                        let token_or_offset = reader.read_u32()?;
+                        clauses.push(Self::new(flags, try_offset, try_length, handler_offset, handler_length, token_or_offset)?);
+                    }
+                }
+            }
+
+            if kind & Self::SECTION_MORE_SECTS == 0 {
+                return Ok(clauses);
+            }
+            position += data_size;
+        }
+    }
+
+    fn new(flags: u32, try_offset: usize, try_length: usize, handler_offset: usize, handler_length: usize, token_or_offset: u32) -> io::Result<ExceptionClause> {
+        let kind = match flags & 0x7 {
+            0 => ExceptionClauseKind::Catch(token_or_offset),
+            1 => ExceptionClauseKind::Filter(token_or_offset as usize),
+            2 => ExceptionClauseKind::Finally,
+            4 => ExceptionClauseKind::Fault,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid exception clause flags: 0x{:X}", flags))),
+        };
+        Ok(ExceptionClause {
+            kind,
+            try_offset,
+            try_length,
+            handler_offset,
+            handler_length,
+        })
+    }
+
+    pub fn try_contains(&self, offset: usize) -> bool {
+        offset >= self.try_offset && offset < self.try_offset + self.try_length
+    }
+
+    pub fn handler_contains(&self, offset: usize) -> bool {
+        offset >= self.handler_offset && offset < self.handler_offset + self.handler_length
+    }
+
+    /// 如果是filter子句，返回filter代码的范围，filter代码总是紧挨着handler之前
+    pub fn filter_range(&self) -> Option<(usize, usize)> {
+        match self.kind {
+            ExceptionClauseKind::Filter(filter_offset) => Some((filter_offset, self.handler_offset)),
+            _ => None,
+        }
+    }
+
+    pub fn is_finally_or_fault(&self) -> bool {
+        matches!(self.kind, ExceptionClauseKind::Finally | ExceptionClauseKind::Fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在5字节的IL代码之后放入额外数据段，第一个段从对齐后的8开始
+    fn read(sections: &[u8]) -> io::Result<Vec<ExceptionClause>> {
+        let mut image = vec![0u8; 8];
+        image.extend_from_slice(sections);
+        ExceptionClause::read_sections(&mut DataReader::new(image), 5)
+    }
+
+    fn small_clause(flags: u16, try_offset: u16, try_length: u8, handler_offset: u16, handler_length: u8, token_or_offset: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&try_offset.to_le_bytes());
+        bytes.push(try_length);
+        bytes.extend_from_slice(&handler_offset.to_le_bytes());
+        bytes.push(handler_length);
+        bytes.extend_from_slice(&token_or_offset.to_le_bytes());
+        bytes
+    }
+
+    fn fat_clause(values: [u32; 6]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn small_section(kind: u8, clauses: &[Vec<u8>]) -> Vec<u8> {
+        let body = clauses.concat();
+        let mut bytes = vec![kind, (body.len() + 4) as u8, 0, 0];
+        bytes.extend(body);
+        bytes
+    }
+
+    fn fat_section(kind: u8, clauses: &[Vec<u8>]) -> Vec<u8> {
+        let body = clauses.concat();
+        let size = (body.len() + 4) as u32;
+        let mut bytes = vec![kind | ExceptionClause::SECTION_FAT_FORMAT, size as u8, (size >> 8) as u8, (size >> 16) as u8];
+        bytes.extend(body);
+        bytes
+    }
+
+    #[test]
+    fn reads_small_clauses() {
+        let clauses = read(&small_section(ExceptionClause::SECTION_EH_TABLE, &[
+            small_clause(0, 0x01, 0x04, 0x05, 0x02, 0x01000005),
+            small_clause(2, 0x01, 0x06, 0x07, 0x01, 0),
+        ])).unwrap();
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(clauses[0].kind, ExceptionClauseKind::Catch(0x01000005));
+        assert_eq!((clauses[0].try_offset, clauses[0].try_length, clauses[0].handler_offset, clauses[0].handler_length), (1, 4, 5, 2));
+        assert_eq!(clauses[1].kind, ExceptionClauseKind::Finally);
+        assert!(clauses[1].is_finally_or_fault() && !clauses[0].is_finally_or_fault());
+    }
+
+    #[test]
+    fn reads_fat_section_followed_by_more_sections() {
+        let mut sections = fat_section(ExceptionClause::SECTION_EH_TABLE | ExceptionClause::SECTION_MORE_SECTS, &[
+            fat_clause([1, 0x10, 0x200, 0x220, 0x30, 0x210]),
+        ]);
+        sections.extend(small_section(ExceptionClause::SECTION_EH_TABLE, &[small_clause(4, 0x10, 0x20, 0x30, 0x08, 0)]));
+        let clauses = read(&sections).unwrap();
+        assert_eq!(clauses.len(), 2);
+        assert_eq!(clauses[0].kind, ExceptionClauseKind::Filter(0x210));
+        assert_eq!(clauses[0].filter_range(), Some((0x210, 0x220)));
+        assert_eq!((clauses[0].try_offset, clauses[0].try_length), (0x10, 0x200));
+        assert_eq!(clauses[1].kind, ExceptionClauseKind::Fault);
+        assert_eq!(clauses[1].filter_range(), None);
+    }
+
+    #[test]
+    fn skips_sections_that_are_not_eh_tables() {
+        let mut sections = small_section(ExceptionClause::SECTION_MORE_SECTS, &[vec![0; 4]]);
+        sections.extend(small_section(ExceptionClause::SECTION_EH_TABLE, &[small_clause(2, 0, 1, 1, 1, 0)]));
+        let clauses = read(&sections).unwrap();
+        assert_eq!(clauses.len(), 1);
+        assert_eq!(clauses[0].kind, ExceptionClauseKind::Finally);
+    }
+
+    #[test]
+    fn rejects_invalid_clause_flags_and_section_size() {
+        let error = read(&small_section(ExceptionClause::SECTION_EH_TABLE, &[small_clause(3, 0, 1, 1, 1, 0)])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = read(&[ExceptionClause::SECTION_EH_TABLE, 2, 0, 0]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn ranges_are_half_open() {
+        let clause = ExceptionClause::new(0, 2, 3, 5, 4, 0x01000001).unwrap();
+        assert!(!clause.try_contains(1) && clause.try_contains(2) && clause.try_contains(4) && !clause.try_contains(5));
+        assert!(!clause.handler_contains(4) && clause.handler_contains(5) && clause.handler_contains(8) && !clause.handler_contains(9));
+    }
+}
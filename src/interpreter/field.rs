@@ -10,12 +10,22 @@ pub struct Field {
     pub signature: Option<CallingConventionSig>,
     /// 字段所属类型，加上0x02000001就是对应的类型
     pub owner_type: u32,
+    /// FieldRVA表中记录的初始数据位置，没有时为0
+    pub rva: u32,
 }
 
 impl Field {
     pub fn read_fields(metadata: &Metadata, field_to_type_map: Vec<u32>) -> io::Result<Vec<Field>> {
         let mut fields = Vec::new();
         let field_table = &metadata.table_stream.md_tables[4];
+        let mut rvas = vec![0u32; field_table.row_count as usize];
+        let field_rva_table = &metadata.table_stream.md_tables[0x1D];
+        for row in 0..field_rva_table.row_count {
+            let field_rid = field_rva_table.columns[1].get_cell_u16_or_u32(row) as usize;
+            if field_rid >= 1 && field_rid <= rvas.len() {
+                rvas[field_rid - 1] = field_rva_table.columns[0].get_cell_u32(row);
+            }
+        }
         let mut type_map_index = 0;
         for row in 0..field_table.row_count {
             if type_map_index < field_to_type_map.len() {
@@ -37,6 +47,7 @@ impl Field {
                 name,
                 signature,
                 owner_type: type_map_index as u32 - 1,
+                rva: rvas[row as usize],
             });
         }
 
@@ -0,0 +1,166 @@
+use std::io;
+
+use super::metadata::{Metadata, md_token::CodedToken, table_stream::MDType};
+
+/// 泛型类型或者泛型方法的类型参数
+#[derive(Debug)]
+pub struct GenericParam {
+    /// 形如0x2A000001
+    pub token: u32,
+    /// 在所属类型或方法的类型参数中的序号，即!n或!!n中的n
+    pub number: u16,
+    /// 协变、逆变以及class、struct、new()这些特殊约束
+    pub flags: u16,
+    /// 所属的TypeDef或者MethodDef的token
+    pub owner: u32,
+    pub name: String,
+    /// 约束的类型，TypeDef、TypeRef或TypeSpec的token
+    pub constraints: Vec<u32>,
+}
+
+impl GenericParam {
+    pub fn read_generic_params(metadata: &Metadata) -> io::Result<Vec<GenericParam>> {
+        let mut generic_params = Vec::new();
+        let generic_param_table = &metadata.table_stream.md_tables[0x2A];
+        for row in 0..generic_param_table.row_count {
+            let owner = CodedToken::from_md_type(MDType::TypeOrMethodDef).decode(generic_param_table.columns[2].get_cell_u16_or_u32(row)).unwrap();
+
+            generic_params.push(GenericParam {
+                token: 0x2A000001 + row,
+                number: generic_param_table.columns[0].get_cell_u16(row),
+                flags: generic_param_table.columns[1].get_cell_u16(row),
+                owner,
+                name: metadata.strings_stream.get_string_clone(generic_param_table.columns[3].get_cell_u16_or_u32(row))?,
+                constraints: Vec::new(),
+            });
+        }
+
+        let constraint_table = &metadata.table_stream.md_tables[0x2C];
+        for row in 0..constraint_table.row_count {
+            let owner = constraint_table.columns[0].get_cell_u16_or_u32(row);
+            let constraint = CodedToken::from_md_type(MDType::TypeDefOrRef).decode(constraint_table.columns[1].get_cell_u16_or_u32(row)).unwrap();
+            // owner是GenericParam表的行号
+            if let Some(generic_param) = generic_params.iter_mut().find(|generic_param| generic_param.token == 0x2A000000 | owner) {
+                generic_param.constraints.push(constraint);
+            }
+        }
+
+        Ok(generic_params)
+    }
+
+    pub fn is_covariant(&self) -> bool {
+        self.flags & 0x0003 == 0x0001
+    }
+
+    pub fn is_contravariant(&self) -> bool {
+        self.flags & 0x0003 == 0x0002
+    }
+
+    /// where T : class
+    pub fn has_reference_type_constraint(&self) -> bool {
+        self.flags & 0x0004 != 0
+    }
+
+    /// where T : struct
+    pub fn has_not_nullable_value_type_constraint(&self) -> bool {
+        self.flags & 0x0008 != 0
+    }
+
+    /// where T : new()
+    pub fn has_default_constructor_constraint(&self) -> bool {
+        self.flags & 0x0010 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Assembly, Interpreter, MemoryResolver, RuntimeErrorKind, runtime_type::{GenericContext, RuntimeType}};
+    use super::super::test_assembly::{cor_lib, COR_LIB_NAME};
+
+    const TEST_IMAGE: &[u8] = include_bytes!("../../ILAssembly/TestCsharp.dll");
+
+    fn generic_param(number: u16, flags: u16, owner: u32, constraints: Vec<u32>) -> GenericParam {
+        GenericParam { token: 0x2A000001 + number as u32, number, flags, owner, name: format!("T{}", number), constraints }
+    }
+
+    /// 把TestCsharp.Class0当作泛型类型Class0<T0>，params为它的类型参数
+    fn interpreter_with_generic_class0(params: impl FnOnce(u32, u32) -> Vec<GenericParam>) -> (Interpreter, usize) {
+        let mut assembly = Assembly::from_bytes(TEST_IMAGE.to_vec(), false).unwrap();
+        let class0 = assembly.type_defs.key_get_index(&String::from("TestCsharp.Class0")).unwrap();
+        let class0_token = 0x02000001 + class0 as u32;
+        let class1_token = 0x02000001 + assembly.type_defs.key_get_index(&String::from("TestCsharp.Class1")).unwrap() as u32;
+        assembly.type_defs.index_get_mut(class0).unwrap().generic_params = params(class0_token, class1_token);
+        (Interpreter::from_assembly(assembly), class0)
+    }
+
+    fn type_arg(interpreter: &Interpreter, name: &str) -> RuntimeType {
+        let index = interpreter.assemblies.index_get(0).unwrap().type_defs.key_get_index(&String::from(name)).unwrap();
+        RuntimeType::Type((0, index), vec![])
+    }
+
+    fn check(interpreter: &mut Interpreter, class0: usize, args: Vec<RuntimeType>) -> Result<(), RuntimeErrorKind> {
+        interpreter.check_instantiation(0, 0x02000001 + class0 as u32, &GenericContext::from_type_args(args)).map_err(|e| e.kind)
+    }
+
+    #[test]
+    fn special_constraints_are_checked() {
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0x0004 | 0x0010, owner, vec![])]);
+        let class1 = type_arg(&interpreter, "TestCsharp.Class1");
+        let program = type_arg(&interpreter, "TestCsharp.Program");
+        assert!(check(&mut interpreter, class0, vec![class1]).is_ok());
+        // 静态类是abstract的，没有可以调用的无参构造函数
+        assert!(matches!(check(&mut interpreter, class0, vec![program]), Err(RuntimeErrorKind::TypeLoad(message))
+            if message == "GenericArguments[0], 'TestCsharp.Program', on 'TestCsharp.Class0' violates the constraint of type parameter 'T0'."));
+
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0x0008, owner, vec![])]);
+        let class1 = type_arg(&interpreter, "TestCsharp.Class1");
+        assert!(matches!(check(&mut interpreter, class0, vec![class1]), Err(RuntimeErrorKind::TypeLoad(_))));
+    }
+
+    /// TestCsharp的类型的基类System.Object来自无法加载的System.Runtime，不满足约束的类型实参用数组，
+    /// 它的基类System.Array来自测试用的核心库
+    #[test]
+    fn type_constraints_accept_derived_types() {
+        // class Class0<T0> where T0 : Class0，Class1继承Class0
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0, owner, vec![owner])]);
+        let mut resolver = MemoryResolver::new();
+        resolver.insert(COR_LIB_NAME, cor_lib());
+        interpreter.add_resolver(Box::new(resolver));
+        let class1 = type_arg(&interpreter, "TestCsharp.Class1");
+        assert!(check(&mut interpreter, class0, vec![class1.clone()]).is_ok());
+        assert!(matches!(check(&mut interpreter, class0, vec![RuntimeType::SZArray(Box::new(class1))]), Err(RuntimeErrorKind::TypeLoad(message))
+            if message == "GenericArguments[0], 'TestCsharp.Class1[]', on 'TestCsharp.Class0' violates the constraint of type parameter 'T0'."));
+    }
+
+    #[test]
+    fn type_argument_count_must_match() {
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0, owner, vec![])]);
+        let class1 = type_arg(&interpreter, "TestCsharp.Class1");
+        assert!(matches!(check(&mut interpreter, class0, vec![class1.clone(), class1]), Err(RuntimeErrorKind::TypeLoad(_))));
+        // 含有未替换的类型参数时不检查
+        assert!(check(&mut interpreter, class0, vec![RuntimeType::Var(0)]).is_ok());
+    }
+
+    /// 反方向的转换要沿着基类找到System.Object，需要加载corlib，这里只检查不需要corlib的情况
+    #[test]
+    fn variance_follows_the_generic_param_flags() {
+        let instantiations = |interpreter: &Interpreter, class0| (
+            RuntimeType::Type((0, class0), vec![type_arg(interpreter, "TestCsharp.Class0")]),
+            RuntimeType::Type((0, class0), vec![type_arg(interpreter, "TestCsharp.Class1")]),
+        );
+
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0x0000, owner, vec![])]);
+        let (base, derived) = instantiations(&interpreter, class0);
+        assert!(!interpreter.is_variant_compatible(&derived, &base).unwrap());
+        assert!(!interpreter.is_variant_compatible(&base, &derived).unwrap());
+
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0x0001, owner, vec![])]);
+        let (base, derived) = instantiations(&interpreter, class0);
+        assert!(interpreter.is_variant_compatible(&derived, &base).unwrap());
+
+        let (mut interpreter, class0) = interpreter_with_generic_class0(|owner, _| vec![generic_param(0, 0x0002, owner, vec![])]);
+        let (base, derived) = instantiations(&interpreter, class0);
+        assert!(interpreter.is_variant_compatible(&base, &derived).unwrap());
+    }
+}
@@ -1,8 +1,10 @@
-use std::{cmp::Ordering, ops::{Add, BitAnd, BitOr, BitXor, Sub}};
+use std::{cmp::Ordering, rc::Rc, ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Not, Rem, Shl, Shr, Sub}};
+use std::fmt::{self, Display, Formatter};
 
+use crate::hash_vec::HashVec;
 use crate::interpreter::type_sig::{CorLibType, TypeSig};
 
-use super::calling_convention_sig::CallingConventionSig;
+use super::{calling_convention_sig::CallingConventionSig, runtime_type::{GenericContext, RuntimeType}};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ILValType {
@@ -82,6 +84,41 @@ impl ILValType {
     }
 }
 
+impl ILValType {
+    /// 在内存中占用的字节数，native int按64位计算
+    pub fn size(&self) -> usize {
+        match self {
+            ILValType::Boolean(_) | ILValType::Byte(_) | ILValType::SByte(_) => 1,
+            ILValType::Char(_) | ILValType::Short(_) | ILValType::UShort(_) => 2,
+            ILValType::Int32(_) | ILValType::UInt32(_) | ILValType::Single(_) => 4,
+            ILValType::Int64(_) | ILValType::UInt64(_) | ILValType::Double(_) | ILValType::Isize(_) | ILValType::Usize(_) => 8,
+        }
+    }
+
+    /// 按照self的类型解析小端字节，bytes的长度必须等于size()
+    pub fn read_le_bytes(&self, bytes: &[u8]) -> ILValType {
+        let mut buffer = [0u8; 8];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        let value = u64::from_le_bytes(buffer);
+        match self {
+            ILValType::Boolean(_) => ILValType::Boolean(value != 0),
+            ILValType::Byte(_) => ILValType::Byte(value as u8),
+            ILValType::SByte(_) => ILValType::SByte(value as i8),
+            ILValType::Char(_) => ILValType::Char(char::from_u32(value as u16 as u32).unwrap_or(char::REPLACEMENT_CHARACTER)),
+            ILValType::Short(_) => ILValType::Short(value as i16),
+            ILValType::UShort(_) => ILValType::UShort(value as u16),
+            ILValType::Int32(_) => ILValType::Int32(value as i32),
+            ILValType::UInt32(_) => ILValType::UInt32(value as u32),
+            ILValType::Single(_) => ILValType::Single(f32::from_bits(value as u32)),
+            ILValType::Int64(_) => ILValType::Int64(value as i64),
+            ILValType::UInt64(_) => ILValType::UInt64(value),
+            ILValType::Double(_) => ILValType::Double(f64::from_bits(value)),
+            ILValType::Isize(_) => ILValType::Isize(value as isize),
+            ILValType::Usize(_) => ILValType::Usize(value as usize),
+        }
+    }
+}
+
 impl ToString for ILValType {
     fn to_string(&self) -> String {
         match self {
@@ -109,17 +146,83 @@ pub enum ILRefType {
     Null,
     String(usize),  // 指向Strings堆
     Object(usize),  // 指向Objects堆
+    Array(usize),   // 指向Arrays堆
 }
 
-/// 表示一个托管的Ptr，可能指向Param，Local或者Static
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// 表示一个托管的Ptr，可能指向Param，Local，Static，对象的字段，数组元素，box的值或者值类型的字段
+#[derive(Debug, Clone, PartialEq)]
 pub enum ILPtr {
     /// (栈ID, index)
     Param((usize, usize)),
     /// (栈ID, index)
     Local((usize, usize)),
-    /// (Assembly_index, token)
-    Static((usize, u32)),
+    /// (字段所属类型的实例化, 字段token)
+    Static(Box<(RuntimeType, u32)>),
+    /// (Objects堆中的index, 字段rid)
+    Field((usize, u32)),
+    /// (Arrays堆中的index, 元素index)
+    Element((usize, usize)),
+    /// Objects堆中box对象的index
+    Boxed(usize),
+    /// (指向值类型实例的Ptr, 字段rid)
+    StructField(Box<(ILPtr, u32)>),
+}
+
+/// 值类型的实例，字段按值内联存放，复制ILType时整个实例一起复制
+#[derive(Debug, Clone, PartialEq)]
+pub struct ILStruct {
+    /// type_token所在Assembly的index
+    pub assembly_index: usize,
+    /// 值类型的TypeDef
+    pub type_token: u32,
+    /// <字段rid, 值>，和Object的字段相同
+    fields: HashVec<u32, ILType>,
+}
+
+impl ILStruct {
+    pub fn new(assembly_index: usize, type_token: u32, fields: HashVec<u32, ILType>) -> ILStruct {
+        ILStruct {
+            assembly_index,
+            type_token,
+            fields,
+        }
+    }
+
+    pub fn get_field(&self, field_token_or_rid: u32) -> Option<&ILType> {
+        self.fields.key_get(&(field_token_or_rid & 0x00FFFFFF))
+    }
+
+    pub fn get_field_mut(&mut self, field_token_or_rid: u32) -> Option<&mut ILType> {
+        self.fields.key_get_mut(&(field_token_or_rid & 0x00FFFFFF))
+    }
+}
+
+/// ldtoken得到的RuntimeTypeHandle、RuntimeMethodHandle或者RuntimeFieldHandle，通过token的表区分
+#[derive(Debug, Clone)]
+pub struct ILHandle {
+    pub assembly_index: usize,
+    pub token: u32,
+    /// RuntimeTypeHandle表示的类型，token中的!n和!!n已经按照ldtoken时的泛型上下文替换
+    pub runtime_type: Option<RuntimeType>,
+}
+
+impl PartialEq for ILHandle {
+    /// 类型的handle按照类型比较，同一个类型可能来自不同的token
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.runtime_type, &other.runtime_type) {
+            (Some(runtime_type), Some(other_type)) => runtime_type == other_type,
+            _ => self.assembly_index == other.assembly_index && self.token == other.token,
+        }
+    }
+}
+
+/// ldftn、ldvirtftn得到的方法指针，用作委托构造函数的IntPtr参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ILFnPtr {
+    /// (assembly_index, method_index)
+    pub method: (usize, usize),
+    /// 调用这个方法时的泛型上下文
+    pub generic: Rc<GenericContext>,
 }
 
 /// 表示一个Native Ptr，但其实不是真的指针，使用安全的方式封装
@@ -137,11 +240,47 @@ impl ILNPtr {
         }
     }
 
+    /// 越界的指针只有在解引用时才会出错，所以这里使用wrapping
     pub fn offset(&mut self, off: isize) {
-        if off.is_negative() {
-            self.offset.checked_sub(off.wrapping_abs() as usize);
-        } else {
-            self.offset.checked_add(off as usize);
+        self.offset = self.offset.wrapping_add(off as usize);
+    }
+}
+
+/// 对ILType进行运算时出现的错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum ILTypeError {
+    /// 两个操作数的类型不能进行这个运算
+    InvalidOperation(String),
+    /// 整数除以0或者对0求余
+    DivideByZero,
+    /// 整数运算的结果超出了范围，例如int.MinValue / -1
+    Overflow,
+    /// ckfinite遇到了NaN或者无穷大
+    NotFinite,
+}
+
+impl ILTypeError {
+    fn invalid(op: &str, a: &ILType, b: &ILType) -> ILTypeError {
+        ILTypeError::InvalidOperation(format!("{:?} {} {:?}", a, op, b))
+    }
+
+    fn invalid_unary(op: &str, a: &ILType) -> ILTypeError {
+        ILTypeError::InvalidOperation(format!("{}{:?}", op, a))
+    }
+
+    /// ECMA-335允许但是这里不支持的运算，reason说明原因
+    fn unsupported(op: &str, a: &ILType, b: &ILType, reason: &str) -> ILTypeError {
+        ILTypeError::InvalidOperation(format!("{:?} {} {:?}: {}", a, op, b, reason))
+    }
+}
+
+impl Display for ILTypeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ILTypeError::InvalidOperation(message) => write!(f, "Invalid Operation: {}", message),
+            ILTypeError::DivideByZero => write!(f, "Divide by zero"),
+            ILTypeError::Overflow => write!(f, "Arithmetic overflow"),
+            ILTypeError::NotFinite => write!(f, "Number is not finite"),
         }
     }
 }
@@ -152,6 +291,9 @@ pub enum ILType {
     Ref(ILRefType),
     Ptr(ILPtr),
     NPtr(ILNPtr),
+    Handle(ILHandle),
+    Struct(ILStruct),
+    FnPtr(ILFnPtr),
 }
 
 impl ILType {
@@ -162,16 +304,18 @@ impl ILType {
         }
     }
 
-    pub fn get_ref(&self) -> usize {
+    /// 返回引用指向的堆中的index，Null或者不是引用时返回None
+    pub fn get_ref(&self) -> Option<usize> {
         match self {
             ILType::Ref(ref r) => {
                 match r {
-                    ILRefType::Object(o) => *o,
-                    ILRefType::String(s) => *s,
-                    _ => panic!("Null reference exception"),
+                    ILRefType::Object(o) => Some(*o),
+                    ILRefType::String(s) => Some(*s),
+                    ILRefType::Array(a) => Some(*a),
+                    ILRefType::Null => None,
                 }
             },
-            _ => panic!("not a ref type"),
+            _ => None,
         }
     }
 
@@ -187,10 +331,11 @@ impl ILType {
             ILType::NPtr(p) => {
                 p.data.is_none()
             },
-            ILType::Ptr(_) => false,
+            ILType::Ptr(_) | ILType::Handle(_) | ILType::Struct(_) | ILType::FnPtr(_) => false,
         }
     }
 
+    /// 基元类型的默认值，其他类型都是null，值类型的默认值需要解析类型，见Interpreter::get_default_value
     pub fn from_type_sig(sig: &TypeSig) -> ILType {
         match sig {
             TypeSig::CorLibTypeSig(c) => {
@@ -208,6 +353,8 @@ impl ILType {
                     CorLibType::UInt32 => ILType::Val(ILValType::UInt32(0)),
                     CorLibType::Int64 => ILType::Val(ILValType::Int64(0)),
                     CorLibType::UInt64 => ILType::Val(ILValType::UInt64(0)),
+                    CorLibType::IntPtr => ILType::Val(ILValType::Isize(0)),
+                    CorLibType::UIntPtr => ILType::Val(ILValType::Usize(0)),
                     _ => ILType::Ref(ILRefType::Null),
                 }
             },
@@ -238,120 +385,816 @@ impl ILType {
     }
 }
 
-impl Add for ILType {
-    type Output = Self;
+/// 参与二元运算的两个操作数，已经按照ECMA-335 III.1.5 Table III.2统一为同一种求值栈类型
+enum Operands {
+    Int32(i32, i32),
+    Int64(i64, i64),
+    NativeInt(isize, isize),
+    Single(f32, f32),
+    Double(f64, f64),
+}
 
-    fn add(self, other: Self) -> Self {
-        match (self, other) {
+impl Operands {
+    fn new(a: &ILValType, b: &ILValType) -> Option<Operands> {
+        match (a.to_stack_type(), b.to_stack_type()) {
+            (ILValType::Int32(x), ILValType::Int32(y)) => Some(Operands::Int32(x, y)),
+            (ILValType::Int32(x), ILValType::Isize(y)) => Some(Operands::NativeInt(x as isize, y)),
+            (ILValType::Isize(x), ILValType::Int32(y)) => Some(Operands::NativeInt(x, y as isize)),
+            (ILValType::Isize(x), ILValType::Isize(y)) => Some(Operands::NativeInt(x, y)),
+            // int64只能和int64运算，和int32或native int混合在ECMA-335中不合法
+            (ILValType::Int64(x), ILValType::Int64(y)) => Some(Operands::Int64(x, y)),
+            (ILValType::Single(x), ILValType::Single(y)) => Some(Operands::Single(x, y)),
+            (ILValType::Single(x), ILValType::Double(y)) => Some(Operands::Double(x as f64, y)),
+            (ILValType::Double(x), ILValType::Single(y)) => Some(Operands::Double(x, y as f64)),
+            (ILValType::Double(x), ILValType::Double(y)) => Some(Operands::Double(x, y)),
+            _ => None,
+        }
+    }
+}
+
+/// 按照求值栈类型对两个数值进行运算，$int和$float分别为整数和浮点数的运算，返回Result
+macro_rules! binary_numeric {
+    ($op:expr, $a:expr, $b:expr, |$x:ident, $y:ident| $int:expr, $float:expr) => {
+        match (&$a, &$b) {
+            (ILType::Val(v1), ILType::Val(v2)) => {
+                match Operands::new(v1, v2) {
+                    Some(Operands::Int32($x, $y)) => $int.map(|r| ILType::Val(ILValType::Int32(r))),
+                    Some(Operands::Int64($x, $y)) => $int.map(|r| ILType::Val(ILValType::Int64(r))),
+                    Some(Operands::NativeInt($x, $y)) => $int.map(|r| ILType::Val(ILValType::Isize(r))),
+                    Some(Operands::Single($x, $y)) => $float.map(|r| ILType::Val(ILValType::Single(r))),
+                    Some(Operands::Double($x, $y)) => $float.map(|r| ILType::Val(ILValType::Double(r))),
+                    None => Err(ILTypeError::invalid($op, &$a, &$b)),
+                }
+            },
+            _ => Err(ILTypeError::invalid($op, &$a, &$b)),
+        }
+    };
+    // 只支持整数的运算
+    ($op:expr, $a:expr, $b:expr, |$x:ident, $y:ident| $int:expr) => {
+        match (&$a, &$b) {
+            (ILType::Val(v1), ILType::Val(v2)) => {
+                match Operands::new(v1, v2) {
+                    Some(Operands::Int32($x, $y)) => $int.map(|r| ILType::Val(ILValType::Int32(r))),
+                    Some(Operands::Int64($x, $y)) => $int.map(|r| ILType::Val(ILValType::Int64(r))),
+                    Some(Operands::NativeInt($x, $y)) => $int.map(|r| ILType::Val(ILValType::Isize(r))),
+                    _ => Err(ILTypeError::invalid($op, &$a, &$b)),
+                }
+            },
+            _ => Err(ILTypeError::invalid($op, &$a, &$b)),
+        }
+    };
+}
+
+/// 无符号的二元运算，只支持整数，$uint中的操作数已经转换为对应宽度的无符号数
+macro_rules! binary_unsigned {
+    ($op:expr, $a:expr, $b:expr, |$x:ident, $y:ident| $uint:expr) => {
+        match (&$a, &$b) {
             (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int32(i1 + i2)),
-                    (ILValType::Int32(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 as i64 + i2)),
-                    (ILValType::Int64(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int64(i1 + i2 as i64)),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 + i2)),
-                    (ILValType::Single(i1), ILValType::Single(i2)) => ILType::Val(ILValType::Single(i1 + i2)),
-                    (ILValType::Double(i1), ILValType::Double(i2)) => ILType::Val(ILValType::Double(i1 + i2)),
-                    _ => panic!("Invalid Operation")
+                match Operands::new(v1, v2) {
+                    Some(Operands::Int32(x, y)) => {
+                        let ($x, $y) = (x as u32, y as u32);
+                        $uint.map(|r| ILType::Val(ILValType::Int32(r as i32)))
+                    },
+                    Some(Operands::Int64(x, y)) => {
+                        let ($x, $y) = (x as u64, y as u64);
+                        $uint.map(|r| ILType::Val(ILValType::Int64(r as i64)))
+                    },
+                    Some(Operands::NativeInt(x, y)) => {
+                        let ($x, $y) = (x as usize, y as usize);
+                        $uint.map(|r| ILType::Val(ILValType::Isize(r as isize)))
+                    },
+                    _ => Err(ILTypeError::invalid($op, &$a, &$b)),
                 }
             },
-            (ILType::NPtr(mut p1), ILType::Val(ILValType::Int32(i2))) => {
-                p1.offset(i2 as isize);
-                ILType::NPtr(p1)
-            }
-            (ILType::NPtr(mut p1), ILType::Val(ILValType::Int64(i2))) => {
-                p1.offset(i2 as isize);
-                ILType::NPtr(p1)
-            }
-            _ => panic!("Invalid Operation")
+            _ => Err(ILTypeError::invalid($op, &$a, &$b)),
+        }
+    };
+}
+
+/// 移位运算，被移位的值可以是int32、int64或native int，移位量只能是int32或native int
+/// 移位量超过位宽时结果在ECMA-335中未定义，这里和x86一样只取低位
+macro_rules! shift {
+    ($op:expr, $value:expr, $amount:expr, |$x:ident, $n:ident| $shift:expr) => {
+        match ($amount.as_shift_amount(), &$value) {
+            (Some($n), ILType::Val(v)) => match v.to_stack_type() {
+                ILValType::Int32($x) => Ok(ILType::Val(ILValType::Int32($shift))),
+                ILValType::Int64($x) => Ok(ILType::Val(ILValType::Int64($shift))),
+                ILValType::Isize($x) => Ok(ILType::Val(ILValType::Isize($shift))),
+                _ => Err(ILTypeError::invalid($op, &$value, &$amount)),
+            },
+            _ => Err(ILTypeError::invalid($op, &$value, &$amount)),
+        }
+    };
+}
+
+/// conv系列指令的目标类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConvTarget {
+    I1,
+    I2,
+    I4,
+    I8,
+    U1,
+    U2,
+    U4,
+    U8,
+    I,
+    U,
+}
+
+impl ConvTarget {
+    /// 目标类型能表示的范围
+    fn range(&self) -> (i128, i128) {
+        match self {
+            ConvTarget::I1 => (i8::MIN as i128, i8::MAX as i128),
+            ConvTarget::I2 => (i16::MIN as i128, i16::MAX as i128),
+            ConvTarget::I4 => (i32::MIN as i128, i32::MAX as i128),
+            ConvTarget::I8 => (i64::MIN as i128, i64::MAX as i128),
+            ConvTarget::U1 => (0, u8::MAX as i128),
+            ConvTarget::U2 => (0, u16::MAX as i128),
+            ConvTarget::U4 => (0, u32::MAX as i128),
+            ConvTarget::U8 => (0, u64::MAX as i128),
+            ConvTarget::I => (isize::MIN as i128, isize::MAX as i128),
+            ConvTarget::U => (0, usize::MAX as i128),
+        }
+    }
+
+    /// 截断到目标类型后，按有无符号扩展回求值栈类型
+    fn to_stack_value(self, value: i128) -> ILType {
+        ILType::Val(match self {
+            ConvTarget::I1 => ILValType::Int32(value as i8 as i32),
+            ConvTarget::I2 => ILValType::Int32(value as i16 as i32),
+            ConvTarget::I4 => ILValType::Int32(value as i32),
+            ConvTarget::I8 => ILValType::Int64(value as i64),
+            ConvTarget::U1 => ILValType::Int32(value as u8 as i32),
+            ConvTarget::U2 => ILValType::Int32(value as u16 as i32),
+            ConvTarget::U4 => ILValType::Int32(value as u32 as i32),
+            ConvTarget::U8 => ILValType::Int64(value as u64 as i64),
+            ConvTarget::I => ILValType::Isize(value as isize),
+            ConvTarget::U => ILValType::Isize(value as usize as isize),
+        })
+    }
+}
+
+impl ILValType {
+    /// 扩展为求值栈上的类型：int32、int64、native int或者浮点数，小于32位的整数按有无符号扩展
+    /// 浮点数保留Single和Double的区别，运算时Single只和Single组合时才保持Single
+    pub fn to_stack_type(&self) -> ILValType {
+        match *self {
+            ILValType::Boolean(b) => ILValType::Int32(b as i32),
+            ILValType::Byte(b) => ILValType::Int32(b as i32),
+            ILValType::SByte(b) => ILValType::Int32(b as i32),
+            ILValType::Char(c) => ILValType::Int32(c as u32 as u16 as i32),
+            ILValType::Short(i) => ILValType::Int32(i as i32),
+            ILValType::UShort(i) => ILValType::Int32(i as i32),
+            ILValType::UInt32(i) => ILValType::Int32(i as i32),
+            ILValType::UInt64(i) => ILValType::Int64(i as i64),
+            ILValType::Usize(i) => ILValType::Isize(i as isize),
+            v => v,
+        }
+    }
+}
+
+impl ILType {
+    /// 如果是int32或者native int（包括可以扩展为int32的类型），返回其值，用于指针运算
+    fn as_offset(&self) -> Option<isize> {
+        match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => Some(i as isize),
+                ILValType::Isize(i) => Some(i),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// 托管指针加上偏移。托管的数据没有字节布局，所以只支持指向数组元素的指针，偏移以元素为单位
+    /// 越界的指针只有在解引用时才会出错
+    fn offset_ptr(op: &str, a: &ILType, b: &ILType, ptr: &ILPtr, off: isize) -> Result<ILType, ILTypeError> {
+        match ptr {
+            ILPtr::Element((array, index)) => Ok(ILType::Ptr(ILPtr::Element((*array, index.wrapping_add_signed(off))))),
+            _ => Err(ILTypeError::unsupported(op, a, b, "managed pointer arithmetic is only supported on pointers to array elements")),
+        }
+    }
+
+    /// div.un
+    pub fn div_un(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_unsigned!("/.un", self, other, |x, y| x.checked_div(y).ok_or(ILTypeError::DivideByZero))
+    }
+
+    /// rem.un
+    pub fn rem_un(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_unsigned!("%.un", self, other, |x, y| x.checked_rem(y).ok_or(ILTypeError::DivideByZero))
+    }
+
+    /// 移位量只能是int32或者native int
+    fn as_shift_amount(&self) -> Option<u32> {
+        match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(n) => Some(n as u32),
+                ILValType::Isize(n) => Some(n as u32),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// shr.un，高位补0
+    pub fn shr_un(self, amount: Self) -> Result<ILType, ILTypeError> {
+        let n = amount.as_shift_amount().ok_or_else(|| ILTypeError::invalid(">>.un", &self, &amount))?;
+        match &self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(x) => Ok(ILType::Val(ILValType::Int32((x as u32).wrapping_shr(n) as i32))),
+                ILValType::Int64(x) => Ok(ILType::Val(ILValType::Int64((x as u64).wrapping_shr(n) as i64))),
+                ILValType::Isize(x) => Ok(ILType::Val(ILValType::Isize((x as usize).wrapping_shr(n) as isize))),
+                _ => Err(ILTypeError::invalid(">>.un", &self, &amount)),
+            },
+            _ => Err(ILTypeError::invalid(">>.un", &self, &amount)),
+        }
+    }
+
+    /// add.ovf
+    pub fn add_ovf(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_numeric!("+.ovf", self, other, |x, y| x.checked_add(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// add.ovf.un
+    pub fn add_ovf_un(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_unsigned!("+.ovf.un", self, other, |x, y| x.checked_add(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// sub.ovf
+    pub fn sub_ovf(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_numeric!("-.ovf", self, other, |x, y| x.checked_sub(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// sub.ovf.un
+    pub fn sub_ovf_un(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_unsigned!("-.ovf.un", self, other, |x, y| x.checked_sub(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// mul.ovf
+    pub fn mul_ovf(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_numeric!("*.ovf", self, other, |x, y| x.checked_mul(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// mul.ovf.un
+    pub fn mul_ovf_un(self, other: Self) -> Result<ILType, ILTypeError> {
+        binary_unsigned!("*.ovf.un", self, other, |x, y| x.checked_mul(y).ok_or(ILTypeError::Overflow))
+    }
+
+    /// conv.ovf.*和conv.ovf.*.un，unsigned为true时把整数源操作数视为无符号数
+    /// 浮点数先向0截断再检查范围，NaN总是溢出
+    pub fn conv_ovf(&self, target: ConvTarget, unsigned: bool) -> Result<ILType, ILTypeError> {
+        let (min, max) = target.range();
+        let value = match self {
+            ILType::Val(v) => match (v.to_stack_type(), unsigned) {
+                (ILValType::Int32(i), false) => i as i128,
+                (ILValType::Int32(i), true) => i as u32 as i128,
+                (ILValType::Int64(i), false) => i as i128,
+                (ILValType::Int64(i), true) => i as u64 as i128,
+                (ILValType::Isize(i), false) => i as i128,
+                (ILValType::Isize(i), true) => i as usize as i128,
+                (ILValType::Single(f), _) => Self::truncate_checked(f as f64, min, max)?,
+                (ILValType::Double(f), _) => Self::truncate_checked(f, min, max)?,
+                _ => return Err(ILTypeError::invalid_unary("conv.ovf ", self)),
+            },
+            _ => return Err(ILTypeError::invalid_unary("conv.ovf ", self)),
+        };
+        if value < min || value > max {
+            return Err(ILTypeError::Overflow);
+        }
+        Ok(target.to_stack_value(value))
+    }
+
+    /// max as f64可能被舍入到2的幂，所以上界用开区间max + 1比较
+    fn truncate_checked(f: f64, min: i128, max: i128) -> Result<i128, ILTypeError> {
+        let t = f.trunc();
+        if t.is_nan() || t < min as f64 || t >= max as f64 + 1.0 {
+            return Err(ILTypeError::Overflow);
+        }
+        Ok(t as i128)
+    }
+
+    /// conv.i1 ~ conv.u，整数截断后按有无符号扩展回求值栈类型
+    /// 浮点数向0截断，超出范围时的结果在ECMA-335中未定义，这里饱和到目标类型的范围，NaN为0
+    pub fn conv(&self, target: ConvTarget) -> Result<ILType, ILTypeError> {
+        let (min, max) = target.range();
+        let value = match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => i as i128,
+                ILValType::Int64(i) => i as i128,
+                ILValType::Isize(i) => i as i128,
+                ILValType::Single(f) => (f as i128).clamp(min, max),
+                ILValType::Double(f) => (f as i128).clamp(min, max),
+                _ => return Err(ILTypeError::invalid_unary("conv ", self)),
+            },
+            // 把指针转换为native int，指针本身没有数值，所以保持原样
+            ILType::NPtr(_) | ILType::Ptr(_) if target == ConvTarget::I || target == ConvTarget::U => return Ok(self.clone()),
+            _ => return Err(ILTypeError::invalid_unary("conv ", self)),
+        };
+        Ok(target.to_stack_value(value))
+    }
+
+    /// conv.r4
+    pub fn conv_r4(&self) -> Result<ILType, ILTypeError> {
+        let value = match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => i as f32,
+                ILValType::Int64(i) => i as f32,
+                ILValType::Isize(i) => i as f32,
+                ILValType::Single(f) => f,
+                ILValType::Double(f) => f as f32,
+                _ => return Err(ILTypeError::invalid_unary("conv.r4 ", self)),
+            },
+            _ => return Err(ILTypeError::invalid_unary("conv.r4 ", self)),
+        };
+        Ok(ILType::Val(ILValType::Single(value)))
+    }
+
+    /// conv.r8
+    pub fn conv_r8(&self) -> Result<ILType, ILTypeError> {
+        let value = match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => i as f64,
+                ILValType::Int64(i) => i as f64,
+                ILValType::Isize(i) => i as f64,
+                ILValType::Single(f) => f as f64,
+                ILValType::Double(f) => f,
+                _ => return Err(ILTypeError::invalid_unary("conv.r8 ", self)),
+            },
+            _ => return Err(ILTypeError::invalid_unary("conv.r8 ", self)),
+        };
+        Ok(ILType::Val(ILValType::Double(value)))
+    }
+
+    /// conv.r.un，把整数视为无符号数转换为浮点数
+    pub fn conv_r_un(&self) -> Result<ILType, ILTypeError> {
+        let value = match self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => i as u32 as f64,
+                ILValType::Int64(i) => i as u64 as f64,
+                ILValType::Isize(i) => i as usize as f64,
+                ILValType::Single(f) => f as f64,
+                ILValType::Double(f) => f,
+                _ => return Err(ILTypeError::invalid_unary("conv.r.un ", self)),
+            },
+            _ => return Err(ILTypeError::invalid_unary("conv.r.un ", self)),
+        };
+        Ok(ILType::Val(ILValType::Double(value)))
+    }
+
+    /// 压入求值栈时的形式，见ILValType::to_stack_type
+    pub fn to_stack_value(&self) -> ILType {
+        match self {
+            ILType::Val(v) => ILType::Val(v.to_stack_type()),
+            other => other.clone(),
+        }
+    }
+
+    /// 存入参数、局部变量或字段时，按照声明的类型截断，declared为该位置原来的值
+    /// 声明的类型不是基元类型时原样存入
+    pub fn coerce_to(self, declared: &ILType) -> ILType {
+        let (declared, value) = match (declared, &self) {
+            (ILType::Val(d), ILType::Val(v)) => (d, v.to_stack_type()),
+            _ => return self,
+        };
+        let value = match value {
+            ILValType::Int32(i) => i as i128,
+            ILValType::Int64(i) => i as i128,
+            ILValType::Isize(i) => i as i128,
+            ILValType::Single(f) => return match declared {
+                ILValType::Double(_) => ILType::Val(ILValType::Double(f as f64)),
+                _ => self,
+            },
+            ILValType::Double(f) => return match declared {
+                ILValType::Single(_) => ILType::Val(ILValType::Single(f as f32)),
+                _ => self,
+            },
+            _ => return self,
+        };
+        ILType::Val(match declared {
+            ILValType::Boolean(_) => ILValType::Boolean(value as u8 != 0),
+            ILValType::Byte(_) => ILValType::Byte(value as u8),
+            ILValType::SByte(_) => ILValType::SByte(value as i8),
+            // Rust的char不能表示单独的代理项，用U+FFFD代替
+            ILValType::Char(_) => ILValType::Char(char::from_u32(value as u16 as u32).unwrap_or(char::REPLACEMENT_CHARACTER)),
+            ILValType::Short(_) => ILValType::Short(value as i16),
+            ILValType::UShort(_) => ILValType::UShort(value as u16),
+            ILValType::Int32(_) => ILValType::Int32(value as i32),
+            ILValType::UInt32(_) => ILValType::UInt32(value as u32),
+            ILValType::Int64(_) => ILValType::Int64(value as i64),
+            ILValType::UInt64(_) => ILValType::UInt64(value as u64),
+            ILValType::Isize(_) => ILValType::Isize(value as isize),
+            ILValType::Usize(_) => ILValType::Usize(value as usize),
+            ILValType::Single(_) | ILValType::Double(_) => return self,
+        })
+    }
+
+    /// ckfinite，值是NaN或无穷大时出错，否则原样返回
+    pub fn check_finite(self) -> Result<ILType, ILTypeError> {
+        match &self {
+            ILType::Val(ILValType::Single(f)) if !f.is_finite() => Err(ILTypeError::NotFinite),
+            ILType::Val(ILValType::Double(f)) if !f.is_finite() => Err(ILTypeError::NotFinite),
+            ILType::Val(ILValType::Single(_)) | ILType::Val(ILValType::Double(_)) => Ok(self),
+            _ => Err(ILTypeError::invalid_unary("ckfinite ", &self)),
+        }
+    }
+}
+
+impl Add for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    fn add(self, other: Self) -> Self::Output {
+        match (&self, &other) {
+            (ILType::NPtr(p), _) => {
+                let off = other.as_offset().ok_or_else(|| ILTypeError::invalid("+", &self, &other))?;
+                let mut p = p.clone();
+                p.offset(off);
+                Ok(ILType::NPtr(p))
+            },
+            (_, ILType::NPtr(p)) => {
+                let off = self.as_offset().ok_or_else(|| ILTypeError::invalid("+", &self, &other))?;
+                let mut p = p.clone();
+                p.offset(off);
+                Ok(ILType::NPtr(p))
+            },
+            (ILType::Ptr(p), _) => {
+                let off = other.as_offset().ok_or_else(|| ILTypeError::invalid("+", &self, &other))?;
+                ILType::offset_ptr("+", &self, &other, p, off)
+            },
+            (_, ILType::Ptr(p)) => {
+                let off = self.as_offset().ok_or_else(|| ILTypeError::invalid("+", &self, &other))?;
+                ILType::offset_ptr("+", &self, &other, p, off)
+            },
+            _ => binary_numeric!("+", self, other, |x, y| Ok(x.wrapping_add(y)), Ok(x + y)),
         }
     }
 }
 
 impl Sub for ILType {
-    type Output = Self;
+    type Output = Result<ILType, ILTypeError>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        match (&self, &other) {
+            (ILType::NPtr(p1), ILType::NPtr(p2)) => Ok(ILType::Val(ILValType::Isize(p1.offset.wrapping_sub(p2.offset) as isize))),
+            (ILType::NPtr(p), _) => {
+                let off = other.as_offset().ok_or_else(|| ILTypeError::invalid("-", &self, &other))?;
+                let mut p = p.clone();
+                p.offset(off.wrapping_neg());
+                Ok(ILType::NPtr(p))
+            },
+            // 同一个数组中两个元素的距离，以元素为单位
+            (ILType::Ptr(ILPtr::Element((a1, i1))), ILType::Ptr(ILPtr::Element((a2, i2)))) if a1 == a2 => {
+                Ok(ILType::Val(ILValType::Isize(i1.wrapping_sub(*i2) as isize)))
+            },
+            (ILType::Ptr(_), ILType::Ptr(_)) => Err(ILTypeError::unsupported("-", &self, &other, "managed pointers can only be subtracted within the same array")),
+            (ILType::Ptr(p), _) => {
+                let off = other.as_offset().ok_or_else(|| ILTypeError::invalid("-", &self, &other))?;
+                ILType::offset_ptr("-", &self, &other, p, off.wrapping_neg())
+            },
+            _ => binary_numeric!("-", self, other, |x, y| Ok(x.wrapping_sub(y)), Ok(x - y)),
+        }
+    }
+}
+
+impl Mul for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    fn mul(self, other: Self) -> Self::Output {
+        binary_numeric!("*", self, other, |x, y| Ok(x.wrapping_mul(y)), Ok(x * y))
+    }
+}
+
+impl Div for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    /// 整数除以0抛出DivideByZeroException，MinValue / -1抛出OverflowException，浮点数遵循IEEE 754
+    fn div(self, other: Self) -> Self::Output {
+        binary_numeric!("/", self, other, |x, y| if y == 0 {
+            Err(ILTypeError::DivideByZero)
+        } else {
+            x.checked_div(y).ok_or(ILTypeError::Overflow)
+        }, Ok(x / y))
+    }
+}
+
+impl Rem for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    fn rem(self, other: Self) -> Self::Output {
+        binary_numeric!("%", self, other, |x, y| if y == 0 {
+            Err(ILTypeError::DivideByZero)
+        } else {
+            x.checked_rem(y).ok_or(ILTypeError::Overflow)
+        }, Ok(x % y))
+    }
+}
+
+impl Neg for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    fn neg(self) -> Self::Output {
+        match &self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => Ok(ILType::Val(ILValType::Int32(i.wrapping_neg()))),
+                ILValType::Int64(i) => Ok(ILType::Val(ILValType::Int64(i.wrapping_neg()))),
+                ILValType::Isize(i) => Ok(ILType::Val(ILValType::Isize(i.wrapping_neg()))),
+                ILValType::Single(f) => Ok(ILType::Val(ILValType::Single(-f))),
+                ILValType::Double(f) => Ok(ILType::Val(ILValType::Double(-f))),
+                _ => Err(ILTypeError::invalid_unary("-", &self)),
+            },
+            _ => Err(ILTypeError::invalid_unary("-", &self)),
+        }
+    }
+}
+
+impl Not for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    fn not(self) -> Self::Output {
+        match &self {
+            ILType::Val(v) => match v.to_stack_type() {
+                ILValType::Int32(i) => Ok(ILType::Val(ILValType::Int32(!i))),
+                ILValType::Int64(i) => Ok(ILType::Val(ILValType::Int64(!i))),
+                ILValType::Isize(i) => Ok(ILType::Val(ILValType::Isize(!i))),
+                _ => Err(ILTypeError::invalid_unary("~", &self)),
+            },
+            _ => Err(ILTypeError::invalid_unary("~", &self)),
+        }
+    }
+}
+
+impl Shl for ILType {
+    type Output = Result<ILType, ILTypeError>;
 
-    fn sub(self, other: Self) -> Self {
+    fn shl(self, amount: Self) -> Self::Output {
+        shift!("<<", self, amount, |x, n| x.wrapping_shl(n))
+    }
+}
+
+impl Shr for ILType {
+    type Output = Result<ILType, ILTypeError>;
+
+    /// 算术右移，高位补符号位
+    fn shr(self, amount: Self) -> Self::Output {
+        shift!(">>", self, amount, |x, n| x.wrapping_shr(n))
+    }
+}
+
+impl ILType {
+    /// 比较两个值，类型不能比较时返回Err，浮点数中有NaN时返回Ok(None)
+    pub fn try_cmp(&self, other: &Self) -> Result<Option<Ordering>, ILTypeError> {
+        self.compare(other, false)
+    }
+
+    /// .un形式的比较，整数按无符号数比较，浮点数和try_cmp相同，调用者需要把None视为成立
+    pub fn try_cmp_un(&self, other: &Self) -> Result<Option<Ordering>, ILTypeError> {
+        self.compare(other, true)
+    }
+
+    /// 对象引用和托管指针只能判断是否相等，不相等时返回None（无序）
+    /// 唯一的例外是和null比较，非null的引用大于null，对应C#中的obj != null会被编译为cgt.un
+    fn compare(&self, other: &Self, unsigned: bool) -> Result<Option<Ordering>, ILTypeError> {
         match (self, other) {
             (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int32(i1 - i2)),
-                    (ILValType::Int32(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 as i64 - i2)),
-                    (ILValType::Int64(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int64(i1 - i2 as i64)),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 - i2)),
-                    (ILValType::Single(i1), ILValType::Single(i2)) => ILType::Val(ILValType::Single(i1 - i2)),
-                    (ILValType::Double(i1), ILValType::Double(i2)) => ILType::Val(ILValType::Double(i1 - i2)),
-                    _ => panic!("Invalid Operation")
+                match Operands::new(v1, v2) {
+                    Some(Operands::Int32(x, y)) if unsigned => Ok(Some((x as u32).cmp(&(y as u32)))),
+                    Some(Operands::Int32(x, y)) => Ok(Some(x.cmp(&y))),
+                    Some(Operands::Int64(x, y)) if unsigned => Ok(Some((x as u64).cmp(&(y as u64)))),
+                    Some(Operands::Int64(x, y)) => Ok(Some(x.cmp(&y))),
+                    Some(Operands::NativeInt(x, y)) if unsigned => Ok(Some((x as usize).cmp(&(y as usize)))),
+                    Some(Operands::NativeInt(x, y)) => Ok(Some(x.cmp(&y))),
+                    Some(Operands::Single(x, y)) => Ok(x.partial_cmp(&y)),
+                    Some(Operands::Double(x, y)) => Ok(x.partial_cmp(&y)),
+                    None => Err(ILTypeError::invalid("<=>", self, other)),
                 }
-            }
-            _ => panic!("Invalid Operation")
+            },
+            (ILType::Ref(r1), ILType::Ref(r2)) => {
+                Ok(match (r1, r2) {
+                    _ if r1 == r2 => Some(Ordering::Equal),
+                    (_, ILRefType::Null) => Some(Ordering::Greater),
+                    (ILRefType::Null, _) => Some(Ordering::Less),
+                    _ => None,
+                })
+            },
+            (ILType::Ptr(p1), ILType::Ptr(p2)) => Ok(if p1 == p2 { Some(Ordering::Equal) } else { None }),
+            (ILType::NPtr(p1), ILType::NPtr(p2)) => Ok(Some(p1.offset.cmp(&p2.offset))),
+            (ILType::Handle(h1), ILType::Handle(h2)) => Ok(if h1 == h2 { Some(Ordering::Equal) } else { None }),
+            (ILType::FnPtr(f1), ILType::FnPtr(f2)) => Ok(if f1.method == f2.method { Some(Ordering::Equal) } else { None }),
+            _ => Err(ILTypeError::invalid("<=>", self, other)),
         }
     }
 }
 
 impl PartialOrd for ILType {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        match (self, other) {
-            (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => i1.partial_cmp(i2),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => i1.partial_cmp(i2),
-                    (ILValType::Single(i1), ILValType::Single(i2)) => i1.partial_cmp(i2),
-                    (ILValType::Double(i1), ILValType::Double(i2)) => i1.partial_cmp(i2),
-                    _ => panic!("Invalid Operation")
-                }
-            }
-            _ => panic!("Invalid Operation")
-        }
+        self.try_cmp(other).ok().flatten()
     }
 }
 
 impl BitAnd for ILType {
-    type Output = Self;
+    type Output = Result<ILType, ILTypeError>;
 
-    fn bitand(self, other: Self) -> Self {
-        match (self, other) {
-            (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int32(i1 & i2)),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 & i2)),
-                    _ => panic!("Invalid Operation")
-                }
-            }
-            _ => panic!("Invalid Operation")
-        }
+    fn bitand(self, other: Self) -> Self::Output {
+        binary_numeric!("&", self, other, |x, y| Ok(x & y))
     }
 }
 
 impl BitOr for ILType {
-    type Output = Self;
+    type Output = Result<ILType, ILTypeError>;
 
-    fn bitor(self, other: Self) -> Self {
-        match (self, other) {
-            (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int32(i1 | i2)),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 | i2)),
-                    _ => panic!("Invalid Operation")
-                }
-            }
-            _ => panic!("Invalid Operation")
-        }
+    fn bitor(self, other: Self) -> Self::Output {
+        binary_numeric!("|", self, other, |x, y| Ok(x | y))
     }
 }
 
 impl BitXor for ILType {
-    type Output = Self;
+    type Output = Result<ILType, ILTypeError>;
 
-    fn bitxor(self, other: Self) -> Self {
-        match (self, other) {
-            (ILType::Val(v1), ILType::Val(v2)) => {
-                match (v1, v2) {
-                    (ILValType::Int32(i1), ILValType::Int32(i2)) => ILType::Val(ILValType::Int32(i1 ^ i2)),
-                    (ILValType::Int64(i1), ILValType::Int64(i2)) => ILType::Val(ILValType::Int64(i1 ^ i2)),
-                    _ => panic!("Invalid Operation")
-                }
-            }
-            _ => panic!("Invalid Operation")
+    fn bitxor(self, other: Self) -> Self::Output {
+        binary_numeric!("^", self, other, |x, y| Ok(x ^ y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int32(i: i32) -> ILType {
+        ILType::Val(ILValType::Int32(i))
+    }
+
+    fn int64(i: i64) -> ILType {
+        ILType::Val(ILValType::Int64(i))
+    }
+
+    fn native(i: isize) -> ILType {
+        ILType::Val(ILValType::Isize(i))
+    }
+
+    fn element(array: usize, index: usize) -> ILType {
+        ILType::Ptr(ILPtr::Element((array, index)))
+    }
+
+    #[test]
+    fn binary_numeric_operations_follow_operand_table() {
+        assert_eq!(int32(i32::MAX) + int32(1), Ok(int32(i32::MIN)));
+        assert_eq!(int32(3) - native(5), Ok(native(-2)));
+        assert_eq!(int64(6) * int64(7), Ok(int64(42)));
+        assert_eq!(ILType::Val(ILValType::Byte(200)) + int32(100), Ok(int32(300)));
+        assert_eq!(ILType::Val(ILValType::Single(1.5)) + ILType::Val(ILValType::Single(2.0)), Ok(ILType::Val(ILValType::Single(3.5))));
+        assert!(matches!(int64(1) + int32(1), Err(ILTypeError::InvalidOperation(_))));
+        assert!(matches!(native(1) - int64(1), Err(ILTypeError::InvalidOperation(_))));
+    }
+
+    #[test]
+    fn integer_division_reports_divide_by_zero_and_overflow() {
+        assert_eq!(int32(7) / int32(-2), Ok(int32(-3)));
+        assert_eq!(int32(7) / int32(0), Err(ILTypeError::DivideByZero));
+        assert_eq!(int64(7) % int64(0), Err(ILTypeError::DivideByZero));
+        assert_eq!(int32(i32::MIN) / int32(-1), Err(ILTypeError::Overflow));
+        assert_eq!(int32(-1).div_un(int32(2)), Ok(int32(i32::MAX)));
+        assert_eq!(int32(i32::MAX).add_ovf(int32(1)), Err(ILTypeError::Overflow));
+        assert_eq!(int32(1).sub_ovf_un(int32(2)), Err(ILTypeError::Overflow));
+    }
+
+    #[test]
+    fn managed_pointer_arithmetic_is_counted_in_elements() {
+        assert_eq!(element(3, 2) + int32(4), Ok(element(3, 6)));
+        assert_eq!(native(1) + element(3, 2), Ok(element(3, 3)));
+        assert_eq!(element(3, 2) - int32(1), Ok(element(3, 1)));
+        assert_eq!(element(3, 2) - element(3, 5), Ok(native(-3)));
+        assert!(matches!(element(3, 2) - element(4, 2), Err(ILTypeError::InvalidOperation(_))));
+        assert!(matches!(ILType::Ptr(ILPtr::Local((0, 1))) + int32(1), Err(ILTypeError::InvalidOperation(_))));
+        assert!(matches!(element(3, 2) + int64(1), Err(ILTypeError::InvalidOperation(_))));
+    }
+
+    fn double(f: f64) -> ILType {
+        ILType::Val(ILValType::Double(f))
+    }
+
+    #[test]
+    fn conv_truncates_integers_and_saturates_floats() {
+        assert_eq!(int32(300).conv(ConvTarget::U1), Ok(int32(44)));
+        assert_eq!(int32(-1).conv(ConvTarget::U2), Ok(int32(0xFFFF)));
+        assert_eq!(int64(-1).conv(ConvTarget::U4), Ok(int32(-1)));
+        assert_eq!(int32(-1).conv(ConvTarget::I8), Ok(int64(-1)));
+        assert_eq!(double(-2.9).conv(ConvTarget::I4), Ok(int32(-2)));
+        assert_eq!(double(300.0).conv(ConvTarget::U1), Ok(int32(255)));
+        assert_eq!(double(-1e10).conv(ConvTarget::I4), Ok(int32(i32::MIN)));
+        assert_eq!(double(1e30).conv(ConvTarget::U8), Ok(int64(-1)));
+        assert_eq!(double(f64::NAN).conv(ConvTarget::I4), Ok(int32(0)));
+        assert_eq!(ILType::Val(ILValType::Single(f32::INFINITY)).conv(ConvTarget::I2), Ok(int32(i16::MAX as i32)));
+    }
+
+    #[test]
+    fn conv_ovf_checks_the_target_range() {
+        assert_eq!(int32(255).conv_ovf(ConvTarget::U1, false), Ok(int32(255)));
+        assert_eq!(int32(256).conv_ovf(ConvTarget::U1, false), Err(ILTypeError::Overflow));
+        assert_eq!(int32(-1).conv_ovf(ConvTarget::U4, false), Err(ILTypeError::Overflow));
+        assert_eq!(int32(-1).conv_ovf(ConvTarget::U4, true), Ok(int32(-1)));
+        assert_eq!(int32(-1).conv_ovf(ConvTarget::I4, true), Err(ILTypeError::Overflow));
+        assert_eq!(double(127.9).conv_ovf(ConvTarget::I1, false), Ok(int32(127)));
+        assert_eq!(double(128.0).conv_ovf(ConvTarget::I1, false), Err(ILTypeError::Overflow));
+        assert_eq!(double(9.3e18).conv_ovf(ConvTarget::I8, false), Err(ILTypeError::Overflow));
+        assert_eq!(double(f64::NAN).conv_ovf(ConvTarget::I4, false), Err(ILTypeError::Overflow));
+    }
+
+    mod structs {
+        use super::super::super::op_codes::{OpCode, OpCode2};
+        use super::super::super::test_assembly::*;
+
+        /// struct S { int X; } struct Outer { S Inner; } class Holder { S s; }，返回每个方法的int32结果
+        fn run(mains: impl FnOnce(&mut AssemblyBuilder, StructTokens) -> Vec<u32>) -> Vec<String> {
+            let mut builder = AssemblyBuilder::new("Test");
+            let object = builder.cor_lib_type("System", "Object");
+            let value_type = builder.cor_lib_type("System", "ValueType");
+            let s = builder.define_type("", "S", SEALED_CLASS, value_type);
+            let x = builder.field(FIELD_PUBLIC, "X", sig::int32());
+            let outer = builder.define_type("", "Outer", SEALED_CLASS, value_type);
+            let inner = builder.field(FIELD_PUBLIC, "Inner", sig::value_type(s));
+            let holder = builder.define_type("", "Holder", CLASS, object);
+            let holder_s = builder.field(FIELD_PUBLIC, "s", sig::value_type(s));
+            let holder_ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![]));
+            builder.body(holder_ctor, vec![], Code::new().op(OpCode::Ret));
+            builder.define_type("", "Program", CLASS, object);
+            let tokens = StructTokens { s, x, outer, inner, holder, holder_s, holder_ctor };
+            let mains = mains(&mut builder, tokens);
+            let mut interpreter = interpreter(builder.build(), vec![]);
+            mains.into_iter().map(|main| {
+                let result = call(&mut interpreter, main).unwrap().unwrap();
+                interpreter.format_il_type(&result)
+            }).collect()
+        }
+
+        struct StructTokens {
+            s: u32,
+            x: u32,
+            outer: u32,
+            inner: u32,
+            holder: u32,
+            holder_s: u32,
+            holder_ctor: u32,
+        }
+
+        fn main(builder: &mut AssemblyBuilder, locals: Vec<Vec<u8>>, code: Code) -> u32 {
+            let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::int32(), vec![]));
+            builder.body(main, locals, code);
+            main
+        }
+
+        #[test]
+        fn structs_are_copied_on_assignment() {
+            let results = run(|builder, t| {
+                // S a; a.X = 1; S b = a; b.X = 2; return a.X;
+                let local = main(builder, vec![sig::value_type(t.s), sig::value_type(t.s)], Code::new()
+                    .op_u8(OpCode::Ldlocas, 0).op(OpCode::Ldci41).op_token(OpCode::Stfld, t.x)
+                    .op(OpCode::Ldloc0).op(OpCode::Stloc1)
+                    .op_u8(OpCode::Ldlocas, 1).op(OpCode::Ldci42).op_token(OpCode::Stfld, t.x)
+                    .op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Ldfld, t.x).op(OpCode::Ret));
+
+                // static void Mutate(S s) { s.X = 5; }  S a; a.X = 1; Mutate(a); return a.X;
+                let mutate = builder.method(PUBLIC | STATIC, "Mutate", sig::method(false, sig::void(), vec![sig::value_type(t.s)]));
+                builder.body(mutate, vec![], Code::new().op_u8(OpCode::Ldargas, 0).op(OpCode::Ldci45).op_token(OpCode::Stfld, t.x).op(OpCode::Ret));
+                let argument = main(builder, vec![sig::value_type(t.s)], Code::new()
+                    .op_u8(OpCode::Ldlocas, 0).op(OpCode::Ldci41).op_token(OpCode::Stfld, t.x)
+                    .op(OpCode::Ldloc0).op_token(OpCode::Call, mutate)
+                    .op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Ldfld, t.x).op(OpCode::Ret));
+
+                // Holder h = new Holder(); h.s.X = 1; S a = h.s; a.X = 2; h.s = a; a.X = 3; return h.s.X;
+                let field = main(builder, vec![sig::class(t.holder), sig::value_type(t.s)], Code::new()
+                    .op_token(OpCode::Newobj, t.holder_ctor).op(OpCode::Stloc0)
+                    .op(OpCode::Ldloc0).op_token(OpCode::Ldflda, t.holder_s).op(OpCode::Ldci41).op_token(OpCode::Stfld, t.x)
+                    .op(OpCode::Ldloc0).op_token(OpCode::Ldfld, t.holder_s).op(OpCode::Stloc1)
+                    .op_u8(OpCode::Ldlocas, 1).op(OpCode::Ldci42).op_token(OpCode::Stfld, t.x)
+                    .op(OpCode::Ldloc0).op_token(OpCode::Ldflda, t.holder_s).op_token(OpCode::Ldfld, t.x)  // 1
+                    .op(OpCode::Ldloc0).op(OpCode::Ldloc1).op_token(OpCode::Stfld, t.holder_s)
+                    .op_u8(OpCode::Ldlocas, 1).op(OpCode::Ldci43).op_token(OpCode::Stfld, t.x)
+                    .op(OpCode::Ldloc0).op_token(OpCode::Ldflda, t.holder_s).op_token(OpCode::Ldfld, t.x)  // 2
+                    .ldc_i4(10).op(OpCode::Mul).op(OpCode::Add).op(OpCode::Ret));
+                vec![local, argument, field]
+            });
+            assert_eq!(results, ["1", "1", "21"]);
+        }
+
+        #[test]
+        fn ldobj_stobj_and_initobj_work_through_struct_field_pointers() {
+            let results = run(|builder, t| {
+                // Outer o; o.Inner.X = 3; S a = o.Inner（ldobj）; a.X = 4; r = o.Inner.X * 100;
+                // o.Inner = a（stobj）; r += o.Inner.X * 10; o.Inner = default（initobj）; return r + o.Inner.X;
+                let inner = |code: Code| code.op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Ldflda, t.inner);
+                let code = inner(Code::new()).op(OpCode::Ldci43).op_token(OpCode::Stfld, t.x);
+                let code = inner(code).op_token(OpCode::Ldobj, t.s).op(OpCode::Stloc1);
+                let code = code.op_u8(OpCode::Ldlocas, 1).op(OpCode::Ldci44).op_token(OpCode::Stfld, t.x);
+                let code = inner(code).op_token(OpCode::Ldfld, t.x).ldc_i4(100).op(OpCode::Mul);
+                let code = inner(code).op(OpCode::Ldloc1).op_token(OpCode::Stobj, t.s);
+                let code = inner(code).op_token(OpCode::Ldfld, t.x).ldc_i4(10).op(OpCode::Mul).op(OpCode::Add);
+                let code = inner(code).op2_token(OpCode2::Initobj, t.s);
+                let code = inner(code).op_token(OpCode::Ldfld, t.x).op(OpCode::Add).op(OpCode::Ret);
+                vec![main(builder, vec![sig::value_type(t.outer), sig::value_type(t.s)], code)]
+            });
+            assert_eq!(results, ["340"]);
         }
     }
 }
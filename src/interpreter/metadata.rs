@@ -4,7 +4,7 @@ use image_option_header::*;
 mod image_section_header;
 use image_section_header::*;
 mod image_cor20_header;
-use image_cor20_header::*;
+pub use image_cor20_header::*;
 mod metadata_header;
 use metadata_header::*;
 pub mod table_stream;
@@ -183,6 +183,7 @@ enum MetadataType {
 
 /// 只包含Raw数据，解析由Assembly类负责
 pub struct Metadata {
+    pub cor20_header: ImageCor20Header,
     pub table_stream: TableStream,
     pub strings_stream: StringsStream,
     pub us_stream: USStream,
@@ -269,6 +270,7 @@ impl Metadata {
         }
 
         Ok(Metadata {
+            cor20_header,
             table_stream,
             strings_stream,
             us_stream,
@@ -1,7 +1,7 @@
 use std::io;
 
 use crate::interpreter::{CallingConventionSig, metadata::md_token::MDToken};
-use super::{Assembly, RidList, data_reader::DataReader, metadata::*};
+use super::{Assembly, RidList, data_reader::DataReader, exception_clause::ExceptionClause, generic_param::GenericParam, metadata::*};
 
 pub struct Method {
     pub token: u32,                 // 形如0x06000001
@@ -21,6 +21,8 @@ pub struct Method {
 
     pub header_position: usize,     // MethodHeader在Image中的真实位置
     pub code_position: usize,       // IL指令在Image中的真实位置
+    pub exception_clauses: Vec<ExceptionClause>,  // 异常处理子句，按从内到外的顺序排列
+    pub generic_params: Vec<GenericParam>,        // 泛型方法的类型参数，按序号排列
 }
 
 impl Method {
@@ -44,6 +46,7 @@ impl Method {
             let max_stack: u16;
             let local_var_rid: u32;
             let header_position: usize;
+            let mut exception_clauses = Vec::new();
 
             let rva = method_table.columns[0].get_cell_u32(row);  // RVA记录了方法具体实现的字节码位置
             if rva == 0 {  // 有些方法有记录但是没有实际的实现（例如InternalCall或者P/Invoke），此时RVA为0
@@ -57,8 +60,8 @@ impl Method {
                 header_position = pe.rva_to_file_offset(rva);
                 reader.set_position(header_position)?;
     
-                let b = reader.read_u8()? & 7;
-                match b {
+                let b = reader.read_u8()?;
+                match b & 7 {
                     2 | 6 => {  // Tiny header. [7:2] = code size, max stack is 8, no locals or exception handlers
                         flags = 2;
                         max_stack = 8;
@@ -67,7 +70,7 @@ impl Method {
                         header_size = 1;
                     },
                     3 => {  // Fat header. Can have locals and exception handlers
-                        flags = (reader.read_u8()? as u16) << 8;
+                        flags = (reader.read_u8()? as u16) << 8 | b as u16;
                         header_size = 4 * (flags >> 12) as u8;
                         max_stack = reader.read_u16()?;
                         code_size = reader.read_u32()?;
@@ -80,9 +83,12 @@ impl Method {
                         if header_size < 12 {
                             flags &= 0xFFF7;
                         }
+                        if flags & 0x0008 != 0 {  // MoreSects，代码之后是异常处理表
+                            exception_clauses = ExceptionClause::read_sections(reader, header_position + header_size as usize + code_size as usize)?;
+                        }
                     },
-                    _ => panic!("Invalid method header")
-                }    
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid method header at 0x{:X}", header_position)))
+                }
             }
             // TODO: 读取locals
 
@@ -105,6 +111,8 @@ impl Method {
 
                 header_position,
                 code_position: header_position + header_size as usize,
+                exception_clauses,
+                generic_params: Vec::new(),
             });
         }
 
@@ -139,6 +147,38 @@ impl Method {
     pub fn is_static(&self) -> bool {
         self.attributes & 0x10 != 0
     }
+
+    pub fn is_virtual(&self) -> bool {
+        self.attributes & 0x0040 != 0
+    }
+
+    /// 参数个数，不包括this，Param表中可能没有未命名的参数，所以优先使用签名
+    pub fn param_count(&self) -> usize {
+        match &self.signature {
+            Some(CallingConventionSig::MethodSig(sig)) => sig.base.parameters.len(),
+            _ => self.param_list.count as usize,
+        }
+    }
+
+    /// 不能被子类重写
+    pub fn is_final(&self) -> bool {
+        self.attributes & 0x0020 != 0
+    }
+
+    /// 总是在虚表中占用新的槽位，而不是重写基类的同名方法
+    pub fn is_new_slot(&self) -> bool {
+        self.attributes & 0x0100 != 0
+    }
+
+    /// 只有能访问这个方法的子类才能重写它
+    pub fn is_strict(&self) -> bool {
+        self.attributes & 0x0200 != 0
+    }
+
+    /// 访问级别，1为private，2为famANDassem，3为assembly，4为family，5为famORassem，6为public
+    pub fn access(&self) -> u16 {
+        self.attributes & 0x0007
+    }
 }
 
 pub enum CodeType {
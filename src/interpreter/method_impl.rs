@@ -0,0 +1,34 @@
+use std::io;
+
+use super::metadata::{Metadata, md_token::CodedToken, table_stream::MDType};
+
+/// 存储显式重写的MethodImpl，例如显式实现接口方法
+#[derive(Debug)]
+pub struct MethodImpl {
+    /// 进行重写的类型，形如0x02000001
+    pub class: u32,
+    /// 实际执行的方法，method_def或者member_ref的token
+    pub body: u32,
+    /// 被重写的方法，method_def或者member_ref的token
+    pub declaration: u32,
+}
+
+impl MethodImpl {
+    pub fn read_method_impls(metadata: &Metadata) -> io::Result<Vec<MethodImpl>> {
+        let mut method_impls = Vec::new();
+        let method_impl_table = &metadata.table_stream.md_tables[0x19];
+        for row in 0..method_impl_table.row_count {
+            let class = 0x02000000 + method_impl_table.columns[0].get_cell_u16_or_u32(row);
+            let body = CodedToken::from_md_type(MDType::MethodDefOrRef).decode(method_impl_table.columns[1].get_cell_u16_or_u32(row)).unwrap();
+            let declaration = CodedToken::from_md_type(MDType::MethodDefOrRef).decode(method_impl_table.columns[2].get_cell_u16_or_u32(row)).unwrap();
+
+            method_impls.push(MethodImpl {
+                class,
+                body,
+                declaration,
+            });
+        }
+
+        Ok(method_impls)
+    }
+}
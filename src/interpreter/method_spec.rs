@@ -1,6 +1,6 @@
 use std::io;
 
-use super::{metadata::{Metadata, md_token::CodedToken, table_stream::MDType}, calling_convention_sig::CallingConventionSig, type_sig::TypeSig};
+use super::{metadata::{Metadata, md_token::CodedToken, table_stream::MDType}, calling_convention_sig::CallingConventionSig};
 
 /// 存储泛型方法的MethodSpec
 #[derive(Debug)]
@@ -31,3 +31,48 @@ impl MethodSpec {
         Ok(method_specs)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::op_codes::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+
+    #[test]
+    fn each_method_spec_instantiates_mvar_in_its_own_frame() {
+        // static T Id<T>(T x) { T local = x; return (T)(object)local; }
+        // static bool IsInt<T>(T x) => (object)x is int;
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let int32 = builder.cor_lib_type("System", "Int32");
+        builder.define_type("", "Program", CLASS, object);
+        let t = builder.type_spec(sig::mvar(0));
+        let id = builder.method(PUBLIC | STATIC, "Id", sig::generic_method(false, 1, sig::mvar(0), vec![sig::mvar(0)]));
+        builder.generic_param(id, 0, "T");
+        builder.body(id, vec![sig::mvar(0)], Code::new().op(OpCode::Ldarg0).op(OpCode::Stloc0)
+            .op(OpCode::Ldloc0).op_token(OpCode::Box, t).op_token(OpCode::Unboxany, t).op(OpCode::Ret));
+        let is_int = builder.method(PUBLIC | STATIC, "IsInt", sig::generic_method(false, 1, sig::boolean(), vec![sig::mvar(0)]));
+        builder.generic_param(is_int, 0, "T");
+        builder.body(is_int, vec![], Code::new().op(OpCode::Ldarg0).op_token(OpCode::Box, t).op_token(OpCode::Isinst, int32)
+            .op(OpCode::Ldnull).op2(OpCode2::Cgtun).op(OpCode::Ret));
+        let (id_int, id_string) = (builder.method_spec(id, vec![sig::int32()]), builder.method_spec(id, vec![sig::string()]));
+        let (is_int_int, is_int_string) = (builder.method_spec(is_int, vec![sig::int32()]), builder.method_spec(is_int, vec![sig::string()]));
+        let x = builder.user_string("x");
+        let mut main = |ret: Vec<u8>, code: Code| {
+            let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, ret, vec![]));
+            builder.body(main, vec![], code.op(OpCode::Ret));
+            main
+        };
+        let mains = vec![
+            main(sig::int32(), Code::new().ldc_i4(5).op_token(OpCode::Call, id_int)),
+            main(sig::string(), Code::new().op_token(OpCode::Ldstr, x).op_token(OpCode::Call, id_string)),
+            main(sig::boolean(), Code::new().ldc_i4(5).op_token(OpCode::Call, is_int_int)),
+            main(sig::boolean(), Code::new().op(OpCode::Ldnull).op_token(OpCode::Call, is_int_string)),
+        ];
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let results: Vec<_> = mains.into_iter().map(|main| {
+            let result = call(&mut interpreter, main).unwrap().unwrap();
+            interpreter.format_il_type(&result)
+        }).collect();
+        assert_eq!(results, ["5", "x", "1", "0"]);
+    }
+}
@@ -2,18 +2,22 @@ use std::{hash::{Hash, Hasher}, ptr};
 
 use crate::hash_vec::HashVec;
 
-use super::{Interpreter, il_type::ILType};
+use super::{Interpreter, delegate::DelegateEntry, il_type::ILType, runtime_type::RuntimeType};
 
 pub struct Object {
     /// 包括locked、pinned、gc_mark和代
     flags: u8,
-    /// 对象原始的type_token，不可改变
+    /// type_token所在Assembly的index
+    pub assembly_index: usize,
+    /// 对象的type_token，不可改变
     origin_type_token: u32,
-    /// 对象的type_token，可能经过castclass发生了改变
-    pub type_token: u32,
+    /// 泛型类型的实例化的类型实参
+    pub generic_args: Vec<RuntimeType>,
     field_map: HashVec<u32, ILType>,
     /// 如果是box，那么这个存储原始数据
     pub box_value: Option<ILType>,
+    /// 如果是委托，那么这个存储调用列表，多播委托有多项
+    pub invocation_list: Vec<DelegateEntry>,
 }
 
 impl Hash for Object {
@@ -23,23 +27,27 @@ impl Hash for Object {
 }
 
 impl Object {
-    pub fn new(type_token: u32, field_map: HashVec<u32, ILType>) -> Object {
+    pub fn new(assembly_index: usize, type_token: u32, field_map: HashVec<u32, ILType>) -> Object {
         Object {
             flags: 0,
+            assembly_index,
             origin_type_token: type_token,
-            type_token,
+            generic_args: Vec::new(),
             field_map,
             box_value: None,
+            invocation_list: Vec::new(),
         }
     }
 
-    pub fn new_box(type_token: u32, value: ILType) -> Object {
+    pub fn new_box(assembly_index: usize, type_token: u32, value: ILType) -> Object {
         Object {
             flags: 0,
+            assembly_index,
             origin_type_token: type_token,
-            type_token,
+            generic_args: Vec::new(),
             field_map: HashVec::new(),
             box_value: Some(value),
+            invocation_list: Vec::new(),
         }
     }
 
@@ -47,9 +55,14 @@ impl Object {
         self.field_map.key_get(&(field_token_or_rid & 0x00FFFFFF))
     }
 
+    pub fn get_field_mut(&mut self, field_token_or_rid: u32) -> Option<&mut ILType> {
+        self.field_map.key_get_mut(&(field_token_or_rid & 0x00FFFFFF))
+    }
+
+    /// 按照字段声明的类型截断后存入
     pub fn set_field(&mut self, field_token_or_rid: u32, value: ILType) {
         let field = self.field_map.key_get_mut(&(field_token_or_rid & 0x00FFFFFF)).unwrap();
-        *field = value;
+        *field = value.coerce_to(field);
     }
 
     fn parse_type_token(type_token: u32) -> [u8; 3] {
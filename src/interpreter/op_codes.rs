@@ -2,7 +2,7 @@ use num_derive::FromPrimitive;
 
 // 单字节的OpCode
 #[repr(u8)]
-#[derive(FromPrimitive)]
+#[derive(Debug, Clone, Copy, FromPrimitive)]
 pub enum OpCode {
     Nop,
     Break,
@@ -208,7 +208,7 @@ pub enum OpCode {
 }
 
 #[repr(u8)]
-#[derive(FromPrimitive)]
+#[derive(Debug, Clone, Copy, FromPrimitive)]
 /// 双字节的opcode，第一位为0xFE
 pub enum OpCode2 {
     Arglist,
@@ -239,4 +239,25 @@ pub enum OpCode2 {
     Sizeof = 0x1C,  // <typeTok>
     Refanytype,
     Readonly,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+
+    #[test]
+    fn unaligned_and_volatile_prefixes_only_skip_their_operands() {
+        // int x; *(&x) = 7（unaligned. 1 volatile.）; return *(&x)（volatile. unaligned. 4）;
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::int32(), vec![]));
+        builder.body(main, vec![sig::int32()], Code::new()
+            .op_u8(OpCode::Ldlocas, 0).ldc_i4(7).op2_u8(OpCode2::Unaligned, 1).op2(OpCode2::Volatile).op(OpCode::Stindi4)
+            .op_u8(OpCode::Ldlocas, 0).op2(OpCode2::Volatile).op2_u8(OpCode2::Unaligned, 4).op(OpCode::Ldindi4).op(OpCode::Ret));
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let result = call(&mut interpreter, main).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "7");
+    }
+}
@@ -0,0 +1,193 @@
+use std::{error::Error, fmt::{self, Display, Formatter}, io};
+
+use super::il_type::ILTypeError;
+
+/// 解释器执行过程中出现的错误类型
+#[derive(Debug)]
+pub enum RuntimeErrorKind {
+    /// 尚未支持的指令
+    UnsupportedOpCode {
+        op_code: String,
+        method: String,
+        offset: usize,
+    },
+    /// 无法识别的指令
+    UnknownOpCode(u16),
+    /// 被引用的Assembly无法加载，包含探测过的路径
+    UnresolvedAssembly(String),
+    UnresolvedType(String),
+    UnresolvedMember(String),
+    InvalidToken(u32),
+    /// 求值栈上的值类型不符合指令的要求
+    TypeMismatch(String),
+    /// 求值栈为空时出栈
+    StackUnderflow,
+    NullReference,
+    InvalidCast(String),
+    IndexOutOfRange,
+    /// 存入数组的元素和数组的元素类型不兼容
+    ArrayTypeMismatch,
+    /// 泛型实例化不满足类型参数的约束
+    TypeLoad(String),
+    /// 例如Activator.CreateInstance<T>()时T没有public无参构造函数
+    MissingMethod(String),
+    /// 例如Delegate.Combine的两个委托类型不同
+    Argument(String),
+    /// 托管指针指向的位置已经不存在，例如指向已经返回的栈帧中的局部变量
+    InvalidPointer(String),
+    Overflow,
+    DivideByZero,
+    /// ckfinite等指令产生的算术错误
+    Arithmetic(String),
+    /// 调用深度超过了Interpreter::max_call_depth
+    StackOverflow,
+    /// PE或者元数据损坏，或者出现了不支持的格式
+    BadImage(String),
+    /// 托管异常，object为异常对象在objects中的index
+    /// handler为第一遍搜索找到的(frame index, clause index)，None表示没有catch能处理
+    Exception {
+        object: usize,
+        handler: Option<(usize, usize)>,
+    },
+    /// 没有被任何catch处理的托管异常，type_name为异常类型的全名
+    UnhandledException {
+        type_name: String,
+        message: String,
+    },
+}
+
+impl RuntimeErrorKind {
+    /// 对于运行时产生的、需要让托管代码捕获的错误，返回对应的异常类型全名和消息
+    pub fn to_managed_exception(&self) -> Option<(&'static str, String)> {
+        match self {
+            RuntimeErrorKind::NullReference => Some(("System.NullReferenceException", String::from("Object reference not set to an instance of an object."))),
+            RuntimeErrorKind::InvalidCast(message) => Some(("System.InvalidCastException", message.clone())),
+            RuntimeErrorKind::IndexOutOfRange => Some(("System.IndexOutOfRangeException", String::from("Index was outside the bounds of the array."))),
+            RuntimeErrorKind::ArrayTypeMismatch => Some(("System.ArrayTypeMismatchException", String::from("Attempted to access an element as a type incompatible with the array."))),
+            RuntimeErrorKind::TypeLoad(message) => Some(("System.TypeLoadException", message.clone())),
+            RuntimeErrorKind::MissingMethod(message) => Some(("System.MissingMethodException", message.clone())),
+            RuntimeErrorKind::Argument(message) => Some(("System.ArgumentException", message.clone())),
+            RuntimeErrorKind::Overflow => Some(("System.OverflowException", String::from("Arithmetic operation resulted in an overflow."))),
+            RuntimeErrorKind::DivideByZero => Some(("System.DivideByZeroException", String::from("Attempted to divide by zero."))),
+            RuntimeErrorKind::Arithmetic(message) => Some(("System.ArithmeticException", message.clone())),
+            RuntimeErrorKind::StackOverflow => Some(("System.StackOverflowException", String::from("Operation caused a stack overflow."))),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::UnsupportedOpCode { op_code, method, offset } => write!(f, "Unsupported OpCode {} in {} at IL_{:04X}", op_code, method, offset),
+            RuntimeErrorKind::UnknownOpCode(op) => write!(f, "Unknown OpCode: 0x{:02X}", op),
+            RuntimeErrorKind::UnresolvedAssembly(message) => write!(f, "{}", message),
+            RuntimeErrorKind::UnresolvedType(name) => write!(f, "Cannot resolve type: {}", name),
+            RuntimeErrorKind::UnresolvedMember(name) => write!(f, "Cannot resolve member: {}", name),
+            RuntimeErrorKind::InvalidToken(token) => write!(f, "Invalid token: 0x{:08X}", token),
+            RuntimeErrorKind::TypeMismatch(message) => write!(f, "Type mismatch: {}", message),
+            RuntimeErrorKind::StackUnderflow => write!(f, "Evaluation stack underflow"),
+            RuntimeErrorKind::NullReference => write!(f, "Null reference"),
+            RuntimeErrorKind::InvalidCast(message) => write!(f, "Invalid cast: {}", message),
+            RuntimeErrorKind::IndexOutOfRange => write!(f, "Index out of range"),
+            RuntimeErrorKind::ArrayTypeMismatch => write!(f, "Array type mismatch"),
+            RuntimeErrorKind::TypeLoad(message) => write!(f, "Type load error: {}", message),
+            RuntimeErrorKind::MissingMethod(message) => write!(f, "Missing method: {}", message),
+            RuntimeErrorKind::Argument(message) => write!(f, "Argument error: {}", message),
+            RuntimeErrorKind::InvalidPointer(message) => write!(f, "Invalid pointer: {}", message),
+            RuntimeErrorKind::Overflow => write!(f, "Arithmetic overflow"),
+            RuntimeErrorKind::DivideByZero => write!(f, "Divide by zero"),
+            RuntimeErrorKind::Arithmetic(message) => write!(f, "Arithmetic error: {}", message),
+            RuntimeErrorKind::StackOverflow => write!(f, "Stack overflow"),
+            RuntimeErrorKind::BadImage(message) => write!(f, "Bad image: {}", message),
+            RuntimeErrorKind::Exception { object, .. } => write!(f, "Unhandled exception: object {}", object),
+            RuntimeErrorKind::UnhandledException { type_name, message } => write!(f, "Unhandled exception. {}: {}", type_name, message),
+        }
+    }
+}
+
+/// 附带了托管调用堆栈的错误，调用堆栈在错误第一次经过il_call时记录
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    /// 每一项形如 Void TestCsharp.Program.Main() IL_0012，栈顶在前
+    pub stack_trace: Vec<String>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            kind,
+            stack_trace: Vec::new(),
+        }
+    }
+}
+
+impl From<RuntimeErrorKind> for RuntimeError {
+    fn from(kind: RuntimeErrorKind) -> Self {
+        RuntimeError::new(kind)
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> Self {
+        RuntimeError::new(RuntimeErrorKind::BadImage(e.to_string()))
+    }
+}
+
+impl From<ILTypeError> for RuntimeError {
+    fn from(e: ILTypeError) -> Self {
+        RuntimeError::new(match e {
+            ILTypeError::DivideByZero => RuntimeErrorKind::DivideByZero,
+            ILTypeError::Overflow => RuntimeErrorKind::Overflow,
+            ILTypeError::NotFinite => RuntimeErrorKind::Arithmetic(String::from("Number encountered was not a finite quantity.")),
+            ILTypeError::InvalidOperation(message) => RuntimeErrorKind::TypeMismatch(message),
+        })
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        for frame in self.stack_trace.iter() {
+            write!(f, "\n    at {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn managed_exception_type(e: impl Into<RuntimeError>) -> Option<&'static str> {
+        e.into().kind.to_managed_exception().map(|(type_name, _)| type_name)
+    }
+
+    #[test]
+    fn runtime_faults_map_to_managed_exceptions() {
+        assert_eq!(managed_exception_type(ILTypeError::DivideByZero), Some("System.DivideByZeroException"));
+        assert_eq!(managed_exception_type(ILTypeError::Overflow), Some("System.OverflowException"));
+        assert_eq!(managed_exception_type(ILTypeError::NotFinite), Some("System.ArithmeticException"));
+        assert_eq!(managed_exception_type(RuntimeErrorKind::NullReference), Some("System.NullReferenceException"));
+        assert_eq!(managed_exception_type(RuntimeErrorKind::IndexOutOfRange), Some("System.IndexOutOfRangeException"));
+        assert_eq!(managed_exception_type(RuntimeErrorKind::StackOverflow), Some("System.StackOverflowException"));
+        // 解释器自身的错误不能被托管代码捕获
+        assert_eq!(managed_exception_type(ILTypeError::InvalidOperation(String::from("int64 + int32"))), None);
+        assert_eq!(managed_exception_type(RuntimeErrorKind::BadImage(String::from("truncated"))), None);
+    }
+
+    #[test]
+    fn unhandled_exception_is_reported_with_stack_trace() {
+        let mut error = RuntimeError::new(RuntimeErrorKind::UnhandledException {
+            type_name: String::from("System.DivideByZeroException"),
+            message: String::from("Attempted to divide by zero."),
+        });
+        error.stack_trace.push(String::from("Int32 TestCsharp.Program.Div(Int32, Int32) IL_0002"));
+        error.stack_trace.push(String::from("Void TestCsharp.Program.Main() IL_0010"));
+        assert_eq!(error.to_string(), "Unhandled exception. System.DivideByZeroException: Attempted to divide by zero.\n    \
+            at Int32 TestCsharp.Program.Div(Int32, Int32) IL_0002\n    at Void TestCsharp.Program.Main() IL_0010");
+    }
+}
@@ -0,0 +1,144 @@
+/// 运行时的类型标识，同一个类型无论从哪个Assembly引用，解析之后都相等
+/// 类型定义用(assembly_index, type_def_index)表示
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RuntimeType {
+    /// 类、接口或者值类型，泛型实例化时带有类型实参，为空表示非泛型或者实例化未知
+    Type((usize, usize), Vec<RuntimeType>),
+    /// 一维且下界为0的数组
+    SZArray(Box<RuntimeType>),
+    /// 多维数组，u32为秩
+    Array(Box<RuntimeType>, u32),
+    /// 托管指针，只出现在方法签名中
+    ByRef(Box<RuntimeType>),
+    /// 非托管指针，只出现在方法签名中
+    Pointer(Box<RuntimeType>),
+    /// 构建虚表时比较方法签名用的未实例化的!n，只和序号相同的!n相等
+    Var(u32),
+    /// 构建虚表时比较方法签名用的未实例化的!!n
+    MVar(u32),
+}
+
+impl RuntimeType {
+    /// 判断两个类型是否为同一个类型，未知的实例化可以匹配任何类型实参
+    pub fn is_same_type(&self, other: &RuntimeType) -> bool {
+        match (self, other) {
+            (RuntimeType::Type(type_def, args), RuntimeType::Type(other_type_def, other_args)) => {
+                type_def == other_type_def && (args.is_empty() || other_args.is_empty()
+                    || (args.len() == other_args.len() && args.iter().zip(other_args.iter()).all(|(a, b)| a.is_same_type(b))))
+            },
+            (RuntimeType::SZArray(element), RuntimeType::SZArray(other_element)) => element.is_same_type(other_element),
+            (RuntimeType::Array(element, rank), RuntimeType::Array(other_element, other_rank)) => rank == other_rank && element.is_same_type(other_element),
+            (RuntimeType::ByRef(element), RuntimeType::ByRef(other_element)) => element.is_same_type(other_element),
+            (RuntimeType::Pointer(element), RuntimeType::Pointer(other_element)) => element.is_same_type(other_element),
+            (RuntimeType::Var(number), RuntimeType::Var(other_number)) | (RuntimeType::MVar(number), RuntimeType::MVar(other_number)) => number == other_number,
+            _ => false,
+        }
+    }
+
+    /// 把Var(n)替换为type_args[n]，超出范围的保持不变
+    pub fn substitute(&self, type_args: &[RuntimeType]) -> RuntimeType {
+        match self {
+            RuntimeType::Type(type_def, args) => RuntimeType::Type(*type_def, args.iter().map(|arg| arg.substitute(type_args)).collect()),
+            RuntimeType::SZArray(element) => RuntimeType::SZArray(Box::new(element.substitute(type_args))),
+            RuntimeType::Array(element, rank) => RuntimeType::Array(Box::new(element.substitute(type_args)), *rank),
+            RuntimeType::ByRef(element) => RuntimeType::ByRef(Box::new(element.substitute(type_args))),
+            RuntimeType::Pointer(element) => RuntimeType::Pointer(Box::new(element.substitute(type_args))),
+            RuntimeType::Var(number) => type_args.get(*number as usize).cloned().unwrap_or_else(|| self.clone()),
+            RuntimeType::MVar(_) => self.clone(),
+        }
+    }
+
+    /// 是否包含未实例化的Var或MVar，这样的类型无法检查约束
+    pub fn contains_generic_param(&self) -> bool {
+        match self {
+            RuntimeType::Type(_, args) => args.iter().any(RuntimeType::contains_generic_param),
+            RuntimeType::SZArray(element) | RuntimeType::Array(element, _) | RuntimeType::ByRef(element) | RuntimeType::Pointer(element) => element.contains_generic_param(),
+            RuntimeType::Var(_) | RuntimeType::MVar(_) => true,
+        }
+    }
+}
+
+/// 泛型上下文，用于替换签名中的!n（类型的泛型参数）和!!n（方法的泛型参数）
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GenericContext {
+    pub type_args: Vec<RuntimeType>,
+    pub method_args: Vec<RuntimeType>,
+}
+
+impl GenericContext {
+    pub fn from_type_args(type_args: Vec<RuntimeType>) -> GenericContext {
+        GenericContext {
+            type_args,
+            method_args: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::op_codes::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+    use super::super::Interpreter;
+
+    #[test]
+    fn generic_params_are_substituted_instead_of_matching_any_type() {
+        // static bool Is<T>(object o) => o is T
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let a = builder.define_type("", "A", CLASS, object);
+        let a_ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![]));
+        builder.body(a_ctor, vec![], Code::new().op(OpCode::Ret));
+        builder.define_type("", "Program", CLASS, object);
+        let is = builder.method(PUBLIC | STATIC, "Is", sig::generic_method(false, 1, sig::boolean(), vec![sig::object()]));
+        builder.generic_param(is, 0, "T");
+        let t = builder.type_spec(sig::mvar(0));
+        builder.body(is, vec![], Code::new().op(OpCode::Ldarg0).op_token(OpCode::Isinst, t).op(OpCode::Ldnull)
+            .op2(OpCode2::Cgtun).op(OpCode::Ret));
+        let mains = vec![Some(sig::class(a)), Some(sig::string()), None].into_iter().enumerate().map(|(i, type_arg)| {
+            let main = builder.method(PUBLIC | STATIC, &format!("Main{}", i), sig::method(false, sig::boolean(), vec![]));
+            let callee = match type_arg {
+                Some(type_arg) => builder.method_spec(is, vec![type_arg]),
+                None => is,  // 没有实例化，!!0无法替换
+            };
+            builder.body(main, vec![], Code::new().op_token(OpCode::Newobj, a_ctor).op_token(OpCode::Call, callee).op(OpCode::Ret));
+            main
+        }).collect::<Vec<_>>();
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let formatted = |interpreter: &mut Interpreter, main| call(interpreter, main).map(|result| interpreter.format_il_type(&result.unwrap()));
+        assert_eq!(formatted(&mut interpreter, mains[0]).unwrap(), "1");
+        assert_eq!(formatted(&mut interpreter, mains[1]).unwrap(), "0");
+        let error = call(&mut interpreter, mains[2]).unwrap_err();
+        assert_eq!(exception_type(&interpreter, &error).as_deref(), Some("System.TypeLoadException"));
+    }
+
+    #[test]
+    fn each_instantiation_of_a_generic_type_has_its_own_statics() {
+        // class G<T> { static int s; static T t; }
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let g = builder.define_type("", "G`1", CLASS, object);
+        builder.field(FIELD_PUBLIC | FIELD_STATIC, "s", sig::int32());
+        builder.field(FIELD_PUBLIC | FIELD_STATIC, "t", sig::var(0));
+        builder.generic_param(g, 0, "T");
+        let g_int = builder.type_spec(sig::generic_inst(sig::class(g), vec![sig::int32()]));
+        let g_string = builder.type_spec(sig::generic_inst(sig::class(g), vec![sig::string()]));
+        let (int_s, string_s) = (builder.member_ref(g_int, "s", sig::field(sig::int32())), builder.member_ref(g_string, "s", sig::field(sig::int32())));
+        let (int_t, string_t) = (builder.member_ref(g_int, "t", sig::field(sig::var(0))), builder.member_ref(g_string, "t", sig::field(sig::var(0))));
+        builder.define_type("", "Program", CLASS, object);
+        // G<int>.s = 1; G<string>.s = 2; return G<int>.s * 10 + G<string>.s;
+        let statics = builder.method(PUBLIC | STATIC, "Statics", sig::method(false, sig::int32(), vec![]));
+        builder.body(statics, vec![], Code::new()
+            .op(OpCode::Ldci41).op_token(OpCode::Stsfld, int_s).op(OpCode::Ldci42).op_token(OpCode::Stsfld, string_s)
+            .op_token(OpCode::Ldsfld, int_s).ldc_i4(10).op(OpCode::Mul).op_token(OpCode::Ldsfld, string_s).op(OpCode::Add).op(OpCode::Ret));
+        // return G<int>.t * 10 + (G<string>.t == null);
+        let layouts = builder.method(PUBLIC | STATIC, "Layouts", sig::method(false, sig::int32(), vec![]));
+        builder.body(layouts, vec![], Code::new()
+            .op_token(OpCode::Ldsfld, int_t).ldc_i4(10).op(OpCode::Mul)
+            .op_token(OpCode::Ldsfld, string_t).op(OpCode::Ldnull).op2(OpCode2::Ceq).op(OpCode::Add).op(OpCode::Ret));
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let result = call(&mut interpreter, statics).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "12");
+        let result = call(&mut interpreter, layouts).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "1");
+    }
+}
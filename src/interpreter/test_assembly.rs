@@ -0,0 +1,612 @@
+//! 测试用的Assembly：在内存中按照Assembly::from_bytes读取的布局写出PE镜像、元数据表和方法体
+//! 没有C#编译器时，用它构造需要特定元数据（虚方法、泛型、委托等）的测试程序
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{Assembly, Context, Interpreter, MemoryResolver, RuntimeError, RuntimeErrorKind};
+use super::il_type::ILType;
+use super::op_codes::{OpCode, OpCode2};
+use super::runtime_type::GenericContext;
+
+pub const COR_LIB_NAME: &str = "System.Private.CoreLib";
+
+/// MethodDef的Flags
+pub const PUBLIC: u16 = 0x0006;
+pub const STATIC: u16 = 0x0010;
+pub const FINAL: u16 = 0x0020;
+pub const VIRTUAL: u16 = 0x0040;
+pub const HIDE_BY_SIG: u16 = 0x0080;
+pub const NEW_SLOT: u16 = 0x0100;
+pub const ABSTRACT: u16 = 0x0400;
+pub const CTOR: u16 = PUBLIC | HIDE_BY_SIG | 0x0800 | 0x1000;  // SpecialName | RTSpecialName
+/// MethodDef的ImplFlags，由运行时实现的方法（委托的.ctor和Invoke）
+pub const RUNTIME: u16 = 0x0003;
+
+/// TypeDef的Flags
+pub const CLASS: u32 = 0x0001;  // public
+pub const INTERFACE: u32 = 0x0001 | 0x0020 | 0x0080;
+pub const ABSTRACT_CLASS: u32 = 0x0001 | 0x0080;
+pub const SEALED_CLASS: u32 = 0x0001 | 0x0100;
+
+/// Field的Flags
+pub const FIELD_PUBLIC: u16 = 0x0006;
+pub const FIELD_STATIC: u16 = 0x0010;
+
+const TYPE_REF: usize = 0x01;
+const TYPE_DEF: usize = 0x02;
+const FIELD: usize = 0x04;
+const METHOD_DEF: usize = 0x06;
+const INTERFACE_IMPL: usize = 0x09;
+const MEMBER_REF: usize = 0x0A;
+const STANDALONE_SIG: usize = 0x11;
+const METHOD_IMPL: usize = 0x19;
+const TYPE_SPEC: usize = 0x1B;
+const ASSEMBLY_REF: usize = 0x23;
+const GENERIC_PARAM: usize = 0x2A;
+const METHOD_SPEC: usize = 0x2B;
+
+/// 按ECMA-335 II.23.2压缩的无符号整数
+fn compress(value: u32) -> Vec<u8> {
+    if value < 0x80 {
+        vec![value as u8]
+    } else if value < 0x4000 {
+        (0x8000 | value as u16).to_be_bytes().to_vec()
+    } else {
+        (0xC000_0000 | value).to_be_bytes().to_vec()
+    }
+}
+
+/// 签名中的TypeDefOrRef编码
+fn type_def_or_ref(token: u32) -> u32 {
+    let tag = match token >> 24 {
+        0x02 => 0,
+        0x01 => 1,
+        0x1B => 2,
+        _ => panic!("0x{:08X} is not a TypeDef, TypeRef or TypeSpec", token),
+    };
+    ((token & 0x00FFFFFF) << 2) | tag
+}
+
+fn member_ref_parent(token: u32) -> u32 {
+    let tag = match token >> 24 {
+        0x02 => 0,
+        0x01 => 1,
+        0x1A => 2,
+        0x06 => 3,
+        0x1B => 4,
+        _ => panic!("0x{:08X} is not a MemberRefParent", token),
+    };
+    ((token & 0x00FFFFFF) << 3) | tag
+}
+
+fn method_def_or_ref(token: u32) -> u32 {
+    let tag = match token >> 24 {
+        0x06 => 0,
+        0x0A => 1,
+        _ => panic!("0x{:08X} is not a MethodDef or MemberRef", token),
+    };
+    ((token & 0x00FFFFFF) << 1) | tag
+}
+
+/// 类型签名，见ECMA-335 II.23.2.12
+pub mod sig {
+    use super::{compress, type_def_or_ref};
+
+    pub fn void() -> Vec<u8> { vec![0x01] }
+    pub fn boolean() -> Vec<u8> { vec![0x02] }
+    pub fn int32() -> Vec<u8> { vec![0x08] }
+    pub fn string() -> Vec<u8> { vec![0x0E] }
+    pub fn native_int() -> Vec<u8> { vec![0x18] }
+    pub fn object() -> Vec<u8> { vec![0x1C] }
+
+    pub fn class(token: u32) -> Vec<u8> {
+        [vec![0x12], compress(type_def_or_ref(token))].concat()
+    }
+
+    pub fn value_type(token: u32) -> Vec<u8> {
+        [vec![0x11], compress(type_def_or_ref(token))].concat()
+    }
+
+    /// !number
+    pub fn var(number: u32) -> Vec<u8> {
+        [vec![0x13], compress(number)].concat()
+    }
+
+    /// !!number
+    pub fn mvar(number: u32) -> Vec<u8> {
+        [vec![0x1E], compress(number)].concat()
+    }
+
+    pub fn sz_array(element: Vec<u8>) -> Vec<u8> {
+        [vec![0x1D], element].concat()
+    }
+
+    pub fn by_ref(element: Vec<u8>) -> Vec<u8> {
+        [vec![0x10], element].concat()
+    }
+
+    /// generic_type为class或value_type
+    pub fn generic_inst(generic_type: Vec<u8>, args: Vec<Vec<u8>>) -> Vec<u8> {
+        [vec![0x15], generic_type, compress(args.len() as u32), args.concat()].concat()
+    }
+
+    /// 方法签名，has_this为实例方法
+    pub fn method(has_this: bool, ret: Vec<u8>, params: Vec<Vec<u8>>) -> Vec<u8> {
+        [vec![if has_this { 0x20 } else { 0x00 }], compress(params.len() as u32), ret, params.concat()].concat()
+    }
+
+    /// 有generic_count个类型参数的泛型方法的签名
+    pub fn generic_method(has_this: bool, generic_count: u32, ret: Vec<u8>, params: Vec<Vec<u8>>) -> Vec<u8> {
+        [vec![if has_this { 0x30 } else { 0x10 }], compress(generic_count), compress(params.len() as u32), ret, params.concat()].concat()
+    }
+
+    pub fn field(field_type: Vec<u8>) -> Vec<u8> {
+        [vec![0x06], field_type].concat()
+    }
+
+    /// MethodSpec的实例化
+    pub fn method_spec(args: Vec<Vec<u8>>) -> Vec<u8> {
+        [vec![0x0A], compress(args.len() as u32), args.concat()].concat()
+    }
+}
+
+/// 方法体中的IL指令
+#[derive(Default)]
+pub struct Code {
+    bytes: Vec<u8>,
+}
+
+impl Code {
+    pub fn new() -> Code {
+        Default::default()
+    }
+
+    pub fn op(mut self, op: OpCode) -> Code {
+        self.bytes.push(op as u8);
+        self
+    }
+
+    pub fn op_u8(mut self, op: OpCode, operand: u8) -> Code {
+        self.bytes.extend([op as u8, operand]);
+        self
+    }
+
+    pub fn op_token(mut self, op: OpCode, token: u32) -> Code {
+        self.bytes.push(op as u8);
+        self.bytes.extend(token.to_le_bytes());
+        self
+    }
+
+    pub fn op2(mut self, op: OpCode2) -> Code {
+        self.bytes.extend([OpCode::Next as u8, op as u8]);
+        self
+    }
+
+    pub fn op2_u8(mut self, op: OpCode2, operand: u8) -> Code {
+        self.bytes.extend([OpCode::Next as u8, op as u8, operand]);
+        self
+    }
+
+    pub fn op2_token(mut self, op: OpCode2, token: u32) -> Code {
+        self.bytes.extend([OpCode::Next as u8, op as u8]);
+        self.bytes.extend(token.to_le_bytes());
+        self
+    }
+
+    pub fn ldc_i4(mut self, value: i32) -> Code {
+        self.bytes.push(OpCode::Ldci4 as u8);
+        self.bytes.extend(value.to_le_bytes());
+        self
+    }
+}
+
+struct MethodRow {
+    impl_flags: u16,
+    flags: u16,
+    name: u32,
+    signature: u32,
+    /// (code, 局部变量签名的StandAloneSig rid)
+    body: Option<(Vec<u8>, u32)>,
+}
+
+/// 逐个添加元数据行，最后用build写出PE镜像
+/// 方法和字段属于最近一次define_type定义的类型，方法体可以在所有方法都声明之后再用body设置
+pub struct AssemblyBuilder {
+    name: u32,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+    user_strings: Vec<u8>,
+    blobs: Vec<u8>,
+    /// 除TypeDef和MethodDef以外的表，每行是按列写好的字节
+    rows: HashMap<usize, Vec<Vec<u8>>>,
+    /// (flags, name, namespace, extends, field_list, method_list)
+    type_defs: Vec<(u32, u32, u32, u32, u32, u32)>,
+    methods: Vec<MethodRow>,
+    fields: Vec<(u16, u32, u32)>,
+    /// 已经添加的TypeRef，读取时TypeRef按名称存放，同一个类型只能有一行 <(scope, namespace, name), token>
+    type_refs: HashMap<(u32, String, String), u32>,
+    /// 指向核心库的AssemblyRef，第一次调用cor_lib_type时添加
+    cor_lib_ref: Option<u32>,
+}
+
+impl AssemblyBuilder {
+    pub fn new(name: &str) -> AssemblyBuilder {
+        let mut builder = AssemblyBuilder {
+            name: 0,
+            strings: vec![0],
+            string_offsets: HashMap::new(),
+            user_strings: vec![0],
+            blobs: vec![0],
+            rows: HashMap::new(),
+            type_defs: Vec::new(),
+            methods: Vec::new(),
+            fields: Vec::new(),
+            type_refs: HashMap::new(),
+            cor_lib_ref: None,
+        };
+        builder.name = builder.string(name);
+        builder.define_type("", "<Module>", 0, 0);
+        builder
+    }
+
+    fn string(&mut self, value: &str) -> u32 {
+        if value.is_empty() {
+            return 0;
+        }
+        if let Some(offset) = self.string_offsets.get(value) {
+            return *offset;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend(value.as_bytes());
+        self.strings.push(0);
+        self.string_offsets.insert(value.to_string(), offset);
+        offset
+    }
+
+    fn blob(&mut self, value: &[u8]) -> u32 {
+        let offset = self.blobs.len() as u32;
+        self.blobs.extend(compress(value.len() as u32));
+        self.blobs.extend(value);
+        offset
+    }
+
+    /// ldstr使用的token
+    pub fn user_string(&mut self, value: &str) -> u32 {
+        let offset = self.user_strings.len() as u32;
+        let utf16 = value.encode_utf16().flat_map(|c| c.to_le_bytes()).collect::<Vec<_>>();
+        self.user_strings.extend(compress(utf16.len() as u32 + 1));
+        self.user_strings.extend(utf16);
+        self.user_strings.push(0);
+        0x70000000 | offset
+    }
+
+    fn push_row(&mut self, table: usize, columns: &[u16]) -> u32 {
+        let rows = self.rows.entry(table).or_default();
+        rows.push(columns.iter().flat_map(|column| column.to_le_bytes()).collect());
+        ((table as u32) << 24) | rows.len() as u32
+    }
+
+    pub fn assembly_ref(&mut self, name: &str) -> u32 {
+        let name = self.string(name) as u16;
+        let rows = self.rows.entry(ASSEMBLY_REF).or_default();
+        // MajorVersion, MinorVersion, BuildNumber, RevisionNumber, Flags, PublicKeyOrToken, Name, Culture, HashValue
+        let mut row = vec![0u8; 14];
+        row.extend(name.to_le_bytes());
+        row.extend([0u8; 4]);
+        rows.push(row);
+        0x23000000 | rows.len() as u32
+    }
+
+    pub fn type_ref(&mut self, assembly_ref: u32, namespace: &str, name: &str) -> u32 {
+        let key = (assembly_ref, namespace.to_string(), name.to_string());
+        if let Some(token) = self.type_refs.get(&key) {
+            return *token;
+        }
+        let scope = ((assembly_ref & 0x00FFFFFF) << 2) | 2;
+        let (name, namespace) = (self.string(name), self.string(namespace));
+        let token = self.push_row(TYPE_REF, &[scope as u16, name as u16, namespace as u16]);
+        self.type_refs.insert(key, token);
+        token
+    }
+
+    /// 核心库中类型的TypeRef
+    pub fn cor_lib_type(&mut self, namespace: &str, name: &str) -> u32 {
+        let cor_lib_ref = match self.cor_lib_ref {
+            Some(cor_lib_ref) => cor_lib_ref,
+            None => {
+                let cor_lib_ref = self.assembly_ref(COR_LIB_NAME);
+                self.cor_lib_ref = Some(cor_lib_ref);
+                cor_lib_ref
+            },
+        };
+        self.type_ref(cor_lib_ref, namespace, name)
+    }
+
+    pub fn define_type(&mut self, namespace: &str, name: &str, flags: u32, extends: u32) -> u32 {
+        let (name, namespace) = (self.string(name), self.string(namespace));
+        let extends = if extends == 0 { 0 } else { type_def_or_ref(extends) };
+        self.type_defs.push((flags, name, namespace, extends, self.fields.len() as u32 + 1, self.methods.len() as u32 + 1));
+        0x02000000 | self.type_defs.len() as u32
+    }
+
+    pub fn field(&mut self, flags: u16, name: &str, field_type: Vec<u8>) -> u32 {
+        let name = self.string(name);
+        let signature = self.blob(&sig::field(field_type));
+        self.fields.push((flags, name, signature));
+        0x04000000 | self.fields.len() as u32
+    }
+
+    /// 声明一个方法，方法体由body设置
+    pub fn method(&mut self, flags: u16, name: &str, signature: Vec<u8>) -> u32 {
+        let (name, signature) = (self.string(name), self.blob(&signature));
+        self.methods.push(MethodRow { impl_flags: 0, flags, name, signature, body: None });
+        0x06000000 | self.methods.len() as u32
+    }
+
+    /// 没有方法体的方法，例如抽象方法和委托的.ctor、Invoke
+    pub fn method_without_body(&mut self, impl_flags: u16, flags: u16, name: &str, signature: Vec<u8>) -> u32 {
+        let token = self.method(flags, name, signature);
+        self.methods.last_mut().unwrap().impl_flags = impl_flags;
+        token
+    }
+
+    pub fn body(&mut self, method: u32, locals: Vec<Vec<u8>>, code: Code) {
+        let local_var_rid = if locals.is_empty() {
+            0
+        } else {
+            let signature = [vec![0x07], compress(locals.len() as u32), locals.concat()].concat();
+            let signature = self.blob(&signature) as u16;
+            self.push_row(STANDALONE_SIG, &[signature]) & 0x00FFFFFF
+        };
+        self.methods[(method & 0x00FFFFFF) as usize - 1].body = Some((code.bytes, local_var_rid));
+    }
+
+    pub fn interface_impl(&mut self, class: u32, interface: u32) {
+        self.push_row(INTERFACE_IMPL, &[(class & 0x00FFFFFF) as u16, type_def_or_ref(interface) as u16]);
+    }
+
+    pub fn member_ref(&mut self, parent: u32, name: &str, signature: Vec<u8>) -> u32 {
+        let (name, signature) = (self.string(name), self.blob(&signature));
+        self.push_row(MEMBER_REF, &[member_ref_parent(parent) as u16, name as u16, signature as u16])
+    }
+
+    pub fn method_impl(&mut self, class: u32, body: u32, declaration: u32) {
+        self.push_row(METHOD_IMPL, &[(class & 0x00FFFFFF) as u16, method_def_or_ref(body) as u16, method_def_or_ref(declaration) as u16]);
+    }
+
+    pub fn type_spec(&mut self, signature: Vec<u8>) -> u32 {
+        let signature = self.blob(&signature);
+        self.push_row(TYPE_SPEC, &[signature as u16])
+    }
+
+    /// owner为TypeDef或MethodDef
+    pub fn generic_param(&mut self, owner: u32, number: u16, name: &str) -> u32 {
+        let owner = match owner >> 24 {
+            0x02 => (owner & 0x00FFFFFF) << 1,
+            _ => ((owner & 0x00FFFFFF) << 1) | 1,
+        };
+        let name = self.string(name);
+        self.push_row(GENERIC_PARAM, &[number, 0, owner as u16, name as u16])
+    }
+
+    pub fn method_spec(&mut self, method: u32, args: Vec<Vec<u8>>) -> u32 {
+        let instantiation = self.blob(&sig::method_spec(args));
+        self.push_row(METHOD_SPEC, &[method_def_or_ref(method) as u16, instantiation as u16])
+    }
+
+    /// #~流：表头、每个表的行数和所有行，所有堆和表都很小，索引都是2字节
+    fn table_stream(&self, method_rvas: &[u32]) -> Vec<u8> {
+        let mut tables: Vec<(usize, Vec<Vec<u8>>)> = Vec::new();
+        // Module: Generation, Name, Mvid, EncId, EncBaseId
+        tables.push((0x00, vec![[0u16, self.name as u16, 0, 0, 0].iter().flat_map(|c| c.to_le_bytes()).collect()]));
+        let type_defs = self.type_defs.iter().map(|(flags, name, namespace, extends, field_list, method_list)| {
+            let mut row = flags.to_le_bytes().to_vec();
+            row.extend([*name as u16, *namespace as u16, *extends as u16, *field_list as u16, *method_list as u16].iter().flat_map(|c| c.to_le_bytes()));
+            row
+        }).collect();
+        tables.push((TYPE_DEF, type_defs));
+        tables.push((FIELD, self.fields.iter().map(|(flags, name, signature)| {
+            [*flags, *name as u16, *signature as u16].iter().flat_map(|c| c.to_le_bytes()).collect()
+        }).collect()));
+        tables.push((METHOD_DEF, self.methods.iter().zip(method_rvas).map(|(method, rva)| {
+            let mut row = rva.to_le_bytes().to_vec();
+            row.extend([method.impl_flags, method.flags, method.name as u16, method.signature as u16, 1].iter().flat_map(|c| c.to_le_bytes()));
+            row
+        }).collect()));
+        // Assembly: HashAlgId, MajorVersion, MinorVersion, BuildNumber, RevisionNumber, Flags, PublicKey, Name, Culture
+        let mut assembly = vec![0u8; 18];
+        assembly.extend((self.name as u16).to_le_bytes());
+        assembly.extend([0u8; 2]);
+        tables.push((0x20, vec![assembly]));
+        for (table, rows) in self.rows.iter() {
+            tables.push((*table, rows.clone()));
+        }
+        tables.sort_by_key(|(table, _)| *table);
+        tables.retain(|(_, rows)| !rows.is_empty());
+
+        let mut stream = vec![0, 0, 0, 0, 2, 0, 0, 1];  // Reserved, MajorVersion, MinorVersion, HeapSizes, Reserved
+        let valid = tables.iter().fold(0u64, |valid, (table, _)| valid | (1 << table));
+        stream.extend(valid.to_le_bytes());
+        stream.extend(0u64.to_le_bytes());  // Sorted
+        for (_, rows) in tables.iter() {
+            stream.extend((rows.len() as u32).to_le_bytes());
+        }
+        for (_, rows) in tables.iter() {
+            stream.extend(rows.concat());
+        }
+        stream
+    }
+
+    /// 没有节的PE32+镜像，rva_to_file_offset找不到节时RVA就是文件偏移
+    pub fn build(&self) -> Vec<u8> {
+        const NT_HEADERS: usize = 0x40;
+        const OPTIONAL_HEADER: usize = NT_HEADERS + 0x18;
+        const OPTIONAL_HEADER_SIZE: usize = 0xF0;
+        const CLI_HEADER: usize = OPTIONAL_HEADER + OPTIONAL_HEADER_SIZE;
+        const CLI_HEADER_SIZE: usize = 0x48;
+
+        let mut image = vec![0u8; CLI_HEADER + CLI_HEADER_SIZE];
+        image[0..2].copy_from_slice(b"MZ");
+        image[0x3C..0x40].copy_from_slice(&(NT_HEADERS as u32).to_le_bytes());
+        image[NT_HEADERS..NT_HEADERS + 4].copy_from_slice(b"PE\0\0");
+        image[NT_HEADERS + 4..NT_HEADERS + 6].copy_from_slice(&0x8664u16.to_le_bytes());
+        image[NT_HEADERS + 0x14..NT_HEADERS + 0x16].copy_from_slice(&(OPTIONAL_HEADER_SIZE as u16).to_le_bytes());
+        image[OPTIONAL_HEADER..OPTIONAL_HEADER + 2].copy_from_slice(&0x020Bu16.to_le_bytes());
+        let cli_directory = OPTIONAL_HEADER + 0x70 + 14 * 8;
+        image[cli_directory..cli_directory + 4].copy_from_slice(&(CLI_HEADER as u32).to_le_bytes());
+        image[cli_directory + 4..cli_directory + 8].copy_from_slice(&(CLI_HEADER_SIZE as u32).to_le_bytes());
+
+        // 方法体，都使用fat header
+        let mut method_rvas = Vec::with_capacity(self.methods.len());
+        for method in self.methods.iter() {
+            match &method.body {
+                Some((code, local_var_rid)) => {
+                    while image.len() % 4 != 0 {
+                        image.push(0);
+                    }
+                    method_rvas.push(image.len() as u32);
+                    image.extend(0x3013u16.to_le_bytes());  // Fat, InitLocals, 3个DWORD
+                    image.extend(8u16.to_le_bytes());
+                    image.extend((code.len() as u32).to_le_bytes());
+                    let local_var_token = if *local_var_rid == 0 { 0 } else { 0x11000000 | local_var_rid };
+                    image.extend(local_var_token.to_le_bytes());
+                    image.extend(code);
+                },
+                None => method_rvas.push(0),
+            }
+        }
+        while image.len() % 4 != 0 {
+            image.push(0);
+        }
+
+        let pad = |mut heap: Vec<u8>| {
+            while heap.len() % 4 != 0 {
+                heap.push(0);
+            }
+            heap
+        };
+        let streams = [
+            ("#~", pad(self.table_stream(&method_rvas))),
+            ("#Strings", pad(self.strings.clone())),
+            ("#US", pad(self.user_strings.clone())),
+            ("#GUID", Vec::new()),
+            ("#Blob", pad(self.blobs.clone())),
+        ];
+        let version = b"v4.0.30319\0\0";
+        let header_size = 16 + version.len() + 4 + streams.iter().map(|(name, _)| 8 + (name.len() + 4) / 4 * 4).sum::<usize>();
+        let mut metadata = Vec::new();
+        metadata.extend(0x424A5342u32.to_le_bytes());
+        metadata.extend([1, 0, 1, 0, 0, 0, 0, 0]);  // MajorVersion, MinorVersion, Reserved
+        metadata.extend((version.len() as u32).to_le_bytes());
+        metadata.extend(version);
+        metadata.extend([0, 0]);  // Flags
+        metadata.extend((streams.len() as u16).to_le_bytes());
+        let mut offset = header_size;
+        for (name, data) in streams.iter() {
+            metadata.extend((offset as u32).to_le_bytes());
+            metadata.extend((data.len() as u32).to_le_bytes());
+            let mut name = name.as_bytes().to_vec();
+            name.resize((name.len() + 4) / 4 * 4, 0);
+            metadata.extend(name);
+            offset += data.len();
+        }
+        for (_, data) in streams.iter() {
+            metadata.extend(data);
+        }
+
+        let metadata_rva = image.len() as u32;
+        image[CLI_HEADER..CLI_HEADER + 4].copy_from_slice(&(CLI_HEADER_SIZE as u32).to_le_bytes());
+        image[CLI_HEADER + 4..CLI_HEADER + 8].copy_from_slice(&[2, 0, 5, 0]);
+        image[CLI_HEADER + 8..CLI_HEADER + 12].copy_from_slice(&metadata_rva.to_le_bytes());
+        image[CLI_HEADER + 12..CLI_HEADER + 16].copy_from_slice(&(metadata.len() as u32).to_le_bytes());
+        image.extend(metadata);
+        image
+    }
+}
+
+/// 最小的核心库：Object（虚方法ToString、Equals、GetHashCode）、String（重写ToString）、ValueType、Enum、基元类型、
+/// Array、Delegate、MulticastDelegate以及运行时错误对应的异常类型
+pub fn cor_lib() -> Vec<u8> {
+    let mut builder = AssemblyBuilder::new(COR_LIB_NAME);
+    let object_name = builder.user_string("System.Object");
+
+    let object = builder.define_type("System", "Object", CLASS, 0);
+    let object_ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![]));
+    builder.body(object_ctor, vec![], Code::new().op(OpCode::Ret));
+    let to_string = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "ToString", sig::method(true, sig::string(), vec![]));
+    builder.body(to_string, vec![], Code::new().op_token(OpCode::Ldstr, object_name).op(OpCode::Ret));
+    let equals = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Equals", sig::method(true, sig::boolean(), vec![sig::object()]));
+    builder.body(equals, vec![], Code::new().op(OpCode::Ldarg0).op(OpCode::Ldarg1).op2(OpCode2::Ceq).op(OpCode::Ret));
+    let get_hash_code = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "GetHashCode", sig::method(true, sig::int32(), vec![]));
+    builder.body(get_hash_code, vec![], Code::new().op(OpCode::Ldci40).op(OpCode::Ret));
+
+    builder.define_type("System", "String", SEALED_CLASS, object);
+    let string_to_string = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL, "ToString", sig::method(true, sig::string(), vec![]));
+    builder.body(string_to_string, vec![], Code::new().op(OpCode::Ldarg0).op(OpCode::Ret));
+
+    let value_type = builder.define_type("System", "ValueType", ABSTRACT_CLASS, object);
+    builder.define_type("System", "Enum", ABSTRACT_CLASS, value_type);
+    for name in ["Void", "Boolean", "Char", "SByte", "Byte", "Int16", "UInt16", "Int32", "UInt32", "Int64", "UInt64", "Single", "Double", "IntPtr", "UIntPtr"] {
+        builder.define_type("System", name, SEALED_CLASS, value_type);
+    }
+    builder.define_type("System", "Array", ABSTRACT_CLASS, object);
+    let delegate = builder.define_type("System", "Delegate", ABSTRACT_CLASS, object);
+    builder.define_type("System", "MulticastDelegate", ABSTRACT_CLASS, delegate);
+
+    let exception = builder.define_type("System", "Exception", CLASS, object);
+    builder.field(0x0001, "_message", sig::string());  // private
+    for name in ["NullReferenceException", "InvalidCastException", "IndexOutOfRangeException", "ArrayTypeMismatchException",
+        "TypeLoadException", "MissingMethodException", "ArgumentException", "OverflowException", "ArithmeticException",
+        "DivideByZeroException", "StackOverflowException"] {
+        builder.define_type("System", name, CLASS, exception);
+    }
+    builder.build()
+}
+
+/// 以image为入口Assembly创建Interpreter，libraries（<名称, 镜像>）和核心库通过MemoryResolver加载
+pub fn interpreter(image: Vec<u8>, libraries: Vec<(&str, Vec<u8>)>) -> Interpreter {
+    let mut interpreter = Interpreter::from_assembly(Assembly::from_bytes(image, false).unwrap());
+    let mut resolver = MemoryResolver::new();
+    resolver.insert(COR_LIB_NAME, cor_lib());
+    for (name, library) in libraries {
+        resolver.insert(name, library);
+    }
+    interpreter.add_resolver(Box::new(resolver));
+    interpreter
+}
+
+/// 没有被捕获的托管异常的类型全名，其他错误返回None
+pub fn exception_type(interpreter: &Interpreter, error: &RuntimeError) -> Option<String> {
+    match error.kind {
+        RuntimeErrorKind::Exception { object, .. } => Some(interpreter.describe_exception(object).0),
+        _ => None,
+    }
+}
+
+/// 调用入口Assembly中没有参数的静态方法，返回它的返回值
+pub fn call(interpreter: &mut Interpreter, method: u32) -> Result<Option<ILType>, RuntimeError> {
+    let assembly = Rc::clone(interpreter.assemblies.index_get(0).unwrap());
+    let stack_len = interpreter.stack.len();
+    interpreter.il_call(&mut Context::new(&assembly, 0), method, &GenericContext::default())?;
+    Ok(if interpreter.stack.len() > stack_len { interpreter.stack.pop_back() } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_static_method() {
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let to_string = builder.member_ref(object, "ToString", sig::method(true, sig::string(), vec![]));
+        let object_ctor = builder.member_ref(object, ".ctor", sig::method(true, sig::void(), vec![]));
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::string(), vec![]));
+        builder.body(main, vec![], Code::new().op_token(OpCode::Newobj, object_ctor).op_token(OpCode::Callvirt, to_string).op(OpCode::Ret));
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let result = call(&mut interpreter, main).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "System.Object");
+    }
+}
@@ -2,7 +2,7 @@ use std::io;
 use likely_stable::unlikely;
 
 use crate::hash_vec::HashVec;
-use super::metadata::{Metadata, RidList, md_token::CodedToken, table_stream::MDType};
+use super::{generic_param::GenericParam, metadata::{Metadata, RidList, md_token::CodedToken, table_stream::MDType}};
 
 pub struct TypeDef {
     pub token: u32,  // 形如0x02000001
@@ -12,6 +12,10 @@ pub struct TypeDef {
     pub extends: u32,
     pub field_list: RidList,
     pub method_list: RidList,
+    /// 直接声明实现的接口，TypeDef、TypeRef或TypeSpec的token，不包括基类和接口继承来的接口
+    pub interfaces: Vec<u32>,
+    /// 泛型类型的类型参数，按序号排列
+    pub generic_params: Vec<GenericParam>,
 }
 
 impl TypeDef {
@@ -35,6 +39,8 @@ impl TypeDef {
                 extends,
                 field_list,
                 method_list,
+                interfaces: Vec::new(),
+                generic_params: Vec::new(),
             };
 
             if unlikely(type_defs.contains(&full_name)) {  // 有些时候可能存在相同的fullname（就nm离谱）
@@ -54,6 +60,15 @@ impl TypeDef {
                 type_defs.insert(full_name, type_def);
             }
         }
+
+        let interface_impl_table = &metadata.table_stream.md_tables[0x09];
+        for row in 0..interface_impl_table.row_count {
+            let class = interface_impl_table.columns[0].get_cell_u16_or_u32(row);
+            let interface = CodedToken::from_md_type(MDType::TypeDefOrRef).decode(interface_impl_table.columns[1].get_cell_u16_or_u32(row)).unwrap();
+            if let Some(type_def) = type_defs.index_get_mut(class as usize - 1) {
+                type_def.interfaces.push(interface);
+            }
+        }
         
         Ok(type_defs)
     }
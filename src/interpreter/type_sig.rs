@@ -295,6 +295,55 @@ pub enum CorLibType {
     Object,
 }
 
+impl CorLibType {
+    pub fn full_name(&self) -> &'static str {
+        match self {
+            CorLibType::Void => "System.Void",
+            CorLibType::Boolean => "System.Boolean",
+            CorLibType::Char => "System.Char",
+            CorLibType::SByte => "System.SByte",
+            CorLibType::Byte => "System.Byte",
+            CorLibType::Int16 => "System.Int16",
+            CorLibType::UInt16 => "System.UInt16",
+            CorLibType::Int32 => "System.Int32",
+            CorLibType::UInt32 => "System.UInt32",
+            CorLibType::Int64 => "System.Int64",
+            CorLibType::UInt64 => "System.UInt64",
+            CorLibType::Single => "System.Single",
+            CorLibType::Double => "System.Double",
+            CorLibType::String => "System.String",
+            CorLibType::TypedReference => "System.TypedReference",
+            CorLibType::IntPtr => "System.IntPtr",
+            CorLibType::UIntPtr => "System.UIntPtr",
+            CorLibType::Object => "System.Object",
+        }
+    }
+
+    /// 根据类型全名（例如System.Int32）找到对应的CorLibType，System.Void不能作为值的类型，所以返回None
+    pub fn from_full_name(full_name: &str) -> Option<CorLibType> {
+        Some(match full_name {
+            "System.Boolean" => CorLibType::Boolean,
+            "System.Char" => CorLibType::Char,
+            "System.SByte" => CorLibType::SByte,
+            "System.Byte" => CorLibType::Byte,
+            "System.Int16" => CorLibType::Int16,
+            "System.UInt16" => CorLibType::UInt16,
+            "System.Int32" => CorLibType::Int32,
+            "System.UInt32" => CorLibType::UInt32,
+            "System.Int64" => CorLibType::Int64,
+            "System.UInt64" => CorLibType::UInt64,
+            "System.Single" => CorLibType::Single,
+            "System.Double" => CorLibType::Double,
+            "System.String" => CorLibType::String,
+            "System.TypedReference" => CorLibType::TypedReference,
+            "System.IntPtr" => CorLibType::IntPtr,
+            "System.UIntPtr" => CorLibType::UIntPtr,
+            "System.Object" => CorLibType::Object,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq)]
 pub struct TypeSigBase {
     pub rid: u32,
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+
+use super::runtime_type::RuntimeType;
+
+/// 类型的虚方法表和接口映射，子类的虚表以基类的虚表为前缀，所以同一个虚方法在整个继承链上的槽位相同
+/// 方法都用(assembly_index, method_index)表示，接口是以这个类型的!n为Var(n)的实例化
+#[derive(Clone, Debug, Default)]
+pub struct VTable {
+    pub slots: Vec<VTableSlot>,
+    /// 实现的所有接口，包括从基类和接口继承来的接口
+    pub interfaces: Vec<RuntimeType>,
+    /// (接口的实例化, 接口方法)到这个类型中实际执行的方法的映射，同一个泛型接口的不同实例化各自映射
+    pub interface_impls: HashMap<(RuntimeType, (usize, usize)), (usize, usize)>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VTableSlot {
+    /// 第一次引入这个槽位的方法
+    pub declaration: (usize, usize),
+    /// 这个类型调用该槽位时实际执行的方法
+    pub body: (usize, usize),
+}
+
+impl VTable {
+    /// 查找method所在的槽位，method可以是引入槽位的方法或者当前的实现
+    pub fn find_slot(&self, method: (usize, usize)) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.body == method)
+            .or_else(|| self.slots.iter().position(|slot| slot.declaration == method))
+    }
+
+    /// 子类继承的虚表，接口中基类的Var(n)替换为基类在子类中的类型实参type_args
+    pub fn instantiate(&self, type_args: &[RuntimeType]) -> VTable {
+        VTable {
+            slots: self.slots.clone(),
+            interfaces: self.interfaces.iter().map(|interface| interface.substitute(type_args)).collect(),
+            interface_impls: self.interface_impls.iter()
+                .map(|((interface, method), body)| ((interface.substitute(type_args), *method), *body))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::op_codes::{OpCode, OpCode2};
+    use super::super::test_assembly::*;
+
+    /// receiver把this压入求值栈，然后callvirt Object.ToString()，返回结果字符串
+    fn call_to_string(receiver: impl FnOnce(&mut AssemblyBuilder, Code) -> Code) -> String {
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let to_string = builder.member_ref(object, "ToString", sig::method(true, sig::string(), vec![]));
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::string(), vec![]));
+        let code = receiver(&mut builder, Code::new()).op_token(OpCode::Callvirt, to_string).op(OpCode::Ret);
+        builder.body(main, vec![], code);
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let result = call(&mut interpreter, main).unwrap().unwrap();
+        interpreter.format_il_type(&result)
+    }
+
+    #[test]
+    fn callvirt_on_strings_and_arrays_uses_the_cor_lib_vtable() {
+        // ((object)"x").ToString()执行String.ToString
+        assert_eq!(call_to_string(|builder, code| code.op_token(OpCode::Ldstr, builder.user_string("x"))), "x");
+        // System.Array没有重写ToString，执行Object.ToString
+        assert_eq!(call_to_string(|builder, code| {
+            let object = builder.cor_lib_type("System", "Object");
+            code.op(OpCode::Ldci41).op_token(OpCode::Newarr, object)
+        }), "System.Object");
+    }
+
+    /// 返回字符串常量的虚方法或静态方法
+    fn returns(builder: &mut AssemblyBuilder, flags: u16, name: &str, signature: Vec<u8>, value: &str) -> u32 {
+        let method = builder.method(flags, name, signature);
+        let value = builder.user_string(value);
+        builder.body(method, vec![], Code::new().op_token(OpCode::Ldstr, value).op(OpCode::Ret));
+        method
+    }
+
+    fn empty_ctor(builder: &mut AssemblyBuilder) -> u32 {
+        let ctor = builder.method(CTOR, ".ctor", sig::method(true, sig::void(), vec![]));
+        builder.body(ctor, vec![], Code::new().op(OpCode::Ret));
+        ctor
+    }
+
+    #[test]
+    fn override_of_a_generic_base_method_matches_the_instantiated_signature() {
+        // class Base<T> { virtual string Foo(T) } class Derived : Base<int> { override string Foo(int) }
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let base = builder.define_type("", "Base`1", CLASS, object);
+        builder.generic_param(base, 0, "T");
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Foo", sig::method(true, sig::string(), vec![sig::var(0)]), "Base");
+        let base_int = builder.type_spec(sig::generic_inst(sig::class(base), vec![sig::int32()]));
+        builder.define_type("", "Derived", CLASS, base_int);
+        let derived_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "Foo", sig::method(true, sig::string(), vec![sig::int32()]), "Derived");
+        let base_foo = builder.member_ref(base_int, "Foo", sig::method(true, sig::string(), vec![sig::var(0)]));
+
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::string(), vec![]));
+        builder.body(main, vec![], Code::new().op_token(OpCode::Newobj, derived_ctor).op(OpCode::Ldci40)
+            .op_token(OpCode::Callvirt, base_foo).op(OpCode::Ret));
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let result = call(&mut interpreter, main).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "Derived");
+    }
+
+    #[test]
+    fn override_across_assemblies_matches_type_refs_to_type_defs() {
+        // Lib中：class Item {} class Base { virtual string Foo(Item) } static string Helper.Call(object b) => ((Base)b).Foo(null)
+        let mut lib = AssemblyBuilder::new("Lib");
+        let lib_object = lib.cor_lib_type("System", "Object");
+        let item = lib.define_type("Lib", "Item", CLASS, lib_object);
+        let lib_base = lib.define_type("Lib", "Base", CLASS, lib_object);
+        let lib_foo = returns(&mut lib, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "Foo", sig::method(true, sig::string(), vec![sig::class(item)]), "Base");
+        lib.define_type("Lib", "Helper", CLASS, lib_object);
+        let helper_call = lib.method(PUBLIC | STATIC, "Call", sig::method(false, sig::string(), vec![sig::object()]));
+        lib.body(helper_call, vec![], Code::new().op(OpCode::Ldarg0).op_token(OpCode::Castclass, lib_base).op(OpCode::Ldnull)
+            .op_token(OpCode::Callvirt, lib_foo).op(OpCode::Ret));
+
+        // 这里的Foo的签名通过TypeRef引用Item
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let lib_ref = builder.assembly_ref("Lib");
+        let base = builder.type_ref(lib_ref, "Lib", "Base");
+        let item = builder.type_ref(lib_ref, "Lib", "Item");
+        let helper = builder.type_ref(lib_ref, "Lib", "Helper");
+        builder.define_type("", "Derived", CLASS, base);
+        let derived_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "Foo", sig::method(true, sig::string(), vec![sig::class(item)]), "Derived");
+        let call_helper = builder.member_ref(helper, "Call", sig::method(false, sig::string(), vec![sig::object()]));
+
+        builder.define_type("", "Program", CLASS, object);
+        let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, sig::string(), vec![]));
+        builder.body(main, vec![], Code::new().op_token(OpCode::Newobj, derived_ctor).op_token(OpCode::Call, call_helper).op(OpCode::Ret));
+        let mut interpreter = interpreter(builder.build(), vec![("Lib", lib.build())]);
+        let result = call(&mut interpreter, main).unwrap().unwrap();
+        assert_eq!(interpreter.format_il_type(&result), "Derived");
+    }
+
+    #[test]
+    fn callvirt_follows_overrides_newslot_final_and_method_impls() {
+        // class A { virtual M(); virtual N(); virtual P() }
+        // class B : A { override M(); new virtual N(); sealed override P() }
+        // class C : B { override M(); virtual P()（B.P是final，只能占用新的槽位）; string A.N()通过MethodImpl显式重写 }
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let signature = || sig::method(true, sig::string(), vec![]);
+        let a = builder.define_type("", "A", CLASS, object);
+        let a_m = returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "M", signature(), "A.M");
+        let a_n = returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "N", signature(), "A.N");
+        let a_p = returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "P", signature(), "A.P");
+        let b = builder.define_type("", "B", CLASS, a);
+        let b_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "M", signature(), "B.M");
+        let b_n = returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT, "N", signature(), "B.N");
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | FINAL, "P", signature(), "B.P");
+        let c = builder.define_type("", "C", CLASS, b);
+        let c_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "M", signature(), "C.M");
+        let c_p = returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "P", signature(), "C.P");
+        let c_explicit_n = returns(&mut builder, HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "A.N", signature(), "C.A.N");
+        builder.method_impl(c, c_explicit_n, a_n);
+
+        builder.define_type("", "Program", CLASS, object);
+        let calls = [(c_ctor, a_m), (c_ctor, a_n), (c_ctor, b_n), (c_ctor, a_p), (c_ctor, c_p), (b_ctor, a_n), (b_ctor, b_n)];
+        let mains = calls.iter().enumerate().map(|(i, (ctor, method))| {
+            let main = builder.method(PUBLIC | STATIC, &format!("Main{}", i), sig::method(false, sig::string(), vec![]));
+            builder.body(main, vec![], Code::new().op_token(OpCode::Newobj, *ctor).op_token(OpCode::Callvirt, *method).op(OpCode::Ret));
+            main
+        }).collect::<Vec<_>>();
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let results = mains.into_iter().map(|main| {
+            let result = call(&mut interpreter, main).unwrap().unwrap();
+            interpreter.format_il_type(&result)
+        }).collect::<Vec<_>>();
+        assert_eq!(results, ["C.M", "C.A.N", "B.N", "B.P", "C.P", "A.N", "B.N"]);
+    }
+
+    #[test]
+    fn each_instantiation_of_a_generic_interface_has_its_own_implementation() {
+        // interface IEq<T> { string Equals(T) }
+        // class C : IEq<A>, IEq<B> { string Equals(A); string Equals(B) }
+        // class D : IEq<A>, IEq<B> { string IEq<A>.Equals(A); string IEq<B>.Equals(B) }
+        // class G<T> : IEq<T>, IEq<string> { string Equals(T); string Equals(string) }
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let a = builder.define_type("", "A", CLASS, object);
+        let b = builder.define_type("", "B", CLASS, object);
+        let i_eq = builder.define_type("", "IEq`1", INTERFACE, 0);
+        builder.generic_param(i_eq, 0, "T");
+        builder.method_without_body(0, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | ABSTRACT, "Equals", sig::method(true, sig::string(), vec![sig::var(0)]));
+        let i_eq_a = builder.type_spec(sig::generic_inst(sig::class(i_eq), vec![sig::class(a)]));
+        let i_eq_b = builder.type_spec(sig::generic_inst(sig::class(i_eq), vec![sig::class(b)]));
+        let i_eq_int = builder.type_spec(sig::generic_inst(sig::class(i_eq), vec![sig::int32()]));
+        let i_eq_string = builder.type_spec(sig::generic_inst(sig::class(i_eq), vec![sig::string()]));
+        let i_eq_var = builder.type_spec(sig::generic_inst(sig::class(i_eq), vec![sig::var(0)]));
+        let equals = |builder: &mut AssemblyBuilder, interface| builder.member_ref(interface, "Equals", sig::method(true, sig::string(), vec![sig::var(0)]));
+        let (equals_a, equals_b, equals_int, equals_string) = (equals(&mut builder, i_eq_a), equals(&mut builder, i_eq_b), equals(&mut builder, i_eq_int), equals(&mut builder, i_eq_string));
+
+        let c = builder.define_type("", "C", CLASS, object);
+        let c_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "Equals", sig::method(true, sig::string(), vec![sig::class(a)]), "C.Equals(A)");
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "Equals", sig::method(true, sig::string(), vec![sig::class(b)]), "C.Equals(B)");
+        builder.interface_impl(c, i_eq_a);
+        builder.interface_impl(c, i_eq_b);
+
+        let d = builder.define_type("", "D", CLASS, object);
+        let d_ctor = empty_ctor(&mut builder);
+        let d_equals_a = returns(&mut builder, HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "IEq<A>.Equals", sig::method(true, sig::string(), vec![sig::class(a)]), "D.IEq<A>.Equals");
+        let d_equals_b = returns(&mut builder, HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "IEq<B>.Equals", sig::method(true, sig::string(), vec![sig::class(b)]), "D.IEq<B>.Equals");
+        builder.interface_impl(d, i_eq_a);
+        builder.interface_impl(d, i_eq_b);
+        builder.method_impl(d, d_equals_a, equals_a);
+        builder.method_impl(d, d_equals_b, equals_b);
+
+        let g = builder.define_type("", "G`1", CLASS, object);
+        builder.generic_param(g, 0, "T");
+        empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "Equals", sig::method(true, sig::string(), vec![sig::var(0)]), "G.Equals(T)");
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "Equals", sig::method(true, sig::string(), vec![sig::string()]), "G.Equals(string)");
+        builder.interface_impl(g, i_eq_var);
+        builder.interface_impl(g, i_eq_string);
+        let g_int = builder.type_spec(sig::generic_inst(sig::class(g), vec![sig::int32()]));
+        let g_ctor = builder.member_ref(g_int, ".ctor", sig::method(true, sig::void(), vec![]));
+
+        builder.define_type("", "Program", CLASS, object);
+        let calls = [(c_ctor, equals_a), (c_ctor, equals_b), (d_ctor, equals_a), (d_ctor, equals_b), (g_ctor, equals_int), (g_ctor, equals_string)];
+        let mains = calls.iter().enumerate().map(|(i, (ctor, method))| {
+            let main = builder.method(PUBLIC | STATIC, &format!("Main{}", i), sig::method(false, sig::string(), vec![]));
+            let code = Code::new().op_token(OpCode::Newobj, *ctor);
+            let code = if *method == equals_int { code.op(OpCode::Ldci40) } else { code.op(OpCode::Ldnull) };
+            builder.body(main, vec![], code.op_token(OpCode::Callvirt, *method).op(OpCode::Ret));
+            main
+        }).collect::<Vec<_>>();
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let results = mains.into_iter().map(|main| {
+            let result = call(&mut interpreter, main).unwrap().unwrap();
+            interpreter.format_il_type(&result)
+        }).collect::<Vec<_>>();
+        assert_eq!(results, ["C.Equals(A)", "C.Equals(B)", "D.IEq<A>.Equals", "D.IEq<B>.Equals", "G.Equals(T)", "G.Equals(string)"]);
+    }
+
+    #[test]
+    fn constrained_callvirt_calls_in_place_boxes_or_dereferences_by_receiver_type() {
+        // interface I { int Inc(); }
+        // struct V : I { int X; int Inc() => ++X; override string ToString() => "V"; } struct W { }
+        // class C { override string ToString() => "C"; }
+        let mut builder = AssemblyBuilder::new("Test");
+        let object = builder.cor_lib_type("System", "Object");
+        let value_type = builder.cor_lib_type("System", "ValueType");
+        let to_string = builder.member_ref(object, "ToString", sig::method(true, sig::string(), vec![]));
+        let i = builder.define_type("", "I", INTERFACE, 0);
+        let inc = builder.method_without_body(0, PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | ABSTRACT, "Inc", sig::method(true, sig::int32(), vec![]));
+        let v = builder.define_type("", "V", SEALED_CLASS, value_type);
+        let x = builder.field(FIELD_PUBLIC, "X", sig::int32());
+        let v_inc = builder.method(PUBLIC | HIDE_BY_SIG | VIRTUAL | NEW_SLOT | FINAL, "Inc", sig::method(true, sig::int32(), vec![]));
+        builder.body(v_inc, vec![], Code::new().op(OpCode::Ldarg0).op(OpCode::Ldarg0).op_token(OpCode::Ldfld, x).op(OpCode::Ldci41).op(OpCode::Add)
+            .op_token(OpCode::Stfld, x).op(OpCode::Ldarg0).op_token(OpCode::Ldfld, x).op(OpCode::Ret));
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "ToString", sig::method(true, sig::string(), vec![]), "V");
+        builder.interface_impl(v, i);
+        let w = builder.define_type("", "W", SEALED_CLASS, value_type);
+        let c = builder.define_type("", "C", CLASS, object);
+        let c_ctor = empty_ctor(&mut builder);
+        returns(&mut builder, PUBLIC | HIDE_BY_SIG | VIRTUAL, "ToString", sig::method(true, sig::string(), vec![]), "C");
+
+        builder.define_type("", "Program", CLASS, object);
+        let t = builder.type_spec(sig::mvar(0));
+        // static string Str<T>(ref T t) => t.ToString();
+        let str = builder.method(PUBLIC | STATIC, "Str", sig::generic_method(false, 1, sig::string(), vec![sig::by_ref(sig::mvar(0))]));
+        builder.generic_param(str, 0, "T");
+        builder.body(str, vec![], Code::new().op(OpCode::Ldarg0).op2_token(OpCode2::Constrained, t).op_token(OpCode::Callvirt, to_string).op(OpCode::Ret));
+        // static int Twice<T>(ref T t) where T : I { t.Inc(); return t.Inc(); }
+        let twice = builder.method(PUBLIC | STATIC, "Twice", sig::generic_method(false, 1, sig::int32(), vec![sig::by_ref(sig::mvar(0))]));
+        builder.generic_param(twice, 0, "T");
+        builder.body(twice, vec![], Code::new().op(OpCode::Ldarg0).op2_token(OpCode2::Constrained, t).op_token(OpCode::Callvirt, inc).op(OpCode::Pop)
+            .op(OpCode::Ldarg0).op2_token(OpCode2::Constrained, t).op_token(OpCode::Callvirt, inc).op(OpCode::Ret));
+        // static string First<T>(T[] a) => a[0].ToString();
+        let first = builder.method(PUBLIC | STATIC, "First", sig::generic_method(false, 1, sig::string(), vec![sig::sz_array(sig::mvar(0))]));
+        builder.generic_param(first, 0, "T");
+        builder.body(first, vec![], Code::new().op(OpCode::Ldarg0).op(OpCode::Ldci40).op2(OpCode2::Readonly).op_token(OpCode::Ldelema, t)
+            .op2_token(OpCode2::Constrained, t).op_token(OpCode::Callvirt, to_string).op(OpCode::Ret));
+
+        let (str_v, str_w, str_c) = (builder.method_spec(str, vec![sig::value_type(v)]), builder.method_spec(str, vec![sig::value_type(w)]), builder.method_spec(str, vec![sig::class(c)]));
+        let (twice_v, first_object) = (builder.method_spec(twice, vec![sig::value_type(v)]), builder.method_spec(first, vec![sig::object()]));
+        let s = builder.user_string("s");
+        let string = builder.cor_lib_type("System", "String");
+        let mut main = |ret: Vec<u8>, locals: Vec<Vec<u8>>, code: Code| {
+            let main = builder.method(PUBLIC | STATIC, "Main", sig::method(false, ret, vec![]));
+            builder.body(main, locals, code.op(OpCode::Ret));
+            main
+        };
+        let mains = vec![
+            main(sig::string(), vec![sig::value_type(v)], Code::new().op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Call, str_v)),
+            main(sig::string(), vec![sig::value_type(w)], Code::new().op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Call, str_w)),
+            main(sig::string(), vec![sig::class(c)], Code::new().op_token(OpCode::Newobj, c_ctor).op(OpCode::Stloc0)
+                .op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Call, str_c)),
+            // V v; return Twice(ref v) * 10 + v.X;  V.Inc修改的是v本身而不是装箱的副本
+            main(sig::int32(), vec![sig::value_type(v)], Code::new().op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Call, twice_v).ldc_i4(10).op(OpCode::Mul)
+                .op_u8(OpCode::Ldlocas, 0).op_token(OpCode::Ldfld, x).op(OpCode::Add)),
+            // First<object>(new string[] { "s" })，readonly.允许string[]的元素地址作为object的托管指针
+            main(sig::string(), vec![], Code::new().op(OpCode::Ldci41).op_token(OpCode::Newarr, string)
+                .op(OpCode::Dup).op(OpCode::Ldci40).op_token(OpCode::Ldstr, s).op(OpCode::Stelemref).op_token(OpCode::Call, first_object)),
+        ];
+        let mut interpreter = interpreter(builder.build(), vec![]);
+        let results = mains.into_iter().map(|main| {
+            let result = call(&mut interpreter, main).unwrap().unwrap();
+            interpreter.format_il_type(&result)
+        }).collect::<Vec<_>>();
+        assert_eq!(results, ["V", "System.Object", "C", "22", "s"]);
+    }
+}
@@ -0,0 +1,2 @@
+mod hash_vec;
+pub mod interpreter;
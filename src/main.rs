@@ -1,8 +1,63 @@
-mod hash_vec;
-mod interpreter;
-use interpreter::*;
+use std::{env, process, thread};
+use il_runtime::interpreter::*;
+
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+const USAGE: &str = "Usage: il_runtime [--probe <dir>]... [--framework <dir>] [--binding exact|minor|ignore] <assembly> [args]...";
 
 fn main() {
-    let mut interpreter = Interpreter::new(String::from(r"F:\SourceOffline\Rust\il_runtime\ILAssembly\TestCsharp.dll")).unwrap();
-    interpreter.run();
-}
\ No newline at end of file
+    let mut assembly_path = None;
+    let mut probing_paths = Vec::new();
+    let mut framework_dir = None;
+    let mut binding_policy = BindingPolicy::default();
+    let mut program_args = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--probe" => probing_paths.extend(args.next()),
+            "--framework" => framework_dir = args.next(),
+            "--binding" => {
+                binding_policy = match args.next().as_deref() {
+                    Some("exact") => BindingPolicy::Exact,
+                    Some("minor") => BindingPolicy::RollForwardMinor,
+                    Some("ignore") => BindingPolicy::IgnoreVersion,
+                    _ => {
+                        eprintln!("{}", USAGE);
+                        process::exit(2);
+                    },
+                };
+            },
+            _ => {  // 第一个不是选项的参数为入口Assembly，之后的参数都传给Main
+                assembly_path = Some(arg);
+                program_args.extend(args);
+                break;
+            },
+        }
+    }
+    let assembly_path = match assembly_path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+
+    // 每一层托管调用都会占用多层Rust调用（debug下每层约几十KB），所以在栈更大的线程中运行，避免max_call_depth之前宿主就栈溢出
+    let interpreter_thread = thread::Builder::new().stack_size(INTERPRETER_STACK_SIZE).spawn(move || {
+        let mut interpreter = Interpreter::new(assembly_path).unwrap();
+        for path in probing_paths {
+            interpreter.add_probing_path(path);
+        }
+        interpreter.set_binding_policy(binding_policy);
+        if let Some(dir) = framework_dir {
+            interpreter.resolver.set_framework_dir(dir);
+        }
+        match interpreter.run(program_args) {
+            Ok(exit_code) => exit_code,
+            Err(e) => {
+                eprintln!("{}", e);
+                -1
+            },
+        }
+    }).unwrap();
+    process::exit(interpreter_thread.join().unwrap_or(-1));
+}